@@ -30,6 +30,10 @@
           inherit pkgs;
           authFeature = "auth-firebase";
         };
+        local = import ./nix/package.nix {
+          inherit pkgs;
+          authFeature = "auth-local";
+        };
       }
     );
 
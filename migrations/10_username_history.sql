@@ -0,0 +1,22 @@
+-- Names a user has given up. They keep resolving to the user, and cannot be claimed by
+-- anyone else, until expires_at
+CREATE TABLE username_history (
+  username TEXT PRIMARY KEY,
+  uid TEXT NOT NULL REFERENCES users (uid) ON DELETE CASCADE,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_username_history_uid ON username_history (uid);
+
+-- Actors a local actor has exchanged messages with. Used to tell them about profile changes
+CREATE TABLE actor_contacts (
+  actor_id TEXT NOT NULL,
+  contact_id TEXT NOT NULL,
+  last_interaction_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (actor_id, contact_id)
+);
+
+CREATE INDEX idx_actor_contacts_contact_id ON actor_contacts (contact_id);
+
+ALTER TYPE activity_type ADD VALUE 'Update';
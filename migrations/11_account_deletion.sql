@@ -0,0 +1,7 @@
+-- Actors whose accounts were deleted. Their URLs answer 410 Gone instead of 404
+CREATE TABLE actor_tombstones (
+  id TEXT PRIMARY KEY,
+  deleted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TYPE activity_type ADD VALUE 'Delete';
@@ -0,0 +1,16 @@
+-- Archives of everything stored about a user, built in the background on request
+CREATE TABLE data_exports (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  uid TEXT NOT NULL REFERENCES users (uid) ON DELETE CASCADE,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'ready', 'failed')),
+  -- Cleared once downloaded
+  archive JSONB,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  completed_at TIMESTAMPTZ,
+  downloaded_at TIMESTAMPTZ,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_data_exports_uid ON data_exports (uid);
+
+CREATE INDEX idx_data_exports_expires_at ON data_exports (expires_at);
@@ -0,0 +1,14 @@
+-- Profile fields shown on the user's Person actor
+ALTER TABLE users
+ADD COLUMN display_name TEXT,
+ADD COLUMN summary TEXT;
+
+-- One avatar per user. A new upload gets a new id so the old URL can be cached forever
+CREATE TABLE avatars (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  uid TEXT NOT NULL UNIQUE REFERENCES users (uid) ON DELETE CASCADE,
+  content_type TEXT NOT NULL,
+  image BYTEA NOT NULL,
+  thumbnail BYTEA NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,21 @@
+-- Server nodes holding websockets. Nodes that stop heartbeating are treated as gone
+CREATE TABLE socket_nodes (
+  node_id UUID PRIMARY KEY,
+  last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The node each connected device's socket lives on
+CREATE TABLE socket_routes (
+  did UUID PRIMARY KEY,
+  node_id UUID NOT NULL REFERENCES socket_nodes (node_id) ON DELETE CASCADE,
+  connected_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_socket_routes_node_id ON socket_routes (node_id);
+
+-- Relayed messages too large for a NOTIFY payload. The receiving node deletes them
+CREATE TABLE socket_relay_payloads (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  payload TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
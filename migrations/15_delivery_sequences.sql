@@ -0,0 +1,42 @@
+-- The last sequence number handed out for each device's deliveries
+CREATE TABLE delivery_sequences (
+  did UUID PRIMARY KEY REFERENCES devices (did) ON DELETE CASCADE,
+  last_seq BIGINT NOT NULL
+);
+
+-- Each delivery's position in its device's inbox. Clients resume from the last one they saw
+ALTER TABLE deliveries ADD COLUMN seq BIGINT;
+
+UPDATE deliveries d
+SET seq = numbered.seq
+FROM (
+  SELECT d.activity_id, d.to_did,
+    ROW_NUMBER() OVER (PARTITION BY d.to_did ORDER BY ia.created_at, d.activity_id) AS seq
+  FROM deliveries d
+  JOIN inbox_activities ia ON ia.id = d.activity_id
+) numbered
+WHERE d.activity_id = numbered.activity_id AND d.to_did = numbered.to_did;
+
+INSERT INTO delivery_sequences (did, last_seq)
+SELECT to_did, MAX(seq) FROM deliveries GROUP BY to_did;
+
+ALTER TABLE deliveries ALTER COLUMN seq SET NOT NULL;
+
+CREATE UNIQUE INDEX idx_deliveries_to_did_seq ON deliveries (to_did, seq);
+
+-- The sequence row stays locked until the inserting transaction commits, so a device's
+-- deliveries become visible in sequence order
+CREATE OR REPLACE FUNCTION assign_delivery_seq () RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO delivery_sequences (did, last_seq)
+    VALUES (NEW.to_did, 1)
+    ON CONFLICT (did) DO UPDATE SET last_seq = delivery_sequences.last_seq + 1
+    RETURNING last_seq INTO NEW.seq;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_assign_delivery_seq
+BEFORE INSERT ON deliveries FOR EACH ROW
+EXECUTE FUNCTION assign_delivery_seq ();
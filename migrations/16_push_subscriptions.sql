@@ -0,0 +1,5 @@
+-- A device may have several push subscriptions, such as a browser and a native one. An
+-- endpoint belongs to one device at a time
+ALTER TABLE notifications DROP CONSTRAINT notifications_pkey;
+ALTER TABLE notifications ADD PRIMARY KEY (endpoint);
+CREATE INDEX notifications_did_idx ON notifications (did);
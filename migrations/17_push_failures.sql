@@ -0,0 +1,5 @@
+-- Failed pushes per subscription. Only a push service saying the subscription is gone removes
+-- it; other failures are counted and, when the service asks, waited out
+ALTER TABLE notifications ADD COLUMN failure_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE notifications ADD COLUMN last_failure_at TIMESTAMPTZ;
+ALTER TABLE notifications ADD COLUMN retry_after TIMESTAMPTZ;
@@ -0,0 +1,5 @@
+-- Native apps register an FCM or APNs device token instead of a Web Push subscription. The
+-- token is kept in endpoint and has no encryption keys
+ALTER TABLE notifications ADD COLUMN provider TEXT NOT NULL DEFAULT 'webpush' CHECK (provider IN ('webpush', 'fcm', 'apns'));
+ALTER TABLE notifications ALTER COLUMN p256dh DROP NOT NULL;
+ALTER TABLE notifications ALTER COLUMN auth DROP NOT NULL;
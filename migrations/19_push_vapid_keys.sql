@@ -0,0 +1,3 @@
+-- VAPID public key a Web Push subscription was made with, so pushes to it are signed with that
+-- key while the server rotates to a new one. Rows from before are assigned on startup
+ALTER TABLE notifications ADD COLUMN vapid_key TEXT;
@@ -0,0 +1,9 @@
+-- Socket tickets that have been issued but not yet used. Each opens one socket
+CREATE TABLE socket_tickets (
+  -- The `jti` claim of the ticket
+  jti UUID PRIMARY KEY,
+  did UUID NOT NULL REFERENCES devices (did) ON DELETE CASCADE,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_socket_tickets_expires_at ON socket_tickets (expires_at);
@@ -0,0 +1,11 @@
+ALTER TABLE users
+ADD COLUMN password_hash TEXT;
+
+CREATE TABLE password_reset_tokens (
+  token UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  uid TEXT NOT NULL REFERENCES users (uid) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_password_reset_tokens_uid ON password_reset_tokens (uid);
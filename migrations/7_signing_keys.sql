@@ -0,0 +1,12 @@
+-- Asymmetric keys used to sign JWTs. Public halves are published at /.well-known/jwks.json
+CREATE TABLE signing_keys (
+  kid TEXT PRIMARY KEY,
+  -- Which kind of token the key signs, e.g. 'access' or 'oidc_verification'
+  purpose TEXT NOT NULL,
+  algorithm TEXT NOT NULL,
+  -- PKCS#8 DER encoded private key
+  private_key BYTEA NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_signing_keys_purpose ON signing_keys (purpose, created_at);
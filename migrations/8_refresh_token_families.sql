@@ -0,0 +1,16 @@
+-- Every rotation of a device's refresh token stays in the same family
+ALTER TABLE refresh_tokens
+ADD COLUMN family_id UUID NOT NULL DEFAULT gen_random_uuid();
+
+-- Tokens that were rotated away. Presenting one again means the token was copied
+CREATE TABLE retired_refresh_tokens (
+  token UUID PRIMARY KEY,
+  did UUID NOT NULL REFERENCES devices (did) ON DELETE CASCADE,
+  family_id UUID NOT NULL,
+  retired_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_retired_refresh_tokens_family_id ON retired_refresh_tokens (family_id);
+
+CREATE INDEX idx_retired_refresh_tokens_expires_at ON retired_refresh_tokens (expires_at);
@@ -0,0 +1,12 @@
+-- In-flight OIDC logins. Kept in the database so the callback can land on any node
+CREATE TABLE oidc_auth_states (
+  -- The `state` parameter sent to the issuer
+  csrf_token TEXT PRIMARY KEY,
+  nonce TEXT NOT NULL,
+  pkce_verifier TEXT NOT NULL,
+  -- Name of the configured issuer the flow was started with
+  provider TEXT NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_oidc_auth_states_expires_at ON oidc_auth_states (expires_at);
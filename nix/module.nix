@@ -22,6 +22,8 @@ in {
       in
         if cfg.authProvider == "firebase"
         then packages.firebase or (pkgs.callPackage ./package.nix {authFeature = "auth-firebase";})
+        else if cfg.authProvider == "local"
+        then packages.local or (pkgs.callPackage ./package.nix {authFeature = "auth-local";})
         else packages.default or (pkgs.callPackage ./package.nix {});
       description = "The eko-messenger package to use";
     };
@@ -39,7 +41,7 @@ in {
     };
 
     authProvider = lib.mkOption {
-      type = lib.types.enum ["oidc" "firebase"];
+      type = lib.types.enum ["oidc" "firebase" "local"];
       default = "oidc";
       description = "The identity provider to use for authentication";
     };
@@ -97,9 +99,16 @@ in {
       description = "The address the server will bind to";
     };
 
-    jwtSecret = lib.mkOption {
-      type = lib.types.str;
-      description = "Secret for JWT signing";
+    jwtAlgorithm = lib.mkOption {
+      type = lib.types.enum ["EdDSA" "ES256"];
+      default = "EdDSA";
+      description = "Algorithm for newly generated JWT signing keys. Keys are stored in the database and published at /.well-known/jwks.json";
+    };
+
+    jwtKeyRotationDays = lib.mkOption {
+      type = lib.types.ints.positive;
+      default = 30;
+      description = "How often JWT signing keys are rotated";
     };
 
     logLevel = lib.mkOption {
@@ -160,7 +169,8 @@ in {
           IP_SOURCE = cfg.ipSource;
           LISTEN_ADDR = cfg.listenAddr;
           RUST_LOG = cfg.logLevel;
-          JWT_SECRET = cfg.jwtSecret;
+          JWT_ALGORITHM = cfg.jwtAlgorithm;
+          JWT_KEY_ROTATION_DAYS = toString cfg.jwtKeyRotationDays;
           VAPID_KEY_PATH = cfg.vapidKeyPath;
         }
         // lib.optionalAttrs (cfg.firebaseServiceAccount != null) {
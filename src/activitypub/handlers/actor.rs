@@ -9,18 +9,28 @@ use axum_extra::{
 
 use crate::{
     AppState,
-    activitypub::{Endpoints, Person},
+    activitypub::{Endpoints, Person, actor_url},
     errors::AppError,
+    users::ProfileService,
 };
 
 /// Fetch an Actor profile. Public route, but if the requester is the
-/// authenticated owner we include the endpoints
+/// authenticated owner we include the endpoints. Deleted actors are 410 Gone
 pub async fn actor_handler(
     State(state): State<AppState>,
     Path(uid): Path<String>,
     auth_header: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> Result<Json<Person>, AppError> {
-    let mut actor = state.auth.provider.person_from_uid(&uid).await?;
+    if state
+        .storage
+        .actors
+        .is_tombstoned(&actor_url(&state.domain, &uid))
+        .await?
+    {
+        return Err(AppError::Gone("Actor has been deleted".to_string()));
+    }
+
+    let mut actor = ProfileService::person(&state, &uid).await?;
 
     // If a valid Bearer token is present and belongs to this actor, attach private endpoints
     if let Some(TypedHeader(auth)) = auth_header
@@ -1,10 +1,15 @@
 use axum::{Json, extract::State};
 use serde::Serialize;
+use time::OffsetDateTime;
 
-use crate::AppState;
+use crate::{AppState, notifications::PushProviderKind, websocket::poll::MAX_POLL_WAIT};
 
 pub const SOCKET_URL: &str = "/ws";
+pub const SOCKET_TICKET_URL: &str = "/ws/ticket";
 pub const NOTIF_URL: &str = "/push";
+pub const INBOX_STREAM_URL: &str = "/inbox/stream";
+pub const INBOX_POLL_URL: &str = "/inbox/poll";
+pub const INBOX_ACK_URL: &str = "/inbox/ack";
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -12,6 +17,8 @@ pub struct CapabilitiesResponse<'a> {
     spec: &'a str,
     protocol: &'a str,
     websocket: WebSocketCapability<'a>,
+    sse: SseCapability<'a>,
+    long_poll: LongPollCapability<'a>,
     webpush: WebPushCapability,
 }
 
@@ -20,11 +27,34 @@ pub struct CapabilitiesResponse<'a> {
 pub struct WebSocketCapability<'a> {
     auth: &'a str,
     endpoint: String,
+    ticket: String,
+}
+
+/// Fallback for networks that block websockets. Acknowledged at `ack` instead of by frame
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SseCapability<'a> {
+    auth: &'a str,
+    endpoint: String,
+    ticket: String,
+    ack: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LongPollCapability<'a> {
+    auth: &'a str,
+    endpoint: String,
+    ack: String,
+    /// Longest a poll is held open, in seconds
+    max_wait: u64,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WebPushCapability {
+    /// Push services `register` accepts. Native ones take a `token` instead of a subscription
+    providers: Vec<PushProviderKind>,
     vapid: Vapid,
     endpoints: WebPushEndpoints,
 }
@@ -33,6 +63,19 @@ pub struct WebPushCapability {
 #[serde(rename_all = "camelCase")]
 pub struct Vapid {
     public_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subject: Option<String>,
+    /// Keys being rotated out. Subscriptions made with one still get pushes, asking them to
+    /// subscribe again with `public_key`, until it retires
+    previous_keys: Vec<PreviousVapidKey>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviousVapidKey {
+    public_key: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    retires_at: Option<OffsetDateTime>,
 }
 
 #[derive(Serialize)]
@@ -45,6 +88,7 @@ pub struct WebPushEndpoints {
 pub async fn capabilities_handler(
     State(state): State<AppState>,
 ) -> Json<CapabilitiesResponse<'static>> {
+    let vapid = state.notification_service.vapid();
     // Derive from domain
     let ws = state
         .domain
@@ -58,11 +102,34 @@ pub async fn capabilities_handler(
         websocket: WebSocketCapability {
             auth: "bearer",
             endpoint: ws,
+            ticket: format!("{}{}", state.domain, SOCKET_TICKET_URL),
+        },
+        sse: SseCapability {
+            auth: "bearer",
+            endpoint: format!("{}{}", state.domain, INBOX_STREAM_URL),
+            ticket: format!("{}{}", state.domain, SOCKET_TICKET_URL),
+            ack: format!("{}{}", state.domain, INBOX_ACK_URL),
+        },
+        long_poll: LongPollCapability {
+            auth: "bearer",
+            endpoint: format!("{}{}", state.domain, INBOX_POLL_URL),
+            ack: format!("{}{}", state.domain, INBOX_ACK_URL),
+            max_wait: MAX_POLL_WAIT.as_secs(),
         },
 
         webpush: WebPushCapability {
+            providers: state.notification_service.providers(),
             vapid: Vapid {
-                public_key: state.notification_service.public_key.clone(),
+                public_key: vapid.current.public_key.clone(),
+                subject: vapid.subject.clone(),
+                previous_keys: vapid
+                    .active_previous()
+                    .map(|previous| PreviousVapidKey {
+                        public_key: previous.public_key.clone(),
+                        retires_at: vapid.retires_at,
+                    })
+                    .into_iter()
+                    .collect(),
             },
             endpoints: WebPushEndpoints {
                 register: format!("{}{}/register", state.domain, NOTIF_URL),
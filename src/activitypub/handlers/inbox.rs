@@ -11,6 +11,7 @@ use axum::{
     extract::{Extension, State},
     http::{HeaderMap, StatusCode},
 };
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::info;
 
@@ -37,6 +38,34 @@ pub async fn get_inbox(
     Ok(Json(collection))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AcknowledgeRequest {
+    /// Ids of the activities the client received
+    activities: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcknowledgeResponse {
+    removed: u64,
+}
+
+/// POST /inbox/ack
+/// Removes received activities from the inbox, like `Received` on a socket. For clients of the
+/// event stream and long-poll transports. A `Create` stays until its `Delivered` is sent
+#[debug_handler]
+pub async fn acknowledge_inbox(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<AcknowledgeRequest>,
+) -> Result<Json<AcknowledgeResponse>, AppError> {
+    let removed = state
+        .storage
+        .activities
+        .acknowledge_deliveries(&req.activities, &claims.did)
+        .await?;
+    Ok(Json(AcknowledgeResponse { removed }))
+}
+
 /// POST /users/:uid/inbox
 /// TODO Receive federated activities from remote servers
 #[debug_handler]
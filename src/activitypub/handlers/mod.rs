@@ -8,6 +8,6 @@ pub mod webfinger;
 pub use actor::actor_handler;
 pub use capabilities::capabilities_handler;
 pub use collections::get_devices;
-pub use inbox::get_inbox;
+pub use inbox::{acknowledge_inbox, get_inbox};
 pub use outbox::post_to_outbox;
 pub use webfinger::webfinger_handler;
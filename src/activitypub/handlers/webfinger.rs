@@ -2,6 +2,7 @@ use crate::{AppState, activitypub::actor_url, errors::AppError};
 use axum::{
     Json,
     extract::{Query, State},
+    response::{IntoResponse, Redirect, Response},
 };
 use serde::Deserialize;
 
@@ -13,7 +14,7 @@ pub struct WebFingerQuery {
 pub async fn webfinger_handler(
     State(state): State<AppState>,
     Query(query): Query<WebFingerQuery>,
-) -> Result<Json<serde_json::Value>, AppError> {
+) -> Result<Response, AppError> {
     let resource = query.resource;
     if !resource.starts_with("acct:") {
         return Err(AppError::BadRequest("Invalid resource format".to_string()));
@@ -38,12 +39,42 @@ pub async fn webfinger_handler(
         )));
     }
 
-    let uid = state.auth.provider.uid_from_username(username).await?;
+    let uid = match state.auth.provider.uid_from_username(username).await {
+        Ok(uid) => uid,
+        Err(AppError::NotFound(e)) => {
+            // Renamed users keep resolving under their old name for a grace period
+            let current = match state
+                .storage
+                .users
+                .get_uid_by_previous_username(username)
+                .await?
+            {
+                Some(uid) => state.storage.users.get_user_by_uid(&uid).await?,
+                None => None,
+            };
+            let Some(current) = current else {
+                return Err(AppError::NotFound(e));
+            };
+            // Temporary, as the old name can be registered again once the grace period is over
+            return Ok(Redirect::temporary(&format!(
+                "/.well-known/webfinger?resource=acct:{}@{}",
+                current.username, trimmed_domain
+            ))
+            .into_response());
+        }
+        Err(e) => return Err(e),
+    };
 
     let actor_url = actor_url(&state.domain, &uid);
 
+    let mut aliases = vec![actor_url.clone()];
+    for previous in state.storage.users.get_previous_usernames(&uid).await? {
+        aliases.push(format!("acct:{}@{}", previous, trimmed_domain));
+    }
+
     let jrd = serde_json::json!({
         "subject": resource,
+        "aliases": aliases,
         "links": [
             {
                 "rel": "self",
@@ -53,5 +84,5 @@ pub async fn webfinger_handler(
         ]
     });
 
-    Ok(Json(jrd))
+    Ok(Json(jrd).into_response())
 }
@@ -4,10 +4,11 @@ pub mod types;
 pub mod validation;
 
 pub use handlers::{
-    actor_handler, capabilities_handler, get_devices, get_inbox, post_to_outbox, webfinger_handler,
+    acknowledge_inbox, actor_handler, capabilities_handler, get_devices, get_inbox, post_to_outbox,
+    webfinger_handler,
 };
 
 pub use types::{
-    Activity, Create, Delivered, EncryptedMessage, EncryptedMessageEntry, Endpoints,
-    OrderedCollection, Person, PreKeyBundle, Take, actor_uid, actor_url, create_person,
+    Activity, Create, Delete, Delivered, EncryptedMessage, EncryptedMessageEntry, Endpoints,
+    OrderedCollection, Person, PreKeyBundle, Take, Update, actor_uid, actor_url, create_person,
 };
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use serde_json::Value;
 
-use crate::activitypub::PreKeyBundle;
+use crate::activitypub::{Person, PreKeyBundle};
 
 use super::eko_types::EncryptedMessage;
 
@@ -55,6 +55,8 @@ macro_rules! delegate_activity {
             Activity::Create($inner) => $result,
             Activity::Take($inner) => $result,
             Activity::Delivered($inner) => $result,
+            Activity::Update($inner) => $result,
+            Activity::Delete($inner) => $result,
         }
     };
 }
@@ -86,6 +88,33 @@ pub struct Delivered {
     pub object: String,
 }
 
+/// ActivityPub Update of a local actor's profile. Only the server sends these
+#[derive(Deserialize, Debug, Serialize)]
+pub struct Update {
+    #[serde(rename = "@context")]
+    pub context: Value,
+    #[serde(default)]
+    pub id: Option<String>,
+    pub actor: String,
+    #[serde(with = "single_item_vec")]
+    pub to: String,
+    pub object: Person,
+}
+
+/// ActivityPub Delete of a local actor whose account was deleted. Only the server sends these
+#[derive(Deserialize, Debug, Serialize)]
+pub struct Delete {
+    #[serde(rename = "@context")]
+    pub context: Value,
+    #[serde(default)]
+    pub id: Option<String>,
+    pub actor: String,
+    #[serde(with = "single_item_vec")]
+    pub to: String,
+    /// Id of the deleted actor
+    pub object: String,
+}
+
 /// ActivityPub Create activity
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Create {
@@ -97,6 +126,10 @@ pub struct Create {
     pub object: EncryptedMessage,
     #[serde(with = "single_item_vec")]
     pub to: String,
+    /// `false` for messages the recipient should not be alerted to, such as edits. Their
+    /// devices are still woken to sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
 }
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -108,12 +141,14 @@ pub struct CreateView<'a> {
     pub object: EncryptedMessageView<'a>,
     #[serde(with = "single_item_vec_borrowed")]
     pub to: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
     #[serde(rename = "type")]
     pub type_field: &'static str,
 }
 
 // Create enum
-define_activities!(Create, Delivered, Take);
+define_activities!(Create, Delivered, Take, Update, Delete);
 
 pub trait ActivityBase {
     fn id(&self) -> Option<&str>;
@@ -126,7 +161,7 @@ pub trait ActivityBaseMut: ActivityBase {
 }
 
 // add traits to variants
-impl_activity_base!(Create, Take, Delivered);
+impl_activity_base!(Create, Take, Delivered, Update, Delete);
 
 impl Activity {
     pub fn as_base(&self) -> &dyn ActivityBase {
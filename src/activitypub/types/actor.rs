@@ -8,7 +8,7 @@ pub fn default_context_value() -> Value {
 /// ActivityPub Actor endpoints
 /// Contains additional endpoints which may be useful for this actor
 /// Only populated for the owning user when authenticated
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Endpoints {
     /// URL to the user's encrypted group state collection
@@ -16,7 +16,7 @@ pub struct Endpoints {
 }
 
 /// ActivityPub Person (Actor) type
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Person {
     #[serde(rename = "@context")]
@@ -4,7 +4,7 @@ pub mod collection;
 pub mod eko_types;
 pub mod serde_helpers;
 
-pub use activity::{Activity, Create, Delivered, Take};
+pub use activity::{Activity, Create, Delete, Delivered, Take, Update};
 pub use actor::{Endpoints, Person, actor_uid, actor_url, create_person};
 pub use collection::OrderedCollection;
 pub use eko_types::{EncryptedMessage, EncryptedMessageEntry, PreKeyBundle};
@@ -0,0 +1,163 @@
+use crate::{
+    activitypub::Person,
+    auth::{IdentityProvider, SignupRequest},
+    errors::AppError,
+    storage::Storage,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+/// Holds several identity providers configured at startup.
+///
+/// Email/password operations are routed by the domain of the email address, falling back to
+/// the first registered provider. Lookups by uid or username go to the first registered
+/// provider.
+pub struct CompositeIdentityProvider {
+    storage: Arc<Storage>,
+    providers: Vec<(String, Arc<dyn IdentityProvider>)>,
+    email_domains: HashMap<String, usize>,
+}
+
+impl CompositeIdentityProvider {
+    pub fn new(storage: Arc<Storage>) -> Self {
+        Self {
+            storage,
+            providers: Vec::new(),
+            email_domains: HashMap::new(),
+        }
+    }
+
+    /// Registers a provider under `name`. The first provider registered is the default.
+    pub fn add<P: IdentityProvider + 'static>(&mut self, name: &str, provider: P) {
+        self.add_arc(name, Arc::new(provider));
+    }
+
+    pub fn add_arc(&mut self, name: &str, provider: Arc<dyn IdentityProvider>) {
+        self.providers.push((name.to_string(), provider));
+    }
+
+    /// Routes email/password operations for `domain` to the provider registered as `name`
+    pub fn route_email_domain(&mut self, domain: &str, name: &str) -> anyhow::Result<()> {
+        let index = self
+            .providers
+            .iter()
+            .position(|(n, _)| n == name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Email domain '{}' routed to unknown provider '{}'",
+                    domain,
+                    name
+                )
+            })?;
+        self.email_domains.insert(domain.to_lowercase(), index);
+        Ok(())
+    }
+
+    /// Parses `domain=provider` pairs separated by commas, e.g. `example.com=local`
+    pub fn route_email_domains(&mut self, rules: &str) -> anyhow::Result<()> {
+        for rule in rules.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (domain, name) = rule
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid email domain rule '{}'", rule))?;
+            self.route_email_domain(domain.trim(), name.trim())?;
+        }
+        Ok(())
+    }
+
+    pub fn provider_names(&self) -> impl Iterator<Item = &str> {
+        self.providers.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    fn default_provider(&self) -> Result<&Arc<dyn IdentityProvider>, AppError> {
+        self.providers
+            .first()
+            .map(|(_, provider)| provider)
+            .ok_or_else(|| AppError::InternalError(anyhow!("No identity providers configured")))
+    }
+
+    fn provider_for_email(&self, email: &str) -> Result<&Arc<dyn IdentityProvider>, AppError> {
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase());
+        match domain.and_then(|d| self.email_domains.get(&d)) {
+            Some(&index) => Ok(&self.providers[index].1),
+            None => self.default_provider(),
+        }
+    }
+
+    async fn provider_for_uid(&self, uid: &str) -> Result<&Arc<dyn IdentityProvider>, AppError> {
+        match self.storage.users.get_user_by_uid(uid).await? {
+            Some(user) => self.provider_for_email(&user.email),
+            None => self.default_provider(),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for CompositeIdentityProvider {
+    async fn login_with_email(
+        &self,
+        email: String,
+        password: String,
+    ) -> Result<(Person, String), AppError> {
+        self.provider_for_email(&email)?
+            .login_with_email(email, password)
+            .await
+    }
+
+    async fn person_from_uid(&self, uid: &str) -> Result<Person, AppError> {
+        self.default_provider()?.person_from_uid(uid).await
+    }
+
+    async fn uid_from_username(&self, username: &str) -> Result<String, AppError> {
+        self.default_provider()?.uid_from_username(username).await
+    }
+
+    async fn signup(&self, req: SignupRequest) -> Result<StatusCode, AppError> {
+        self.provider_for_email(&req.email)?.signup(req).await
+    }
+
+    async fn change_password(
+        &self,
+        uid: &str,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), AppError> {
+        self.provider_for_uid(uid)
+            .await?
+            .change_password(uid, current_password, new_password)
+            .await
+    }
+
+    async fn request_password_reset(&self, email: String) -> Result<(), AppError> {
+        self.provider_for_email(&email)?
+            .request_password_reset(email)
+            .await
+    }
+
+    fn manages_passwords(&self) -> bool {
+        self.providers.iter().any(|(_, p)| p.manages_passwords())
+    }
+
+    async fn owns_username(&self, uid: &str) -> Result<bool, AppError> {
+        self.provider_for_uid(uid).await?.owns_username(uid).await
+    }
+
+    async fn reset_password(&self, token: Uuid, new_password: String) -> Result<String, AppError> {
+        // Reset tokens do not say who issued them, so hand them to the credential store
+        let provider = self
+            .providers
+            .iter()
+            .map(|(_, p)| p)
+            .find(|p| p.manages_passwords())
+            .map_or_else(|| self.default_provider(), Ok)?;
+        provider.reset_password(token, new_password).await
+    }
+}
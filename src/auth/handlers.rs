@@ -1,13 +1,13 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{Extension, Json, extract::State, http::StatusCode};
 use axum_client_ip::ClientIp;
 use axum_extra::{TypedHeader, headers::UserAgent};
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::serde_as;
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 pub const REFRESH_EXPIRATION: i64 = 60 * 60 * 24 * 31;
@@ -16,11 +16,16 @@ pub const JWT_LIFESPAN: time::Duration = time::Duration::minutes(15);
 use crate::{
     AppState,
     activitypub::{Person, actor_url},
-    auth::jwt::{Claims, JwtHelper},
+    auth::{
+        jwt::{Claims, JwtHelper},
+        username::check_username_available,
+    },
+    devices::{DeviceId, DeviceService},
     errors::AppError,
-    storage::Storage,
+    storage::{Storage, models::RefreshTokenRotation},
+    websocket::ServerEvent,
 };
-use jsonwebtoken;
+use jsonwebtoken::{self, jwk::JwkSet};
 
 #[serde_as]
 #[derive(Clone, Deserialize, Debug, Serialize)]
@@ -77,6 +82,26 @@ pub struct SignupRequest {
     pub username: String,
 }
 
+#[derive(Deserialize, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordRequest {
+    pub token: Uuid,
+    pub new_password: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginResponse {
@@ -95,6 +120,15 @@ pub struct RefreshResponse {
     pub refresh_token: Uuid,
     pub expires_at: String,
 }
+pub enum RefreshOutcome {
+    Refreshed(RefreshResponse),
+    /// An already rotated token was presented and its family has been revoked
+    Reused {
+        uid: String,
+        did: DeviceId,
+    },
+}
+
 #[async_trait]
 pub trait IdentityProvider: Send + Sync {
     /// Returns actor and uid
@@ -110,12 +144,48 @@ pub trait IdentityProvider: Send + Sync {
             "Signup is not supported by this provider".to_string(),
         ))
     }
+    /// Whether this provider stores credentials itself and can change or reset them
+    fn manages_passwords(&self) -> bool {
+        false
+    }
+    /// Whether the user's username is kept in the `users` table, where it can be changed.
+    /// Providers that keep profiles elsewhere manage usernames themselves
+    async fn owns_username(&self, _uid: &str) -> Result<bool, AppError> {
+        Ok(false)
+    }
+    async fn change_password(
+        &self,
+        _uid: &str,
+        _current_password: String,
+        _new_password: String,
+    ) -> Result<(), AppError> {
+        Err(AppError::BadRequest(
+            "Password change is not supported by this provider".to_string(),
+        ))
+    }
+    /// Issues a reset token for the account with this email. Providers must not reveal
+    /// whether the account exists.
+    async fn request_password_reset(&self, _email: String) -> Result<(), AppError> {
+        Err(AppError::BadRequest(
+            "Password reset is not supported by this provider".to_string(),
+        ))
+    }
+    /// Returns the uid of the account whose password was reset
+    async fn reset_password(
+        &self,
+        _token: Uuid,
+        _new_password: String,
+    ) -> Result<String, AppError> {
+        Err(AppError::BadRequest(
+            "Password reset is not supported by this provider".to_string(),
+        ))
+    }
 }
 
 pub struct Auth {
     pub provider: Arc<dyn IdentityProvider>,
     storage: Arc<Storage>,
-    jwt_helper: JwtHelper,
+    jwt_helper: Arc<JwtHelper>,
     domain: Arc<String>,
 }
 
@@ -124,8 +194,8 @@ impl Auth {
         domain: Arc<String>,
         provider: P,
         storage: Arc<Storage>,
+        jwt_helper: Arc<JwtHelper>,
     ) -> Self {
-        let jwt_helper = JwtHelper::new_from_env().expect("Could not instantiate JwtHelper");
         Self {
             domain,
             provider: Arc::new(provider),
@@ -192,7 +262,7 @@ impl Auth {
         old_refresh_token: &Uuid,
         ip_address: &str,
         user_agent: &str,
-    ) -> Result<Json<RefreshResponse>, AppError> {
+    ) -> Result<RefreshOutcome, AppError> {
         let result = self
             .storage
             .devices
@@ -200,28 +270,64 @@ impl Auth {
             .await?;
 
         match result {
-            Some(rotated) => {
+            RefreshTokenRotation::Rotated(rotated) => {
                 let access_token = self.jwt_helper.create_jwt(&rotated.uid, rotated.did)?;
                 let expires_at = time::OffsetDateTime::now_utc() + JWT_LIFESPAN;
-                Ok(Json(RefreshResponse {
+                Ok(RefreshOutcome::Refreshed(RefreshResponse {
                     access_token,
                     refresh_token: rotated.refresh_token,
                     expires_at: expires_at
                         .format(&time::format_description::well_known::Rfc3339)?,
                 }))
             }
-            None => Err(AppError::Unauthorized("Invalid refresh token".into())),
+            RefreshTokenRotation::Reused { uid, did } => {
+                warn!(
+                    "Refresh token reuse detected for {} - {}, revoked token family",
+                    uid, did
+                );
+                Ok(RefreshOutcome::Reused { uid, did })
+            }
+            RefreshTokenRotation::Invalid => {
+                Err(AppError::Unauthorized("Invalid refresh token".into()))
+            }
         }
     }
 
-    pub async fn logout(&self, refresh_token: &Uuid) -> Result<(), AppError> {
+    /// Revokes the device of the refresh token. Returns the device, if the token was known
+    pub async fn logout(&self, refresh_token: &Uuid) -> Result<Option<DeviceId>, AppError> {
         self.storage.devices.logout_device(refresh_token).await
     }
 
     pub async fn signup(&self, req: SignupRequest) -> Result<StatusCode, AppError> {
+        check_username_available(&self.storage, &req.username).await?;
         self.provider.signup(req).await
     }
 
+    pub async fn change_password(
+        &self,
+        uid: &str,
+        req: ChangePasswordRequest,
+    ) -> Result<(), AppError> {
+        self.provider
+            .change_password(uid, req.current_password, req.new_password)
+            .await
+    }
+
+    pub async fn request_password_reset(&self, req: PasswordResetRequest) -> Result<(), AppError> {
+        self.provider.request_password_reset(req.email).await
+    }
+
+    /// Returns the uid of the account whose password was reset
+    pub async fn reset_password(&self, req: ResetPasswordRequest) -> Result<String, AppError> {
+        self.provider
+            .reset_password(req.token, req.new_password)
+            .await
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        self.jwt_helper.jwks()
+    }
+
     pub fn verify_access_token(&self, token: &str) -> Result<Claims, AppError> {
         let data = self.jwt_helper.decrypt_jwt(token);
 
@@ -256,6 +362,40 @@ pub async fn signup_handler(
     state.auth.signup(req).await
 }
 
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    state.auth.change_password(&claims.sub, req).await?;
+    // Whoever knew the old password is signed out everywhere but here
+    DeviceService::revoke_devices(&state, &claims.sub, Some(claims.did), "Password changed")
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn password_reset_request_handler(
+    State(state): State<AppState>,
+    Json(req): Json<PasswordResetRequest>,
+) -> Result<StatusCode, AppError> {
+    state.auth.request_password_reset(req).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn reset_password_handler(
+    State(state): State<AppState>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let uid = state.auth.reset_password(req).await?;
+    // A reset follows a lost or compromised password, so no session survives it
+    DeviceService::revoke_devices(&state, &uid, None, "Password reset").await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn jwks_handler(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.auth.jwks())
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RefreshRequest {
@@ -269,10 +409,37 @@ pub async fn refresh_token_handler(
     Json(req): Json<RefreshRequest>,
 ) -> Result<Json<RefreshResponse>, AppError> {
     info!("refresh token request received");
-    state
+    match state
         .auth
         .refresh_token(&req.refresh_token, &ip.to_string(), &user_agent.to_string())
-        .await
+        .await?
+    {
+        RefreshOutcome::Refreshed(response) => Ok(Json(response)),
+        RefreshOutcome::Reused { uid, did } => {
+            sign_out_compromised_device(&state, &uid, did).await?;
+            Err(AppError::Unauthorized("Invalid refresh token".into()))
+        }
+    }
+}
+
+/// Drops the live connection of a device whose refresh token leaked and tells the user's
+/// other devices about it
+async fn sign_out_compromised_device(
+    state: &AppState,
+    uid: &str,
+    did: DeviceId,
+) -> Result<(), AppError> {
+    state.sockets.disconnect(&did, "Refresh token reused").await;
+
+    let event = ServerEvent::RefreshTokenReused {
+        did: did.to_url(&state.domain),
+    };
+    for other in state.storage.devices.get_approved_devices(uid).await? {
+        if other != did {
+            state.sockets.send_event(other, &event).await;
+        }
+    }
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -285,6 +452,8 @@ pub async fn logout_handler(
     State(state): State<AppState>,
     Json(req): Json<LogoutRequest>,
 ) -> Result<StatusCode, AppError> {
-    state.auth.logout(&req.refresh_token).await?;
+    if let Some(did) = state.auth.logout(&req.refresh_token).await? {
+        state.sockets.disconnect(&did, "Logged out").await;
+    }
     Ok(StatusCode::OK)
 }
@@ -1,12 +1,46 @@
-use std::env::var_os;
+use std::{collections::HashSet, env, str::FromStr, sync::Arc, sync::RwLock};
 
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use jsonwebtoken::{
-    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, encode,
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header,
+    encode,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse,
+    },
 };
-use serde::{Deserialize, Serialize};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{Id, PKey},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use time::OffsetDateTime;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    auth::handlers::JWT_LIFESPAN, devices::DeviceId, errors::AppError, storage::Storage,
+    storage::models::StoredSigningKey,
+};
 
-use crate::{auth::handlers::JWT_LIFESPAN, devices::DeviceId};
+pub const VERIFICATION_TOKEN_LIFESPAN: time::Duration = time::Duration::minutes(10);
+pub const EXPORT_DOWNLOAD_LIFESPAN: time::Duration = time::Duration::minutes(15);
+pub const SOCKET_TICKET_LIFESPAN: time::Duration = time::Duration::seconds(30);
+pub const DEFAULT_KEY_ROTATION: time::Duration = time::Duration::days(30);
+/// New keys are published this long before they are used, so every node and every service
+/// caching our JWKS has seen them before the first token signed with them arrives
+pub const KEY_ACTIVATION_DELAY: time::Duration = time::Duration::minutes(5);
+/// How often each node reloads the key set and rotates when due
+pub const KEY_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Role of every signed in user
+pub const USER_ROLE: &str = "user";
+/// Role of operators, who may see server wide state
+pub const ADMIN_ROLE: &str = "admin";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -17,25 +51,327 @@ pub struct Claims {
     pub roles: Vec<String>,
 }
 
-pub struct JwtHelper {
+/// Tokens of different purposes are signed with unrelated keys so that one can never be
+/// accepted as the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPurpose {
+    Access,
+    OidcVerification,
+    ExportDownload,
+    SocketTicket,
+}
+
+impl KeyPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyPurpose::Access => "access",
+            KeyPurpose::OidcVerification => "oidc_verification",
+            KeyPurpose::ExportDownload => "export_download",
+            KeyPurpose::SocketTicket => "socket_ticket",
+        }
+    }
+
+    fn token_lifespan(&self) -> time::Duration {
+        match self {
+            KeyPurpose::Access => JWT_LIFESPAN,
+            KeyPurpose::OidcVerification => VERIFICATION_TOKEN_LIFESPAN,
+            KeyPurpose::ExportDownload => EXPORT_DOWNLOAD_LIFESPAN,
+            KeyPurpose::SocketTicket => SOCKET_TICKET_LIFESPAN,
+        }
+    }
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    created_at: OffsetDateTime,
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
+    jwk: Jwk,
+}
+
+/// Signs and verifies JWTs with asymmetric keys stored in the database.
+///
+/// Keys are rotated every `JWT_KEY_ROTATION_DAYS`. A new key is published for
+/// `KEY_ACTIVATION_DELAY` before it signs anything, and a replaced key stays published until
+/// every token it signed has expired.
+pub struct JwtHelper {
+    storage: Arc<Storage>,
+    purpose: KeyPurpose,
+    algorithm: Algorithm,
+    rotation_interval: time::Duration,
+    keys: RwLock<Vec<SigningKey>>,
+    /// Uids whose access tokens carry [`ADMIN_ROLE`]
+    admins: HashSet<String>,
+}
+
+fn b64(b: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(b)
+}
+
+fn algorithm_from_env() -> anyhow::Result<Algorithm> {
+    match env::var("JWT_ALGORITHM") {
+        Ok(alg) => match Algorithm::from_str(&alg) {
+            Ok(alg @ (Algorithm::EdDSA | Algorithm::ES256)) => Ok(alg),
+            _ => anyhow::bail!("Unsupported JWT_ALGORITHM '{}', use EdDSA or ES256", alg),
+        },
+        Err(_) => Ok(Algorithm::EdDSA),
+    }
+}
+
+fn rotation_from_env() -> anyhow::Result<time::Duration> {
+    match env::var("JWT_KEY_ROTATION_DAYS") {
+        Ok(days) => {
+            let days: i64 = days
+                .parse()
+                .with_context(|| format!("Invalid JWT_KEY_ROTATION_DAYS '{}'", days))?;
+            if days <= 0 {
+                anyhow::bail!("JWT_KEY_ROTATION_DAYS must be positive");
+            }
+            Ok(time::Duration::days(days))
+        }
+        Err(_) => Ok(DEFAULT_KEY_ROTATION),
+    }
+}
+
+fn algorithm_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::EdDSA => "EdDSA",
+        _ => "ES256",
+    }
+}
+
+/// Generates a PKCS#8 DER encoded private key
+fn generate_private_key(algorithm: Algorithm) -> anyhow::Result<Vec<u8>> {
+    let pkey = match algorithm {
+        Algorithm::EdDSA => PKey::generate_ed25519()?,
+        Algorithm::ES256 => {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+            PKey::from_ec_key(EcKey::generate(&group)?)?
+        }
+        other => anyhow::bail!("Unsupported signing algorithm {:?}", other),
+    };
+    Ok(pkey.private_key_to_pkcs8()?)
+}
+
+fn load_key(stored: &StoredSigningKey) -> anyhow::Result<SigningKey> {
+    let algorithm = Algorithm::from_str(&stored.algorithm)
+        .map_err(|_| anyhow::anyhow!("Unknown algorithm '{}'", stored.algorithm))?;
+    let pkey = PKey::private_key_from_pkcs8(&stored.private_key)
+        .context("Failed to parse PKCS#8 signing key")?;
+
+    let (encoding_key, key_algorithm, parameters) = match (algorithm, pkey.id()) {
+        (Algorithm::EdDSA, Id::ED25519) => (
+            EncodingKey::from_ed_der(&stored.private_key),
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(&pkey.raw_public_key()?),
+            }),
+        ),
+        (Algorithm::ES256, Id::EC) => {
+            let ec_key = pkey.ec_key()?;
+            let mut ctx = BigNumContext::new()?;
+            let mut x = BigNum::new()?;
+            let mut y = BigNum::new()?;
+            ec_key
+                .public_key()
+                .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)?;
+            (
+                EncodingKey::from_ec_der(&stored.private_key),
+                KeyAlgorithm::ES256,
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: b64(&x.to_vec_padded(32)?),
+                    y: b64(&y.to_vec_padded(32)?),
+                }),
+            )
+        }
+        _ => anyhow::bail!(
+            "Signing key {} does not match algorithm {}",
+            stored.kid,
+            stored.algorithm
+        ),
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(stored.kid.clone()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    };
+    let decoding_key = DecodingKey::from_jwk(&jwk)?;
+
+    Ok(SigningKey {
+        kid: stored.kid.clone(),
+        algorithm,
+        created_at: stored.created_at,
+        encoding_key,
+        decoding_key,
+        jwk,
+    })
 }
 
 impl JwtHelper {
-    pub fn new_from_env() -> anyhow::Result<Self> {
-        let secret = var_os("JWT_SECRET")
-            .expect("JWT_SECRET not found in enviroment")
-            .into_string()
-            .map_err(|_| anyhow::anyhow!("Failed to convert from OsString to String"))?;
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-        let encoding_key = EncodingKey::from_secret(secret.as_ref());
-
-        Ok(JwtHelper {
-            encoding_key,
-            decoding_key,
-        })
+    /// Loads the keys for `purpose`, creating the first one if needed. Reads `JWT_ALGORITHM`
+    /// (EdDSA or ES256, default EdDSA) and `JWT_KEY_ROTATION_DAYS` (default 30).
+    pub async fn new(storage: Arc<Storage>, purpose: KeyPurpose) -> anyhow::Result<Self> {
+        let helper = JwtHelper {
+            storage,
+            purpose,
+            algorithm: algorithm_from_env()?,
+            rotation_interval: rotation_from_env()?,
+            keys: RwLock::new(Vec::new()),
+            admins: HashSet::new(),
+        };
+        helper
+            .rotate()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to load signing keys: {:?}", e))?;
+        Ok(helper)
+    }
+
+    /// Reloads keys written by other nodes, creates a new key when the newest one is due for
+    /// rotation and deletes keys that can no longer have valid tokens
+    pub async fn rotate(&self) -> Result<(), AppError> {
+        let purpose = self.purpose.as_str();
+        let now = OffsetDateTime::now_utc();
+        let mut stored = self.storage.signing_keys.get_signing_keys(purpose).await?;
+
+        let due = match stored.last() {
+            Some(newest) => {
+                newest.created_at + self.rotation_interval <= now
+                    || newest.algorithm != algorithm_name(self.algorithm)
+            }
+            None => true,
+        };
+        if due {
+            let key = StoredSigningKey {
+                kid: Uuid::new_v4().to_string(),
+                purpose: purpose.to_string(),
+                algorithm: algorithm_name(self.algorithm).to_string(),
+                private_key: generate_private_key(self.algorithm)?,
+                created_at: now,
+            };
+            self.storage.signing_keys.insert_signing_key(&key).await?;
+            info!("Created {} signing key {}", purpose, key.kid);
+            stored.push(key);
+        }
+
+        // A key stops signing once its successor activates. Keep it until the tokens it
+        // signed have expired
+        let retention = KEY_ACTIVATION_DELAY + self.purpose.token_lifespan() + KEY_ACTIVATION_DELAY;
+        let expired: Vec<String> = stored
+            .windows(2)
+            .filter(|pair| pair[1].created_at + retention < now)
+            .map(|pair| pair[0].kid.clone())
+            .collect();
+        for kid in &expired {
+            self.storage.signing_keys.delete_signing_key(kid).await?;
+            info!("Deleted retired {} signing key {}", purpose, kid);
+        }
+        stored.retain(|key| !expired.contains(&key.kid));
+
+        let mut keys = Vec::with_capacity(stored.len());
+        for key in &stored {
+            match load_key(key) {
+                Ok(key) => keys.push(key),
+                Err(e) => error!("Skipping unusable signing key {}: {:?}", key.kid, e),
+            }
+        }
+        if keys.is_empty() {
+            return Err(AppError::InternalError(anyhow::anyhow!(
+                "No usable {} signing keys",
+                purpose
+            )));
+        }
+
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Periodically calls `rotate` for the lifetime of the process
+    pub fn spawn_rotation(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEY_REFRESH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.rotate().await {
+                    error!(
+                        "Failed to rotate {} signing keys: {:?}",
+                        self.purpose.as_str(),
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    /// Public keys that may have signed a currently valid token
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .read()
+                .unwrap()
+                .iter()
+                .map(|key| key.jwk.clone())
+                .collect(),
+        }
     }
+
+    /// Signs with the newest key that has been published for long enough
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let keys = self.keys.read().unwrap();
+        let activated_before = OffsetDateTime::now_utc() - KEY_ACTIVATION_DELAY;
+        let key = keys
+            .iter()
+            .rev()
+            .find(|key| key.created_at <= activated_before)
+            .or(keys.last())
+            .ok_or(ErrorKind::InvalidKeyFormat)?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding_key)
+    }
+
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+        let kid = header.kid.ok_or(ErrorKind::InvalidToken)?;
+
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or(ErrorKind::InvalidToken)?;
+
+        decode::<T>(token, &key.decoding_key, &Validation::new(key.algorithm))
+    }
+
+    /// Grants [`ADMIN_ROLE`] to the access tokens of these users
+    pub fn with_admins(mut self, admins: impl IntoIterator<Item = String>) -> Self {
+        self.admins = admins.into_iter().collect();
+        self
+    }
+
+    fn roles(&self, uid: &str) -> Vec<String> {
+        let mut roles = vec![USER_ROLE.to_string()];
+        if self.admins.contains(uid) {
+            roles.push(ADMIN_ROLE.to_string());
+        }
+        roles
+    }
+
     pub fn create_jwt(
         &self,
         uid: &str,
@@ -51,18 +387,15 @@ impl JwtHelper {
             did,
             exp,
             iat,
-            roles: vec!["user".to_string()],
+            roles: self.roles(uid),
         };
 
-        let token = encode(&Header::default(), &claims, &self.encoding_key)?;
-
-        Ok(token)
+        self.sign(&claims)
     }
     pub fn decrypt_jwt(
         &self,
         token: &str,
     ) -> Result<TokenData<Claims>, jsonwebtoken::errors::Error> {
-        let validation = Validation::new(Algorithm::HS256);
-        decode::<Claims>(token, &self.decoding_key, &validation)
+        self.verify(token)
     }
 }
@@ -0,0 +1,269 @@
+use crate::{
+    activitypub::{Person, create_person},
+    auth::{IdentityProvider, SignupRequest},
+    errors::AppError,
+    storage::Storage,
+};
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use std::sync::{Arc, LazyLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const PASSWORD_RESET_LIFESPAN: time::Duration = time::Duration::hours(1);
+
+/// Verified against when there is no hash to check, so that logging in to an unknown email
+/// takes as long as a wrong password and does not reveal which emails are registered
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(b"not-the-password", &salt)
+        .expect("Failed to hash dummy password")
+        .to_string()
+});
+
+/// Hands a password reset token to the owner of the account, e.g. by mail
+#[async_trait]
+pub trait ResetTokenSender: Send + Sync {
+    async fn send_reset_token(&self, email: &str, token: Uuid) -> Result<(), AppError>;
+}
+
+/// Used while no transport is configured. Tokens are dropped, so resets can only be done by
+/// an operator issuing a token from the database
+pub struct NoResetTokenSender;
+
+#[async_trait]
+impl ResetTokenSender for NoResetTokenSender {
+    async fn send_reset_token(&self, _email: &str, _token: Uuid) -> Result<(), AppError> {
+        warn!("No transport for password reset tokens is configured, dropping the token");
+        Ok(())
+    }
+}
+
+/// Identity provider for self-hosted instances. Passwords are stored in the `users` table
+/// as Argon2id PHC strings.
+pub struct LocalAuth {
+    storage: Arc<Storage>,
+    domain: Arc<String>,
+    reset_sender: Arc<dyn ResetTokenSender>,
+}
+
+impl LocalAuth {
+    pub fn new(domain: Arc<String>, storage: Arc<Storage>) -> Self {
+        // Hashed now rather than by the first login to an unknown email, which would be slower
+        LazyLock::force(&DUMMY_HASH);
+        Self {
+            storage,
+            domain,
+            reset_sender: Arc::new(NoResetTokenSender),
+        }
+    }
+
+    pub fn with_reset_sender(mut self, sender: impl ResetTokenSender + 'static) -> Self {
+        self.reset_sender = Arc::new(sender);
+        self
+    }
+
+    async fn verify_password(&self, uid: &str, password: String) -> Result<bool, AppError> {
+        let hash = self.storage.users.get_password_hash(uid).await?;
+        verify_hash(hash, password).await
+    }
+}
+
+/// Checks the password against a PHC string. Without one the dummy hash is checked instead
+/// and the password is always rejected
+async fn verify_hash(hash: Option<String>, password: String) -> Result<bool, AppError> {
+    // Argon2 is deliberately slow, keep it off the async workers
+    Ok(tokio::task::spawn_blocking(move || {
+        let valid = PasswordHash::new(hash.as_deref().unwrap_or(&DUMMY_HASH))
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+        valid && hash.is_some()
+    })
+    .await?)
+}
+
+fn validate_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+async fn hash_password(password: String) -> Result<String, AppError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::InternalError(anyhow::anyhow!("Failed to hash password: {}", e)))
+    })
+    .await?
+}
+
+#[async_trait]
+impl IdentityProvider for LocalAuth {
+    async fn login_with_email(
+        &self,
+        email: String,
+        password: String,
+    ) -> Result<(Person, String), AppError> {
+        let invalid = || AppError::Unauthorized("Invalid email or password".to_string());
+
+        let Some(user) = self.storage.users.get_user_by_email(&email).await? else {
+            verify_hash(None, password).await?;
+            return Err(invalid());
+        };
+
+        if !self.verify_password(&user.uid, password).await? {
+            return Err(invalid());
+        }
+
+        let person = create_person(
+            &self.domain,
+            &user.uid,
+            None,
+            user.username.clone(),
+            None,
+            None,
+        );
+
+        Ok((person, user.uid))
+    }
+
+    async fn person_from_uid(&self, uid: &str) -> Result<Person, AppError> {
+        let user = self
+            .storage
+            .users
+            .get_user_by_uid(uid)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        Ok(create_person(
+            &self.domain,
+            &user.uid,
+            None,
+            user.username,
+            None,
+            None,
+        ))
+    }
+
+    async fn uid_from_username(&self, username: &str) -> Result<String, AppError> {
+        let user = self
+            .storage
+            .users
+            .get_user_by_username(username)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        Ok(user.uid)
+    }
+
+    async fn signup(&self, req: SignupRequest) -> Result<StatusCode, AppError> {
+        validate_password(&req.password)?;
+
+        if self
+            .storage
+            .users
+            .get_user_by_email(&req.email)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::BadRequest("User already exists".to_string()));
+        }
+
+        if self
+            .storage
+            .users
+            .get_user_by_username(&req.username)
+            .await?
+            .is_some()
+        {
+            return Err(AppError::BadRequest("Username already taken".to_string()));
+        }
+
+        let uid = Uuid::new_v4().to_string();
+        let password_hash = hash_password(req.password).await?;
+
+        self.storage
+            .users
+            .create_local_user(&uid, &req.username, &req.email, &password_hash)
+            .await?;
+
+        info!("Created new local user: {} ({})", req.username, uid);
+        Ok(StatusCode::CREATED)
+    }
+
+    fn manages_passwords(&self) -> bool {
+        true
+    }
+
+    async fn owns_username(&self, _uid: &str) -> Result<bool, AppError> {
+        Ok(true)
+    }
+
+    async fn change_password(
+        &self,
+        uid: &str,
+        current_password: String,
+        new_password: String,
+    ) -> Result<(), AppError> {
+        if !self.verify_password(uid, current_password).await? {
+            return Err(AppError::Unauthorized("Invalid password".to_string()));
+        }
+        validate_password(&new_password)?;
+
+        let password_hash = hash_password(new_password).await?;
+        self.storage
+            .users
+            .set_password_hash(uid, &password_hash)
+            .await
+    }
+
+    async fn request_password_reset(&self, email: String) -> Result<(), AppError> {
+        let Some(user) = self.storage.users.get_user_by_email(&email).await? else {
+            return Ok(());
+        };
+
+        let expires_at = time::OffsetDateTime::now_utc() + PASSWORD_RESET_LIFESPAN;
+        let token = self
+            .storage
+            .users
+            .create_password_reset_token(&user.uid, expires_at)
+            .await?;
+
+        info!("Issued password reset token for {}", user.uid);
+        self.reset_sender.send_reset_token(&email, token).await
+    }
+
+    async fn reset_password(&self, token: Uuid, new_password: String) -> Result<String, AppError> {
+        validate_password(&new_password)?;
+
+        let uid = self
+            .storage
+            .users
+            .consume_password_reset_token(&token)
+            .await?
+            .ok_or_else(|| AppError::Unauthorized("Invalid or expired reset token".to_string()))?;
+
+        let password_hash = hash_password(new_password).await?;
+        self.storage
+            .users
+            .set_password_hash(&uid, &password_hash)
+            .await?;
+        Ok(uid)
+    }
+}
@@ -1,25 +1,35 @@
+pub mod composite;
 #[cfg(feature = "auth-firebase")]
 pub mod firebase;
 pub mod handlers;
 pub mod jwt;
+#[cfg(feature = "auth-local")]
+pub mod local;
 pub mod provider;
+pub mod username;
 
 #[cfg(feature = "auth-oidc")]
 pub mod oidc;
 
+pub use composite::CompositeIdentityProvider;
 #[cfg(feature = "auth-firebase")]
 pub use firebase::FirebaseAuth;
 pub use handlers::{
-    Auth, IdentityProvider, LoginRequest, LoginResponse, PreKey, REFRESH_EXPIRATION,
-    RefreshRequest, RefreshResponse, SignedPreKey, SignupRequest, login_handler, logout_handler,
-    refresh_token_handler, signup_handler,
+    Auth, ChangePasswordRequest, IdentityProvider, LoginRequest, LoginResponse,
+    PasswordResetRequest, PreKey, REFRESH_EXPIRATION, RefreshOutcome, RefreshRequest,
+    RefreshResponse, ResetPasswordRequest, SignedPreKey, SignupRequest, change_password_handler,
+    jwks_handler, login_handler, logout_handler, password_reset_request_handler,
+    refresh_token_handler, reset_password_handler, signup_handler,
 };
-pub use jwt::{Claims, JwtHelper};
+pub use jwt::{Claims, JwtHelper, KeyPurpose};
+#[cfg(feature = "auth-local")]
+pub use local::{LocalAuth, NoResetTokenSender, ResetTokenSender};
 
 pub use provider::{OidcProviderState, add_oidc_routes, build_auth};
+pub use username::{username_availability_handler, validate_username};
 
 #[cfg(feature = "auth-oidc")]
 pub use oidc::{
     OidcConfig, OidcIdentityProvider, OidcProvider, oidc_callback_handler, oidc_complete_handler,
-    oidc_login_handler,
+    oidc_login_handler, oidc_providers_handler,
 };
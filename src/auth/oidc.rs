@@ -4,10 +4,11 @@ use crate::{
     auth::{
         IdentityProvider, LoginResponse, PreKey, SignedPreKey,
         handlers::{DeviceRegistration, JWT_LIFESPAN, REFRESH_EXPIRATION},
-        jwt::JwtHelper,
+        jwt::{JwtHelper, KeyPurpose, VERIFICATION_TOKEN_LIFESPAN},
+        username::{check_username_available, validate_username},
     },
     errors::AppError,
-    storage::Storage,
+    storage::{Storage, models::StoredAuthState},
 };
 use async_trait::async_trait;
 use axum::{
@@ -16,21 +17,22 @@ use axum::{
 };
 use axum_client_ip::ClientIp;
 use axum_extra::{TypedHeader, headers::UserAgent};
-use dashmap::DashMap;
 use openidconnect::{
     AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
-    RedirectUrl, Scope, TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
     core::{CoreClient, CoreProviderMetadata, CoreResponseType},
 };
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::serde_as;
-use std::{env, sync::Arc, time::Duration};
+use std::{env, sync::Arc};
 use tracing::{error, info};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct OidcConfig {
+    /// Name clients use to select this issuer, `default` for the unprefixed variables
+    pub name: String,
     pub issuer_url: String,
     pub client_id: String,
     pub client_secret: String,
@@ -38,53 +40,43 @@ pub struct OidcConfig {
     pub provider_metadata: CoreProviderMetadata,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct AuthState {
-    csrf_token: String,
-    nonce: String,
-    created_at: time::OffsetDateTime,
+/// How long a user has to finish signing in with the issuer
+pub const AUTH_STATE_LIFESPAN: time::Duration = time::Duration::minutes(15);
+/// The identity an issuer vouched for, carried between the callback and complete steps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcIdentity {
+    pub email: String,
+    pub issuer: String,
+    pub sub: String,
+    /// Set when the identity already belongs to a user
+    pub uid: Option<String>,
 }
 
-impl AuthState {
-    fn new(csrf_token: CsrfToken, nonce: Nonce) -> Self {
-        Self {
-            csrf_token: csrf_token.secret().clone(),
-            nonce: nonce.secret().clone(),
-            created_at: time::OffsetDateTime::now_utc(),
-        }
-    }
-
-    fn is_expired(&self, ttl: Duration) -> bool {
-        let elapsed = time::OffsetDateTime::now_utc() - self.created_at;
-        elapsed > time::Duration::seconds(ttl.as_secs() as i64)
+fn required_var(key: &str) -> anyhow::Result<String> {
+    let value = env::var(key).map_err(|_| anyhow::anyhow!("{} not set", key))?;
+    if value.is_empty() {
+        return Err(anyhow::anyhow!("{} cannot be empty", key));
     }
+    Ok(value)
 }
 
 impl OidcConfig {
+    /// Loads the issuer configured by the unprefixed `OIDC_*` variables
     pub async fn from_env(http_client: &reqwest::Client) -> anyhow::Result<Self> {
-        let issuer = env::var("OIDC_ISSUER").map_err(|_| anyhow::anyhow!("OIDC_ISSUER not set"))?;
-        if issuer.is_empty() {
-            return Err(anyhow::anyhow!("OIDC_ISSUER cannot be empty"));
-        }
-
-        let client_id =
-            env::var("OIDC_CLIENT_ID").map_err(|_| anyhow::anyhow!("OIDC_CLIENT_ID not set"))?;
-        if client_id.is_empty() {
-            return Err(anyhow::anyhow!("OIDC_CLIENT_ID cannot be empty"));
-        }
-
-        let client_secret = env::var("OIDC_CLIENT_SECRET")
-            .map_err(|_| anyhow::anyhow!("OIDC_CLIENT_SECRET not set"))?;
-        if client_secret.is_empty() {
-            return Err(anyhow::anyhow!("OIDC_CLIENT_SECRET cannot be empty"));
-        }
+        Self::from_env_prefixed("default", "OIDC_", http_client).await
+    }
 
-        let redirect_url = env::var("OIDC_REDIRECT_URL")
-            .map_err(|_| anyhow::anyhow!("OIDC_REDIRECT_URL not set"))?;
-        if redirect_url.is_empty() {
-            return Err(anyhow::anyhow!("OIDC_REDIRECT_URL cannot be empty"));
-        }
+    /// Loads the issuer configured by `{prefix}ISSUER`, `{prefix}CLIENT_ID`,
+    /// `{prefix}CLIENT_SECRET` and `{prefix}REDIRECT_URL`
+    pub async fn from_env_prefixed(
+        name: &str,
+        prefix: &str,
+        http_client: &reqwest::Client,
+    ) -> anyhow::Result<Self> {
+        let issuer = required_var(&format!("{}ISSUER", prefix))?;
+        let client_id = required_var(&format!("{}CLIENT_ID", prefix))?;
+        let client_secret = required_var(&format!("{}CLIENT_SECRET", prefix))?;
+        let redirect_url = required_var(&format!("{}REDIRECT_URL", prefix))?;
 
         let issuer_url = IssuerUrl::new(issuer.clone())?;
 
@@ -93,6 +85,7 @@ impl OidcConfig {
             .map_err(|e| anyhow::anyhow!("Failed to discover OIDC provider: {}", e))?;
 
         Ok(Self {
+            name: name.to_string(),
             issuer_url: issuer,
             client_id,
             client_secret,
@@ -103,34 +96,82 @@ impl OidcConfig {
 }
 
 pub struct OidcProvider {
-    config: OidcConfig,
+    /// Configured issuers, the first one is used when the client does not pick one
+    configs: Vec<OidcConfig>,
     http_client: reqwest::Client,
     storage: Arc<Storage>,
     domain: Arc<String>,
-    jwt_helper: JwtHelper,
-    auth_states: Arc<DashMap<String, AuthState>>,
+    jwt_helper: Arc<JwtHelper>,
+    verification_jwt: Arc<JwtHelper>,
 }
 
 impl OidcProvider {
-    pub async fn new_from_env(domain: Arc<String>, storage: Arc<Storage>) -> anyhow::Result<Self> {
-        let jwt_helper = JwtHelper::new_from_env()?;
+    /// `jwt_helper` signs access tokens, verification tokens get their own keys
+    pub async fn new_from_env(
+        domain: Arc<String>,
+        storage: Arc<Storage>,
+        jwt_helper: Arc<JwtHelper>,
+    ) -> anyhow::Result<Self> {
+        let verification_jwt =
+            Arc::new(JwtHelper::new(storage.clone(), KeyPurpose::OidcVerification).await?);
+        verification_jwt.clone().spawn_rotation();
         let http_client = reqwest::Client::new();
 
-        let config = OidcConfig::from_env(&http_client).await?;
-        info!("Configured OIDC provider: {}", config.issuer_url);
+        // OIDC_PROVIDERS=google,corp reads OIDC_GOOGLE_ISSUER, OIDC_CORP_ISSUER, ...
+        let configs = match env::var("OIDC_PROVIDERS") {
+            Ok(names) if !names.trim().is_empty() => {
+                let mut configs: Vec<OidcConfig> = Vec::new();
+                for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    if configs.iter().any(|c| c.name == name) {
+                        anyhow::bail!("OIDC provider '{}' is configured twice", name);
+                    }
+                    let prefix = format!("OIDC_{}_", name.to_uppercase().replace('-', "_"));
+                    configs.push(OidcConfig::from_env_prefixed(name, &prefix, &http_client).await?);
+                }
+                configs
+            }
+            _ => vec![OidcConfig::from_env(&http_client).await?],
+        };
+        for config in &configs {
+            info!(
+                "Configured OIDC provider '{}': {}",
+                config.name, config.issuer_url
+            );
+        }
 
         Ok(Self {
-            config,
+            configs,
             http_client,
             storage,
             domain,
             jwt_helper,
-            auth_states: Arc::new(DashMap::new()),
+            verification_jwt,
         })
     }
 
-    pub fn start_auth(&self) -> Result<(String, CsrfToken, Nonce), AppError> {
-        let config = &self.config;
+    pub fn configs(&self) -> &[OidcConfig] {
+        &self.configs
+    }
+
+    /// Returns the issuer registered as `name`, or the first issuer when no name is given
+    pub fn config(&self, name: Option<&str>) -> Result<&OidcConfig, AppError> {
+        match name {
+            Some(name) => {
+                self.configs.iter().find(|c| c.name == name).ok_or_else(|| {
+                    AppError::BadRequest(format!("Unknown OIDC provider '{}'", name))
+                })
+            }
+            None => self.configs.first().ok_or_else(|| {
+                AppError::InternalError(anyhow::anyhow!("No OIDC providers configured"))
+            }),
+        }
+    }
+
+    pub async fn start_auth(
+        &self,
+        provider: Option<&str>,
+    ) -> Result<(String, CsrfToken, Nonce), AppError> {
+        let config = self.config(provider)?;
 
         let client =
             CoreClient::from_provider_metadata(
@@ -142,6 +183,8 @@ impl OidcProvider {
                 |e| AppError::InternalError(anyhow::anyhow!("Invalid redirect URL: {}", e)),
             )?);
 
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
         let (auth_url, csrf_token, nonce) = client
             .authorize_url(
                 AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
@@ -151,53 +194,51 @@ impl OidcProvider {
             .add_scope(Scope::new("openid".to_string()))
             .add_scope(Scope::new("email".to_string()))
             .add_scope(Scope::new("profile".to_string()))
+            .set_pkce_challenge(pkce_challenge)
             .url();
 
-        // Store CSRF token and nonce for verification
-        let state_key = csrf_token.secret().clone();
-        let auth_state = AuthState::new(csrf_token.clone(), nonce.clone());
-        self.auth_states.insert(state_key, auth_state);
-
-        // Clean up expired states periodically
-        self.cleanup_expired_states();
+        // Stored so the callback can be verified by whichever node receives it
+        self.storage
+            .auth_states
+            .insert_auth_state(&StoredAuthState {
+                csrf_token: csrf_token.secret().clone(),
+                nonce: nonce.secret().clone(),
+                pkce_verifier: pkce_verifier.secret().clone(),
+                provider: config.name.clone(),
+                expires_at: time::OffsetDateTime::now_utc() + AUTH_STATE_LIFESPAN,
+            })
+            .await?;
 
         Ok((auth_url.to_string(), csrf_token, nonce))
     }
 
-    fn cleanup_expired_states(&self) {
-        let ttl = Duration::from_secs(15 * 60);
-        self.auth_states.retain(|_, state| !state.is_expired(ttl));
-    }
-
-    fn verify_csrf_token(&self, csrf_token: &str) -> Result<String, AppError> {
+    /// Consumes the state stored by `start_auth`, so a callback cannot be replayed
+    async fn take_auth_state(&self, csrf_token: &str) -> Result<StoredAuthState, AppError> {
         let state = self
+            .storage
             .auth_states
-            .get(csrf_token)
+            .take_auth_state(csrf_token)
+            .await?
             .ok_or_else(|| AppError::Unauthorized("Invalid or expired CSRF token".to_string()))?;
 
-        let ttl = Duration::from_secs(15 * 60);
-        if state.is_expired(ttl) {
-            self.auth_states.remove(csrf_token);
+        if state.expires_at < time::OffsetDateTime::now_utc() {
             return Err(AppError::Unauthorized("CSRF token has expired".to_string()));
         }
 
-        Ok(state.nonce.clone())
-    }
-
-    fn consume_auth_state(&self, csrf_token: &str) -> Result<(), AppError> {
-        self.auth_states.remove(csrf_token);
-        Ok(())
+        Ok(state)
     }
 
+    /// Returns the verified email, subject and issuer URL
     pub async fn exchange_code(
         &self,
         code: &str,
         csrf_token: &str,
-    ) -> Result<(String, String), AppError> {
-        // Verify CSRF token and retrieve stored nonce
-        let expected_nonce = self.verify_csrf_token(csrf_token)?;
+    ) -> Result<(String, String, String), AppError> {
+        // Verify CSRF token and retrieve stored nonce and PKCE verifier
+        let auth_state = self.take_auth_state(csrf_token).await?;
+        let expected_nonce = auth_state.nonce;
 
-        let config = &self.config;
+        let config = self.config(Some(&auth_state.provider))?;
 
         let client_id = ClientId::new(config.client_id.clone());
         let client_secret = ClientSecret::new(config.client_secret.clone());
@@ -217,6 +258,7 @@ impl OidcProvider {
                 error!("Failed to prepare token exchange: {:?}", e);
                 AppError::InternalError(anyhow::anyhow!("Failed to prepare token exchange: {}", e))
             })?
+            .set_pkce_verifier(PkceCodeVerifier::new(auth_state.pkce_verifier))
             .request_async(&self.http_client)
             .await
             .map_err(|e| {
@@ -257,123 +299,136 @@ impl OidcProvider {
 
         let sub = id_token_claims.subject().to_string();
 
-        // Consume the auth state to prevent reuse
-        self.consume_auth_state(csrf_token)?;
-
-        Ok((email, sub))
+        Ok((email, sub, config.issuer_url.clone()))
     }
 
-    pub async fn get_or_create_user(
+    /// Looks up the user linked to the issuer's subject
+    pub async fn find_user(
         &self,
+        issuer: &str,
         email: &str,
-        oidc_sub: &str,
-    ) -> Result<(String, String), AppError> {
-        let config = &self.config;
-        let issuer = &config.issuer_url;
+        sub: &str,
+    ) -> Result<OidcIdentity, AppError> {
+        let user = self.storage.users.get_user_by_oidc(issuer, sub).await?;
+        Ok(OidcIdentity {
+            email: email.to_string(),
+            issuer: issuer.to_string(),
+            sub: sub.to_string(),
+            uid: user.map(|u| u.uid),
+        })
+    }
 
-        if let Some(user) = self
-            .storage
-            .users
-            .get_user_by_oidc(issuer, oidc_sub)
-            .await?
-        {
-            return Ok((user.uid, user.username));
+    /// Username offered to new users, derived from the email local part when it is usable
+    pub async fn suggest_username(&self, email: &str) -> Result<Option<String>, AppError> {
+        let local_part = email.split('@').next().unwrap_or_default();
+        let candidate: String = local_part
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if validate_username(&candidate).is_err() {
+            return Ok(None);
         }
-
-        // TODO: allow users to pick username, should that happen here in the flow?
-        let username = email.split('@').next().unwrap_or("user").to_string();
-        let mut final_username = username.clone();
-        let mut counter = 1;
-        while self
+        let taken = self
             .storage
             .users
-            .get_user_by_username(&final_username)
+            .get_user_by_username(&candidate)
             .await?
-            .is_some()
-        {
-            final_username = format!("{}_{}", username, counter);
-            counter += 1;
-        }
+            .is_some();
+        Ok((!taken).then_some(candidate))
+    }
+
+    async fn create_user(
+        &self,
+        identity: &OidcIdentity,
+        username: &str,
+    ) -> Result<String, AppError> {
+        check_username_available(&self.storage, username).await?;
 
         let uid = Uuid::new_v4().to_string();
         self.storage
             .users
-            .create_oidc_user(&uid, &final_username, email, issuer, oidc_sub)
+            .create_oidc_user(
+                &uid,
+                username,
+                &identity.email,
+                &identity.issuer,
+                &identity.sub,
+            )
             .await?;
 
-        info!("Created new OIDC user: {} ({})", final_username, uid);
-        Ok((uid, final_username))
+        info!("Created new OIDC user: {} ({})", username, uid);
+        Ok(uid)
     }
 
-    pub fn create_verification_token(&self, email: &str, uid: &str) -> Result<String, AppError> {
-        use jsonwebtoken::{EncodingKey, Header, encode};
-
+    pub fn create_verification_token(&self, identity: &OidcIdentity) -> Result<String, AppError> {
         #[derive(Serialize)]
-        struct VerificationClaims {
-            provider: String,
-            email: String,
-            uid: String,
+        struct VerificationClaims<'a> {
+            provider: &'a str,
+            #[serde(flatten)]
+            identity: &'a OidcIdentity,
             exp: usize,
             iat: usize,
         }
 
         let now = time::OffsetDateTime::now_utc();
         let claims = VerificationClaims {
-            provider: "oidc".to_string(),
-            email: email.to_string(),
-            uid: uid.to_string(),
-            exp: (now + time::Duration::minutes(10)).unix_timestamp() as usize,
+            provider: "oidc",
+            identity,
+            exp: (now + VERIFICATION_TOKEN_LIFESPAN).unix_timestamp() as usize,
             iat: now.unix_timestamp() as usize,
         };
 
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| AppError::InternalError(anyhow::anyhow!("JWT_SECRET not set")))?;
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
-        .map_err(|e| AppError::InternalError(anyhow::anyhow!("Failed to create token: {}", e)))?;
+        let token = self.verification_jwt.sign(&claims).map_err(|e| {
+            AppError::InternalError(anyhow::anyhow!("Failed to create token: {}", e))
+        })?;
 
         Ok(token)
     }
 
-    pub fn verify_verification_token(
-        &self,
-        token: &str,
-    ) -> Result<(String, String, String), AppError> {
-        use jsonwebtoken::{DecodingKey, Validation, decode};
-
-        #[derive(Deserialize)]
-        struct VerificationClaims {
-            provider: String,
-            email: String,
-            uid: String,
-        }
+    pub fn verify_verification_token(&self, token: &str) -> Result<OidcIdentity, AppError> {
+        let token_data = self
+            .verification_jwt
+            .verify::<OidcIdentity>(token)
+            .map_err(|e| AppError::Unauthorized(format!("Invalid verification token: {}", e)))?;
 
-        let secret = env::var("JWT_SECRET")
-            .map_err(|_| AppError::InternalError(anyhow::anyhow!("JWT_SECRET not set")))?;
-        let token_data = decode::<VerificationClaims>(
-            token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &Validation::default(),
-        )
-        .map_err(|e| AppError::Unauthorized(format!("Invalid verification token: {}", e)))?;
-
-        Ok((
-            token_data.claims.provider,
-            token_data.claims.email,
-            token_data.claims.uid,
-        ))
+        Ok(token_data.claims)
     }
 
+    /// Registers the device, creating the user first when the identity is new. New users must
+    /// pick a username unless the suggested one is available
     pub async fn complete_login(
         &self,
         verification_token: &str,
+        username: Option<String>,
         registration: DeviceRegistration,
         ip_address: &str,
     ) -> Result<LoginResponse, AppError> {
-        let (_provider, _email, uid) = self.verify_verification_token(verification_token)?;
+        let identity = self.verify_verification_token(verification_token)?;
+
+        let uid =
+            match identity.uid.clone() {
+                Some(uid) => uid,
+                // The same identity may have completed on another device since the callback
+                None => match self
+                    .storage
+                    .users
+                    .get_user_by_oidc(&identity.issuer, &identity.sub)
+                    .await?
+                {
+                    Some(user) => user.uid,
+                    None => {
+                        let username =
+                            match username {
+                                Some(username) => username,
+                                None => self.suggest_username(&identity.email).await?.ok_or_else(
+                                    || AppError::BadRequest("A username is required".to_string()),
+                                )?,
+                            };
+                        self.create_user(&identity, &username).await?
+                    }
+                },
+            };
 
         // Get user info
         let user = self
@@ -484,6 +539,10 @@ impl IdentityProvider for OidcIdentityProvider {
 
         Ok(user.uid)
     }
+
+    async fn owns_username(&self, _uid: &str) -> Result<bool, AppError> {
+        Ok(true)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -493,6 +552,19 @@ pub struct OidcLoginResponse {
     pub state: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    /// Name of the issuer to sign in with, defaults to the first configured one
+    pub provider: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcProviderInfo {
+    pub name: String,
+    pub issuer: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct OidcCallbackQuery {
     pub code: String,
@@ -504,7 +576,9 @@ pub struct OidcCallbackQuery {
 pub struct OidcCallbackResponse {
     pub verification_token: String,
     pub email: String,
-    pub uid: String,
+    /// Absent for new users, who pick a username in the complete step
+    pub uid: Option<String>,
+    pub suggested_username: Option<String>,
 }
 
 #[serde_as]
@@ -512,6 +586,8 @@ pub struct OidcCallbackResponse {
 #[serde(rename_all = "camelCase")]
 pub struct OidcCompleteRequest {
     pub verification_token: String,
+    /// Username for new users, ignored when the identity already has an account
+    pub username: Option<String>,
     pub device_name: String,
     #[serde_as(as = "Base64")]
     pub identity_key: Vec<u8>,
@@ -520,15 +596,35 @@ pub struct OidcCompleteRequest {
     pub signed_pre_key: SignedPreKey,
 }
 
+pub async fn oidc_providers_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<OidcProviderInfo>>, AppError> {
+    let oidc = state
+        .oidc_provider
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("OIDC is not configured".to_string()))?;
+
+    Ok(Json(
+        oidc.configs()
+            .iter()
+            .map(|c| OidcProviderInfo {
+                name: c.name.clone(),
+                issuer: c.issuer_url.clone(),
+            })
+            .collect(),
+    ))
+}
+
 pub async fn oidc_login_handler(
     State(state): State<AppState>,
+    Query(query): Query<OidcLoginQuery>,
 ) -> Result<Json<OidcLoginResponse>, AppError> {
     let oidc = state
         .oidc_provider
         .as_ref()
         .ok_or_else(|| AppError::BadRequest("OIDC is not configured".to_string()))?;
 
-    let (login_url, csrf_token, _nonce) = oidc.start_auth()?;
+    let (login_url, csrf_token, _nonce) = oidc.start_auth(query.provider.as_deref()).await?;
 
     Ok(Json(OidcLoginResponse {
         login_url,
@@ -553,16 +649,22 @@ pub async fn oidc_callback_handler(
     }
 
     // Exchange code and verify CSRF token + nonce + ID token signature
-    let (email, sub) = oidc.exchange_code(&query.code, &query.state).await?;
+    let (email, sub, issuer) = oidc.exchange_code(&query.code, &query.state).await?;
+
+    let identity = oidc.find_user(&issuer, &email, &sub).await?;
 
-    let (uid, _username) = oidc.get_or_create_user(&email, &sub).await?;
+    let suggested_username = match identity.uid {
+        Some(_) => None,
+        None => oidc.suggest_username(&email).await?,
+    };
 
-    let verification_token = oidc.create_verification_token(&email, &uid)?;
+    let verification_token = oidc.create_verification_token(&identity)?;
 
     Ok(Json(OidcCallbackResponse {
         verification_token,
         email,
-        uid,
+        uid: identity.uid,
+        suggested_username,
     }))
 }
 
@@ -587,7 +689,12 @@ pub async fn oidc_complete_handler(
     };
 
     let response = oidc
-        .complete_login(&req.verification_token, registration, &ip.to_string())
+        .complete_login(
+            &req.verification_token,
+            req.username,
+            registration,
+            &ip.to_string(),
+        )
         .await?;
 
     Ok(Json(response))
@@ -1,53 +1,159 @@
 #[cfg(feature = "auth-firebase")]
 use crate::auth::FirebaseAuth;
+#[cfg(feature = "auth-local")]
+use crate::auth::LocalAuth;
 #[cfg(feature = "auth-oidc")]
 use crate::auth::{
     OidcIdentityProvider, OidcProvider, oidc_callback_handler, oidc_complete_handler,
-    oidc_login_handler,
+    oidc_login_handler, oidc_providers_handler,
 };
-use crate::{AppState, auth::Auth, storage::Storage};
-use axum::{
-    Router,
-    routing::{get, post},
+use crate::{
+    AppState,
+    auth::{Auth, CompositeIdentityProvider, JwtHelper, KeyPurpose},
+    storage::Storage,
 };
-use std::sync::Arc;
+use axum::Router;
+#[cfg(feature = "auth-oidc")]
+use axum::routing::{get, post};
+use std::{env, sync::Arc, time::Duration};
+use tracing::{error, info};
 
-#[cfg(all(feature = "auth-firebase", feature = "auth-oidc"))]
-compile_error!("Cannot enable both 'auth-firebase' and 'auth-oidc' features");
-#[cfg(not(any(feature = "auth-firebase", feature = "auth-oidc")))]
-compile_error!("Must enable exactly one auth provider: 'auth-firebase' or 'auth-oidc'");
+#[cfg(not(any(
+    feature = "auth-firebase",
+    feature = "auth-oidc",
+    feature = "auth-local"
+)))]
+compile_error!(
+    "Must enable at least one auth provider: 'auth-firebase', 'auth-oidc' or 'auth-local'"
+);
 
 #[cfg(feature = "auth-oidc")]
 pub type OidcProviderState = Option<Arc<OidcProvider>>;
 #[cfg(not(feature = "auth-oidc"))]
 pub type OidcProviderState = Option<()>;
 
+/// How often expired login states and socket tickets are deleted
+const AUTH_STATE_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Providers compiled into this build, used in this order when `AUTH_PROVIDERS` is not set
+const COMPILED_PROVIDERS: &[(&str, bool)] = &[
+    ("oidc", cfg!(feature = "auth-oidc")),
+    ("firebase", cfg!(feature = "auth-firebase")),
+    ("local", cfg!(feature = "auth-local")),
+];
+
+/// Builds the identity providers listed in `AUTH_PROVIDERS` (comma separated, the first one
+/// is the default) and applies the `AUTH_EMAIL_DOMAINS` routing rules.
 pub async fn build_auth(
     domain: Arc<String>,
     storage: Arc<Storage>,
 ) -> anyhow::Result<(Auth, OidcProviderState)> {
-    #[cfg(feature = "auth-firebase")]
-    {
-        let client = reqwest::Client::new();
-        let firebase_auth = FirebaseAuth::new_from_env(domain.clone(), client).await?;
-        let auth = Auth::new(domain.clone(), firebase_auth, storage.clone());
-        Ok((auth, None))
+    let names = env::var("AUTH_PROVIDERS").unwrap_or_else(|_| {
+        COMPILED_PROVIDERS
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+
+    // Comma separated uids of operators
+    let admins = env::var("ADMIN_UIDS").unwrap_or_default();
+    let admins = admins
+        .split(',')
+        .map(str::trim)
+        .filter(|uid| !uid.is_empty())
+        .map(str::to_string);
+    let jwt_helper = Arc::new(
+        JwtHelper::new(storage.clone(), KeyPurpose::Access)
+            .await?
+            .with_admins(admins),
+    );
+    jwt_helper.clone().spawn_rotation();
+
+    let mut composite = CompositeIdentityProvider::new(storage.clone());
+    #[allow(unused_mut)]
+    let mut oidc_state: OidcProviderState = None;
+
+    for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        if composite.provider_names().any(|n| n == name) {
+            anyhow::bail!("Identity provider '{}' is listed twice", name);
+        }
+        match name {
+            #[cfg(feature = "auth-firebase")]
+            "firebase" => {
+                let client = reqwest::Client::new();
+                let firebase_auth = FirebaseAuth::new_from_env(domain.clone(), client).await?;
+                composite.add(name, firebase_auth);
+            }
+            #[cfg(feature = "auth-oidc")]
+            "oidc" => {
+                let oidc_provider = Arc::new(
+                    OidcProvider::new_from_env(domain.clone(), storage.clone(), jwt_helper.clone())
+                        .await?,
+                );
+                composite.add(
+                    name,
+                    OidcIdentityProvider::new(domain.clone(), oidc_provider.clone()),
+                );
+                oidc_state = Some(oidc_provider);
+            }
+            #[cfg(feature = "auth-local")]
+            "local" => {
+                composite.add(name, LocalAuth::new(domain.clone(), storage.clone()));
+            }
+            other => anyhow::bail!(
+                "Unknown identity provider '{}', or its feature is not enabled in this build",
+                other
+            ),
+        }
     }
 
-    #[cfg(feature = "auth-oidc")]
-    {
-        let oidc_provider =
-            Arc::new(OidcProvider::new_from_env(domain.clone(), storage.clone()).await?);
-        let oidc_identity = OidcIdentityProvider::new(domain.clone(), oidc_provider.clone());
-        let auth = Auth::new(domain.clone(), oidc_identity, storage.clone());
-        Ok((auth, Some(oidc_provider)))
+    if composite.is_empty() {
+        anyhow::bail!("AUTH_PROVIDERS must list at least one identity provider");
+    }
+
+    if let Ok(rules) = env::var("AUTH_EMAIL_DOMAINS") {
+        composite.route_email_domains(&rules)?;
     }
+
+    info!(
+        "Configured identity providers: {}",
+        composite.provider_names().collect::<Vec<_>>().join(", ")
+    );
+
+    spawn_auth_state_sweep(storage.clone());
+    let auth = Auth::new(domain.clone(), composite, storage.clone(), jwt_helper);
+    Ok((auth, oidc_state))
+}
+
+/// Periodically deletes OIDC login states that were never called back and socket tickets
+/// that were never used
+fn spawn_auth_state_sweep(storage: Arc<Storage>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(AUTH_STATE_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let now = time::OffsetDateTime::now_utc();
+            match storage.auth_states.delete_expired_auth_states(now).await {
+                Ok(0) => {}
+                Ok(n) => info!("Deleted {} expired OIDC login states", n),
+                Err(e) => error!("Failed to delete expired OIDC login states: {:?}", e),
+            }
+            match storage.socket_tickets.delete_expired_tickets(now).await {
+                Ok(0) => {}
+                Ok(n) => info!("Deleted {} expired socket tickets", n),
+                Err(e) => error!("Failed to delete expired socket tickets: {:?}", e),
+            }
+        }
+    });
 }
 
 pub fn add_oidc_routes(router: Router<AppState>) -> Router<AppState> {
     #[cfg(feature = "auth-oidc")]
     {
         router
+            .route("/auth/v1/oidc/providers", get(oidc_providers_handler))
             .route("/auth/v1/oidc/login", get(oidc_login_handler))
             .route("/auth/v1/oidc/callback", get(oidc_callback_handler))
             .route("/auth/v1/oidc/complete", post(oidc_complete_handler))
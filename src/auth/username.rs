@@ -0,0 +1,118 @@
+use crate::{AppState, errors::AppError, storage::Storage};
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::{Deserialize, Serialize};
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 30;
+
+/// Names that could be mistaken for the instance itself or its staff
+const RESERVED_USERNAMES: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "api",
+    "auth",
+    "devices",
+    "eko",
+    "help",
+    "inbox",
+    "instance",
+    "moderator",
+    "notifications",
+    "outbox",
+    "postmaster",
+    "root",
+    "security",
+    "support",
+    "system",
+    "users",
+    "webmaster",
+];
+
+/// Usernames become webfinger handles, so only lowercase ASCII letters, digits and
+/// underscores are allowed, starting with a letter
+pub fn validate_username(username: &str) -> Result<(), AppError> {
+    let length = username.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        return Err(AppError::BadRequest(format!(
+            "Username must be between {} and {} characters",
+            MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+        )));
+    }
+    if !username.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(AppError::BadRequest(
+            "Username must start with a lowercase letter".to_string(),
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(AppError::BadRequest(
+            "Username may only contain lowercase letters, digits and underscores".to_string(),
+        ));
+    }
+    if RESERVED_USERNAMES.contains(&username) {
+        return Err(AppError::BadRequest("Username is reserved".to_string()));
+    }
+    Ok(())
+}
+
+/// Validates the username and checks that nobody has it yet
+pub async fn check_username_available(storage: &Storage, username: &str) -> Result<(), AppError> {
+    validate_username(username)?;
+    if storage
+        .users
+        .get_user_by_username(username)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest("Username already taken".to_string()));
+    }
+    if storage
+        .users
+        .get_uid_by_previous_username(username)
+        .await?
+        .is_some()
+    {
+        return Err(AppError::BadRequest(
+            "Username was recently given up and is reserved".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsernameAvailabilityQuery {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsernameAvailabilityResponse {
+    pub username: String,
+    pub available: bool,
+    /// Why the username cannot be used, when it is not available
+    pub reason: Option<String>,
+}
+
+/// GET /auth/v1/username-availability?username=
+pub async fn username_availability_handler(
+    State(state): State<AppState>,
+    Query(query): Query<UsernameAvailabilityQuery>,
+) -> Result<Json<UsernameAvailabilityResponse>, AppError> {
+    let reason = match check_username_available(&state.storage, &query.username).await {
+        Ok(()) => None,
+        Err(AppError::BadRequest(reason)) => Some(reason),
+        Err(e) => return Err(e),
+    };
+
+    Ok(Json(UsernameAvailabilityResponse {
+        username: query.username,
+        available: reason.is_none(),
+        reason,
+    }))
+}
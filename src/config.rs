@@ -1,4 +1,11 @@
-use crate::storage::{Storage, postgres::connection::postgres_storage};
+use crate::{
+    notifications::{
+        NotificationService, PushPolicy,
+        policy::{PushOptions, parse_urgency},
+    },
+    storage::{Storage, memory::MemoryAuthStateStore, postgres::connection::postgres_storage},
+    websocket::WebSocketService,
+};
 use anyhow::Context;
 use sqlx::{PgPool, Postgres};
 use std::{env::var, sync::Arc};
@@ -23,7 +30,12 @@ pub async fn storage_config(domain: Arc<String>) -> anyhow::Result<Storage> {
         "postgres" => {
             info!("Using PostgreSQL storage backend");
             let pool = db_config().await?;
-            Ok(postgres_storage(domain, pool))
+            let mut storage = postgres_storage(domain, pool);
+            if var("OIDC_STATE_STORE").is_ok_and(|v| v.eq_ignore_ascii_case("memory")) {
+                info!("Keeping OIDC login state in memory");
+                storage.auth_states = Arc::new(MemoryAuthStateStore::new());
+            }
+            Ok(storage)
         }
         _ => {
             anyhow::bail!(
@@ -33,3 +45,59 @@ pub async fn storage_config(domain: Arc<String>) -> anyhow::Result<Storage> {
         }
     }
 }
+
+/// Push headers per kind of wake, `PUSH_<KIND>_TTL` in seconds and `PUSH_<KIND>_URGENCY` for
+/// `MESSAGE`, `SILENT` and `BACKGROUND`. `PUSH_TOPICS=false` stops collapsing wakes
+pub fn push_policy_config() -> anyhow::Result<PushPolicy> {
+    let defaults = PushPolicy::default();
+    Ok(PushPolicy {
+        message: push_options("MESSAGE", defaults.message)?,
+        silent_message: push_options("SILENT", defaults.silent_message)?,
+        background: push_options("BACKGROUND", defaults.background)?,
+        topics: var("PUSH_TOPICS").map_or(true, |v| !v.eq_ignore_ascii_case("false")),
+    })
+}
+
+fn push_options(kind: &str, default: PushOptions) -> anyhow::Result<PushOptions> {
+    let ttl = match var(format!("PUSH_{}_TTL", kind)) {
+        Ok(ttl) => ttl
+            .parse()
+            .with_context(|| format!("Invalid PUSH_{}_TTL: '{}'", kind, ttl))?,
+        Err(_) => default.ttl,
+    };
+    let urgency = match var(format!("PUSH_{}_URGENCY", kind)) {
+        Ok(urgency) => parse_urgency(&urgency).with_context(|| {
+            format!(
+                "Invalid PUSH_{}_URGENCY: '{}'. Valid options are 'very-low', 'low', 'normal' and 'high'",
+                kind, urgency
+            )
+        })?,
+        Err(_) => default.urgency,
+    };
+    Ok(PushOptions { ttl, urgency })
+}
+
+/// Sockets are routed across nodes through postgres unless `SOCKET_ROUTING` is `local`, which
+/// only suits single node deployments. Clustered nodes wake devices through `notifications`
+/// when they cannot queue a delivery relayed to them
+pub async fn sockets_config(
+    storage: &Storage,
+    notifications: Arc<NotificationService>,
+) -> anyhow::Result<Arc<WebSocketService>> {
+    if var("SOCKET_ROUTING").is_ok_and(|v| v.eq_ignore_ascii_case("local")) {
+        info!("Routing sockets on this node only");
+        return Ok(Arc::new(WebSocketService::new()));
+    }
+
+    let sockets = Arc::new(
+        WebSocketService::new()
+            .with_cluster(storage.socket_routes.clone())
+            .with_notifications(notifications),
+    );
+    sockets
+        .clone()
+        .spawn_cluster_tasks()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to join the socket cluster: {:?}", e))?;
+    Ok(sockets)
+}
@@ -1,6 +1,18 @@
-use crate::{AppState, auth::jwt::Claims, errors::AppError};
-use axum::{Json, extract::State};
+use crate::{
+    AppState,
+    auth::jwt::Claims,
+    devices::{DeviceId, DeviceService},
+    errors::AppError,
+};
+use axum::{
+    Json, debug_handler,
+    extract::{Extension, Path, State},
+    http::StatusCode,
+};
+use serde::Serialize;
 use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
 /// Handler to get the approval status of the current device
 pub async fn get_approval_status_handler(
@@ -10,3 +22,67 @@ pub async fn get_approval_status_handler(
     let status = state.storage.devices.get_device_status(claims.did).await?;
     Ok(Json(status))
 }
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub id: String,
+    pub device_name: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub last_ip_address: Option<String>,
+    pub last_user_agent: Option<String>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_refreshed_at: Option<OffsetDateTime>,
+    pub approved: bool,
+    pub online: bool,
+    /// True for the device making the request
+    pub current: bool,
+}
+
+/// GET /users/{uid}/devices
+#[debug_handler]
+pub async fn list_devices_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<Json<Vec<DeviceInfo>>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot list another user's devices".to_string(),
+        ));
+    }
+
+    let mut devices = Vec::new();
+    for d in DeviceService::list_devices(&state, &uid).await? {
+        devices.push(DeviceInfo {
+            id: d.did.to_url(&state.domain),
+            online: state.sockets.is_online(&d.did).await,
+            device_name: d.device_name,
+            created_at: d.created_at,
+            last_ip_address: d.last_ip_address,
+            last_user_agent: d.last_user_agent,
+            last_refreshed_at: d.last_refreshed_at,
+            approved: d.is_approved,
+            current: d.did == claims.did,
+        });
+    }
+    Ok(Json(devices))
+}
+
+/// DELETE /users/{uid}/devices/{did}
+#[debug_handler]
+pub async fn revoke_device_handler(
+    State(state): State<AppState>,
+    Path((uid, did)): Path<(String, Uuid)>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<StatusCode, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot revoke another user's devices".to_string(),
+        ));
+    }
+
+    DeviceService::revoke_device(&state, &uid, DeviceId::new(did)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
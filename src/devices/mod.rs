@@ -3,5 +3,5 @@ pub mod handlers;
 pub mod service;
 
 pub use device_id::DeviceId;
-pub use handlers::get_approval_status_handler;
+pub use handlers::{get_approval_status_handler, list_devices_handler, revoke_device_handler};
 pub use service::DeviceService;
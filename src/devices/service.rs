@@ -1,6 +1,9 @@
 use std::collections::HashSet;
 
-use crate::{AppState, activitypub::types::eko_types::DeviceAction, errors::AppError};
+use crate::{
+    AppState, activitypub::types::eko_types::DeviceAction, devices::DeviceId, errors::AppError,
+    storage::models::StoredDevice,
+};
 
 /// Service for managing user devices and key bundles
 pub struct DeviceService;
@@ -19,4 +22,31 @@ impl DeviceService {
         let dids = state.storage.devices.get_approved_devices(uid).await?;
         Ok(dids.into_iter().map(|v| v.to_url(&state.domain)).collect())
     }
+
+    /// List all of a user's devices, including pending ones
+    pub async fn list_devices(state: &AppState, uid: &str) -> Result<Vec<StoredDevice>, AppError> {
+        state.storage.devices.list_devices(uid).await
+    }
+
+    /// Revoke one of a user's devices and drop its live connection
+    pub async fn revoke_device(state: &AppState, uid: &str, did: DeviceId) -> Result<(), AppError> {
+        if !state.storage.devices.revoke_device(uid, did).await? {
+            return Err(AppError::NotFound("Device not found".to_string()));
+        }
+        state.sockets.disconnect(&did, "Device revoked").await;
+        Ok(())
+    }
+
+    /// Revoke all of a user's devices but `keep`, and drop their live connections
+    pub async fn revoke_devices(
+        state: &AppState,
+        uid: &str,
+        keep: Option<DeviceId>,
+        reason: &str,
+    ) -> Result<(), AppError> {
+        for did in state.storage.devices.revoke_devices(uid, keep).await? {
+            state.sockets.disconnect(&did, reason).await;
+        }
+        Ok(())
+    }
 }
@@ -11,13 +11,16 @@ pub enum AppError {
     Unauthorized(String),
     Forbidden(String),
     NotFound(String),
+    Gone(String),
     DevicePending(String),
+    TooManyRequests(String),
     InternalError(anyhow::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl AppError {
+    /// Logs the error and returns the status and the message that is safe to show clients
+    pub fn into_status(self) -> (StatusCode, String) {
+        match self {
             AppError::BadRequest(msg) => {
                 error!("Bad request: {}", msg);
                 (StatusCode::BAD_REQUEST, msg)
@@ -34,10 +37,18 @@ impl IntoResponse for AppError {
                 error!("Not found: {}", msg);
                 (StatusCode::NOT_FOUND, msg)
             }
+            AppError::Gone(msg) => {
+                error!("Gone: {}", msg);
+                (StatusCode::GONE, msg)
+            }
             AppError::DevicePending(msg) => {
                 error!("Device pending approval: {}", msg);
                 (StatusCode::FORBIDDEN, msg)
             }
+            AppError::TooManyRequests(msg) => {
+                error!("Too many requests: {}", msg);
+                (StatusCode::TOO_MANY_REQUESTS, msg)
+            }
             AppError::InternalError(e) => {
                 error!("Internal server error: {:#}", e);
                 (
@@ -45,8 +56,13 @@ impl IntoResponse for AppError {
                     "Internal server error".to_string(),
                 )
             }
-        };
+        }
+    }
+}
 
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.into_status();
         let body = AxumJson(json!({ "error": error_message }));
 
         (status, body).into_response()
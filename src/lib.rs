@@ -8,34 +8,52 @@ pub mod messaging;
 pub mod middleware;
 pub mod notifications;
 pub mod storage;
+pub mod users;
 pub mod websocket;
 
 use crate::{
     activitypub::{
-        actor_handler, capabilities_handler, get_devices, get_inbox,
-        handlers::capabilities::{NOTIF_URL, SOCKET_URL},
+        acknowledge_inbox, actor_handler, capabilities_handler, get_devices, get_inbox,
+        handlers::capabilities::{
+            INBOX_ACK_URL, INBOX_POLL_URL, INBOX_STREAM_URL, NOTIF_URL, SOCKET_TICKET_URL,
+            SOCKET_URL,
+        },
         post_to_outbox, webfinger_handler,
     },
     auth::{
-        Auth, OidcProviderState, add_oidc_routes, build_auth, login_handler, logout_handler,
-        refresh_token_handler, signup_handler,
+        Auth, JwtHelper, KeyPurpose, OidcProviderState, add_oidc_routes, build_auth,
+        change_password_handler, jwks_handler, login_handler, logout_handler,
+        password_reset_request_handler, refresh_token_handler, reset_password_handler,
+        signup_handler, username_availability_handler,
     },
-    config::storage_config,
-    devices::get_approval_status_handler,
+    config::{push_policy_config, sockets_config, storage_config},
+    devices::{get_approval_status_handler, list_devices_handler, revoke_device_handler},
     groups::{
         delete_group_state_handler, get_all_group_states_handler, get_group_state_handler,
         upsert_group_state_handler,
     },
     middleware::auth_middleware,
-    notifications::{NotificationService, register_handler},
+    notifications::{NotificationService, metrics_handler, register_handler, revoke_handler},
     storage::Storage,
-    websocket::{WebSocketService, handler::ws_handler},
+    users::{
+        ExportService, change_username_handler, delete_account_handler, delete_avatar_handler,
+        download_export_handler, get_avatar_handler, get_avatar_thumbnail_handler,
+        get_export_handler, profile::MAX_AVATAR_BYTES, request_export_handler,
+        update_avatar_handler, update_profile_handler,
+    },
+    websocket::{
+        WebSocketService,
+        handler::{ticket_handler, ws_handler},
+        poll::poll_handler,
+        sse::sse_handler,
+    },
 };
+use axum::extract::DefaultBodyLimit;
 use axum::middleware::from_fn_with_state;
 use axum::{
     Router,
     response::Html,
-    routing::{get, post},
+    routing::{delete, get, post, put},
 };
 use axum_client_ip::ClientIpSource;
 use std::{
@@ -55,12 +73,19 @@ pub struct AppState {
     pub sockets: Arc<WebSocketService>,
     pub notification_service: Arc<NotificationService>,
     pub oidc_provider: OidcProviderState,
+    /// Signs the download links of data exports
+    pub export_jwt: Arc<JwtHelper>,
+    /// Signs the tickets browsers open websockets with
+    pub socket_ticket_jwt: Arc<JwtHelper>,
 }
 
 pub fn app(app_state: AppState, ip_source_str: String) -> anyhow::Result<Router> {
     let protected_routes = Router::new()
         .route("/auth/v1/logout", post(logout_handler))
+        .route("/auth/v1/password", post(change_password_handler))
         .route(&format!("{}/register", NOTIF_URL), post(register_handler))
+        .route(&format!("{}/revoke", NOTIF_URL), post(revoke_handler))
+        .route(&format!("{}/metrics", NOTIF_URL), get(metrics_handler))
         .route("/users/{uid}/outbox", post(post_to_outbox))
         .route("/users/{uid}/inbox", get(get_inbox))
         .route("/users/{uid}/deviceActions", get(get_devices))
@@ -69,7 +94,22 @@ pub fn app(app_state: AppState, ip_source_str: String) -> anyhow::Result<Router>
             "/devices/{did}/approval-status",
             get(get_approval_status_handler),
         )
-        .route(SOCKET_URL, get(ws_handler))
+        .route("/users/{uid}", delete(delete_account_handler))
+        .route("/users/{uid}/username", put(change_username_handler))
+        .route("/users/{uid}/profile", put(update_profile_handler))
+        .route(
+            "/users/{uid}/avatar",
+            put(update_avatar_handler)
+                .delete(delete_avatar_handler)
+                .layer(DefaultBodyLimit::max(MAX_AVATAR_BYTES)),
+        )
+        .route("/users/{uid}/exports", post(request_export_handler))
+        .route("/users/{uid}/exports/{id}", get(get_export_handler))
+        .route("/users/{uid}/devices", get(list_devices_handler))
+        .route("/users/{uid}/devices/{did}", delete(revoke_device_handler))
+        .route(SOCKET_TICKET_URL, post(ticket_handler))
+        .route(INBOX_POLL_URL, get(poll_handler))
+        .route(INBOX_ACK_URL, post(acknowledge_inbox))
         .route("/users/{uid}/groups", get(get_all_group_states_handler))
         .route(
             "/users/{uid}/groups/{group_id}",
@@ -85,9 +125,24 @@ pub fn app(app_state: AppState, ip_source_str: String) -> anyhow::Result<Router>
         .route("/auth/v1/login", post(login_handler))
         .route("/auth/v1/signup", post(signup_handler))
         .route("/auth/v1/refresh", post(refresh_token_handler))
+        .route(
+            "/auth/v1/password/reset-request",
+            post(password_reset_request_handler),
+        )
+        .route("/auth/v1/password/reset", post(reset_password_handler))
+        .route(
+            "/auth/v1/username-availability",
+            get(username_availability_handler),
+        )
         .route("/.well-known/webfinger", get(webfinger_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .route("/users/{uid}", get(actor_handler))
-        .route("/.well-known/ecp", get(capabilities_handler));
+        .route("/exports/{id}/download", get(download_export_handler))
+        .route("/avatars/{id}", get(get_avatar_handler))
+        .route("/avatars/{id}/thumbnail", get(get_avatar_thumbnail_handler))
+        .route("/.well-known/ecp", get(capabilities_handler))
+        .route(SOCKET_URL, get(ws_handler))
+        .route(INBOX_STREAM_URL, get(sse_handler));
     let router = add_oidc_routes(router);
 
     Ok(router
@@ -113,15 +168,30 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     let (auth, oidc_provider) = build_auth(domain.clone(), storage.clone()).await?;
 
-    let notification_service = NotificationService::new(storage.clone()).await?;
+    let notification_service = Arc::new(
+        NotificationService::new(storage.clone())
+            .await?
+            .with_policy(push_policy_config()?),
+    );
+
+    let export_jwt = Arc::new(JwtHelper::new(storage.clone(), KeyPurpose::ExportDownload).await?);
+    export_jwt.clone().spawn_rotation();
+    let socket_ticket_jwt =
+        Arc::new(JwtHelper::new(storage.clone(), KeyPurpose::SocketTicket).await?);
+    socket_ticket_jwt.clone().spawn_rotation();
+    ExportService::spawn_sweep(storage.clone());
+
+    let sockets = sockets_config(&storage, notification_service.clone()).await?;
 
     let app_state = AppState {
         domain,
         auth: Arc::new(auth),
-        sockets: Arc::new(WebSocketService::new()),
-        notification_service: Arc::new(notification_service),
+        sockets,
+        notification_service,
         storage,
         oidc_provider,
+        export_jwt,
+        socket_ticket_jwt,
     };
 
     let app = app(app_state, ip_source)?;
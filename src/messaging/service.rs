@@ -2,22 +2,89 @@ use std::{collections::HashSet, sync::Arc};
 
 use crate::{
     AppState,
+    activitypub::actor_uid,
     activitypub::{
         Activity,
         handlers::outbox::KEY_COLLECTION_URL,
         types::{activity::CreateView, eko_types::EncryptedMessageView},
     },
+    auth::Claims,
     devices::DeviceId,
     errors::AppError,
+    notifications::PushKind,
 };
 use futures::future::join_all;
 use tokio::task::yield_now;
-use tracing::warn;
+use tracing::{info, warn};
+use uuid::Uuid;
 
 /// Main service for orchestrating message delivery
 pub struct MessagingService;
 
 impl MessagingService {
+    /// Validates an activity a device posted to its user's outbox, assigns ids and delivers
+    /// it. Used by both the HTTP outbox and the websocket. Returns the activity as stored
+    pub async fn accept_outbox_activity(
+        state: &AppState,
+        claims: &Claims,
+        mut payload: Activity,
+    ) -> Result<Activity, AppError> {
+        if matches!(payload, Activity::Update(_) | Activity::Delete(_)) {
+            return Err(AppError::BadRequest(
+                "Update and Delete activities are sent by the server".into(),
+            ));
+        }
+
+        // Extract the UID from the actor URL and compare with the authenticated user
+        let extracted_actor_uid = actor_uid(payload.as_base().actor())?;
+        if claims.sub != extracted_actor_uid {
+            info!(
+                "{} tried to send a message as {}",
+                claims.sub,
+                payload.as_base().actor()
+            );
+            return Err(AppError::Forbidden(
+                "Messages may not be sent on behalf of other users".into(),
+            ));
+        }
+
+        if let Activity::Create(create) = &mut payload {
+            let attributed_uid = actor_uid(&create.object.attributed_to)?;
+            if claims.sub != attributed_uid {
+                return Err(AppError::Forbidden(
+                    "Messages may not be sent on behalf of other users".into(),
+                ));
+            }
+            // The message is valid, so we assign id to the inner
+            let message_id = format!("{}/messages/{}", state.domain, Uuid::new_v4());
+            create.object.id = Some(message_id);
+        }
+        // all activities get an ID
+        let activity_id = format!("{}/activities/{}", state.domain, Uuid::new_v4());
+        payload.as_base_mut().set_id(activity_id);
+
+        if let Activity::Take(take) = &mut payload {
+            if !take.to.ends_with(KEY_COLLECTION_URL) {
+                return Err(AppError::BadRequest("Invalid target URL".into()));
+            }
+
+            let device_url = take.to.trim_end_matches(KEY_COLLECTION_URL);
+            let target_did = DeviceId::from_url(device_url)?;
+            let bundle = state
+                .storage
+                .devices
+                .get_prekey_bundle(target_did)
+                .await?
+                .ok_or_else(|| {
+                    AppError::NotFound("PreKey bundle not available for this device".into())
+                })?;
+            take.result = Some(bundle);
+        }
+
+        Self::process_outgoing_message(state, &payload, &claims.did).await?;
+        Ok(payload)
+    }
+
     /// Process an outgoing message envelope from a local user
     /// Handles routing to local or remote recipients
     pub async fn process_outgoing_message(
@@ -36,6 +103,76 @@ impl MessagingService {
             Self::deliver_remote(state, activity).await?;
         }
 
+        if let Activity::Create(create) = activity
+            && create.actor != create.to
+        {
+            state
+                .storage
+                .actors
+                .record_contact(&create.actor, &create.to)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deliver an activity generated by the server rather than posted by a device, such as an
+    /// `Update` of a local actor
+    pub async fn process_server_activity(
+        state: &AppState,
+        activity: &Activity,
+    ) -> Result<(), AppError> {
+        if state
+            .storage
+            .actors
+            .is_local_actor(activity.as_base().to())
+            .await?
+        {
+            Self::deliver_to_all_devices(state, activity).await
+        } else {
+            Self::deliver_remote(state, activity).await
+        }
+    }
+
+    /// Send to every approved device of the local recipient
+    async fn deliver_to_all_devices(state: &AppState, activity: &Activity) -> Result<(), AppError> {
+        let uid = crate::activitypub::actor_uid(activity.as_base().to())?;
+        let dids = state.storage.devices.get_approved_devices(&uid).await?;
+        Self::persist_and_push(state, activity, &dids).await
+    }
+
+    /// Stores the activity for each device, then pushes it to the ones with a socket and wakes
+    /// the others in the background. The stored copy stays until the device acknowledges it, so
+    /// a socket that drops before the client read the activity does not lose it
+    async fn persist_and_push(
+        state: &AppState,
+        activity: &Activity,
+        dids: &[DeviceId],
+    ) -> Result<(), AppError> {
+        if dids.is_empty() {
+            return Ok(());
+        }
+        let seqs = state
+            .storage
+            .activities
+            .insert_non_create(activity, dids)
+            .await?;
+
+        let kind = PushKind::for_activity(activity);
+        for (did, seq) in seqs {
+            if !state
+                .sockets
+                .try_websocket_delivery(activity, did, seq, kind)
+                .await
+            {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = state.notification_service.notify(did, kind).await {
+                        warn!("Tried to notify {} Error: {:?}", did, e);
+                    }
+                });
+            }
+        }
         Ok(())
     }
 
@@ -85,18 +222,20 @@ impl MessagingService {
                     return Err(AppError::BadRequest("device_list_mismatch".into()));
                 }
 
-                state.storage.activities.insert_create(create).await?;
+                let seqs = Arc::new(state.storage.activities.insert_create(create).await?);
 
                 tokio::spawn({
                     // try to delay a little
                     yield_now().await;
                     let state = state.clone();
                     let create = Arc::new(create.clone());
+                    let kind = PushKind::for_activity(activity);
                     async move {
                         let mut futures = Vec::new();
                         for entry in create.object.content.iter() {
                             let state = state.clone();
                             let create = Arc::clone(&create);
+                            let seqs = Arc::clone(&seqs);
                             let entry = entry.clone();
 
                             futures.push(async move {
@@ -113,15 +252,19 @@ impl MessagingService {
                                         to: &create.object.to,
                                     },
                                     to: &create.to,
+                                    notify: create.notify,
                                     type_field: "Create",
                                 };
 
-                                if let Ok(did) = DeviceId::from_url(&entry.to) {
+                                if let Ok(did) = DeviceId::from_url(&entry.to)
+                                    && let Some(&seq) = seqs.get(&did)
+                                {
                                     if !state
                                         .sockets
-                                        .try_websocket_delivery(&activity_view, did)
+                                        .try_websocket_delivery(&activity_view, did, seq, kind)
                                         .await
-                                        && let Err(e) = state.notification_service.notify(did).await
+                                        && let Err(e) =
+                                            state.notification_service.notify(did, kind).await
                                     {
                                         warn!("Tried to notify {} Error: {:?}", entry.to, e);
                                     }
@@ -138,18 +281,7 @@ impl MessagingService {
                 // this re-does compute from prev function (a little bad)
                 let device_url = take.to.trim_end_matches(KEY_COLLECTION_URL);
                 let target_did = DeviceId::from_url(device_url)?;
-                // try to send over socket, if it fails write to db
-                if !state
-                    .sockets
-                    .try_websocket_delivery(activity, target_did)
-                    .await
-                {
-                    state
-                        .storage
-                        .activities
-                        .insert_non_create(activity, &[target_did])
-                        .await?;
-                }
+                Self::persist_and_push(state, activity, &[target_did]).await?;
             }
             Activity::Delivered(delivered) => {
                 let is_sync_message = activity.as_base().actor() == activity.as_base().to();
@@ -187,31 +319,16 @@ impl MessagingService {
 
                 // don't sync deliveries to yourself and don't send duplicates for the same message
                 if !is_sync_message && is_first_delivery {
-                    let mut failed_dids = Vec::new();
-
-                    for device_url in fanout {
-                        if let Ok(target_did) = DeviceId::from_url(&device_url) {
-                            // Try to send via websocket
-                            if !state
-                                .sockets
-                                .try_websocket_delivery(activity, target_did)
-                                .await
-                            {
-                                failed_dids.push(target_did);
-                            }
-                        }
-                    }
-
-                    // If any devices failed to receive via websocket, insert the activity
-                    if !failed_dids.is_empty() {
-                        state
-                            .storage
-                            .activities
-                            .insert_non_create(activity, &failed_dids)
-                            .await?;
-                    }
+                    let target_dids: Vec<DeviceId> = fanout
+                        .iter()
+                        .filter_map(|device_url| DeviceId::from_url(device_url).ok())
+                        .collect();
+                    Self::persist_and_push(state, activity, &target_dids).await?;
                 }
             }
+            Activity::Update(_) | Activity::Delete(_) => {
+                Self::deliver_to_all_devices(state, activity).await?
+            }
         };
 
         Ok(())
@@ -2,19 +2,109 @@ use std::sync::Arc;
 
 use axum::{Extension, Json, extract::State};
 use reqwest::StatusCode;
-use web_push::SubscriptionInfo;
+use serde::Deserialize;
+use web_push::SubscriptionKeys;
 
-use crate::{AppState, auth::Claims, errors::AppError};
+use crate::{
+    AppState,
+    auth::{Claims, jwt::ADMIN_ROLE},
+    errors::AppError,
+    notifications::{PushProviderKind, PushSubscription, metrics::PushMetricsSnapshot},
+};
 
+/// A Web Push subscription as the browser hands it out, or a native device token
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    #[serde(default)]
+    provider: PushProviderKind,
+    /// Web Push endpoint URL
+    endpoint: Option<String>,
+    /// Web Push encryption keys
+    keys: Option<SubscriptionKeys>,
+    /// FCM registration token or APNs device token
+    token: Option<String>,
+    /// VAPID public key the Web Push subscription was made with, the current one if not given
+    #[serde(rename = "applicationServerKey")]
+    application_server_key: Option<String>,
+}
+
+impl TryFrom<RegisterRequest> for PushSubscription {
+    type Error = AppError;
+
+    fn try_from(req: RegisterRequest) -> Result<Self, Self::Error> {
+        let (endpoint, keys) = match (req.provider, req.endpoint, req.keys, req.token) {
+            (PushProviderKind::WebPush, Some(endpoint), Some(keys), None) => (endpoint, Some(keys)),
+            (PushProviderKind::WebPush, ..) => {
+                return Err(AppError::BadRequest(
+                    "Web Push subscriptions need an endpoint and keys".to_string(),
+                ));
+            }
+            (_, None, None, Some(token)) if !token.is_empty() => (token, None),
+            _ => {
+                return Err(AppError::BadRequest(format!(
+                    "{} registrations need only a token",
+                    req.provider.as_str()
+                )));
+            }
+        };
+        Ok(PushSubscription {
+            provider: req.provider,
+            endpoint,
+            keys,
+            vapid_key: req.application_server_key,
+        })
+    }
+}
+
+/// POST /push/register
 pub async fn register_handler(
     State(state): State<AppState>,
     Extension(claims): Extension<Arc<Claims>>,
-    Json(req): Json<SubscriptionInfo>,
+    Json(req): Json<RegisterRequest>,
 ) -> Result<StatusCode, AppError> {
-    tracing::info!("Recived Registration for {}", req.endpoint);
+    let subscription = PushSubscription::try_from(req)?;
+    tracing::info!(
+        "Recived {} Registration for {}",
+        subscription.provider.as_str(),
+        claims.did
+    );
     state
         .notification_service
-        .register(claims.did, &req)
+        .register(claims.did, subscription)
         .await?;
     Ok(StatusCode::OK)
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeRequest {
+    /// The subscription, or native token, to remove. All of the device's are removed without
+    /// one
+    endpoint: Option<String>,
+}
+
+/// POST /push/revoke
+pub async fn revoke_handler(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<RevokeRequest>,
+) -> Result<StatusCode, AppError> {
+    state
+        .notification_service
+        .revoke(claims.did, req.endpoint.as_deref())
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /push/metrics
+/// How pushes have been going since the server started. Only for admins, as it is server wide
+pub async fn metrics_handler(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<Json<PushMetricsSnapshot>, AppError> {
+    if !claims.roles.iter().any(|role| role == ADMIN_ROLE) {
+        return Err(AppError::Forbidden(
+            "Push metrics are only available to admins".to_string(),
+        ));
+    }
+    Ok(Json(state.notification_service.metrics()))
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::notifications::outcome::PushOutcome;
+
+/// Counts of push attempts since the server started
+#[derive(Default)]
+pub struct PushMetrics {
+    attempts: AtomicU64,
+    delivered: AtomicU64,
+    gone: AtomicU64,
+    rate_limited: AtomicU64,
+    unavailable: AtomicU64,
+    rejected: AtomicU64,
+    retries: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushMetricsSnapshot {
+    pub attempts: u64,
+    pub delivered: u64,
+    /// Subscriptions removed because the push service said they are gone
+    pub gone: u64,
+    pub rate_limited: u64,
+    pub unavailable: u64,
+    pub rejected: u64,
+    pub retries: u64,
+    /// Share of attempts that were delivered, `None` before the first attempt
+    pub success_rate: Option<f64>,
+}
+
+impl PushMetrics {
+    pub fn record(&self, outcome: &PushOutcome) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        let counter = match outcome {
+            PushOutcome::Delivered => &self.delivered,
+            PushOutcome::Gone => &self.gone,
+            PushOutcome::RateLimited(_) => &self.rate_limited,
+            PushOutcome::Unavailable(_) => &self.unavailable,
+            PushOutcome::Rejected(_) => &self.rejected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> PushMetricsSnapshot {
+        let attempts = self.attempts.load(Ordering::Relaxed);
+        let delivered = self.delivered.load(Ordering::Relaxed);
+        PushMetricsSnapshot {
+            attempts,
+            delivered,
+            gone: self.gone.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            unavailable: self.unavailable.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            success_rate: (attempts > 0).then(|| delivered as f64 / attempts as f64),
+        }
+    }
+}
@@ -1,5 +1,11 @@
 pub mod handler;
+pub mod metrics;
+pub mod outcome;
+pub mod policy;
+pub mod providers;
 pub mod service;
 pub mod vapid;
-pub use handler::register_handler;
+pub use handler::{metrics_handler, register_handler, revoke_handler};
+pub use policy::{PushKind, PushPolicy};
+pub use providers::{PushProvider, PushProviderKind, PushSubscription};
 pub use service::NotificationService;
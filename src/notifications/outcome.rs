@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use reqwest::{StatusCode, header::HeaderMap};
+use time::{OffsetDateTime, format_description::well_known::Rfc2822};
+
+/// What a push service's answer means for the subscription it was sent to
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushOutcome {
+    Delivered,
+    /// 404 or 410. The subscription expired or was unsubscribed and must not be used again
+    Gone,
+    /// 429. The service wants nothing sent for a while
+    RateLimited(Option<Duration>),
+    /// 5xx or no answer at all. Worth retrying
+    Unavailable(Option<Duration>),
+    /// Any other refusal. Retrying the same push would not help, but the subscription may
+    /// still work for later ones
+    Rejected(String),
+}
+
+impl PushOutcome {
+    pub fn from_response(status: StatusCode, headers: &HeaderMap) -> Self {
+        let retry_after = || parse_retry_after(headers);
+        match status {
+            status if status.is_success() => PushOutcome::Delivered,
+            StatusCode::NOT_FOUND | StatusCode::GONE => PushOutcome::Gone,
+            StatusCode::TOO_MANY_REQUESTS => PushOutcome::RateLimited(retry_after()),
+            status if status.is_server_error() => PushOutcome::Unavailable(retry_after()),
+            status => PushOutcome::Rejected(format!("Push service answered {}", status)),
+        }
+    }
+}
+
+/// `Retry-After` as either seconds or an HTTP date
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = OffsetDateTime::parse(value.trim(), &Rfc2822).ok()?;
+    let wait = at - OffsetDateTime::now_utc();
+    Some(wait.try_into().unwrap_or(Duration::ZERO))
+}
@@ -0,0 +1,110 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use web_push::Urgency;
+
+use crate::{activitypub::Activity, devices::DeviceId};
+
+/// One day, long enough for a phone that was switched off overnight
+pub const DEFAULT_MESSAGE_TTL: u32 = 24 * 60 * 60;
+/// Syncs are worth little once the client has been opened anyway
+pub const DEFAULT_BACKGROUND_TTL: u32 = 60 * 60;
+
+/// What a wake is for, which decides the headers it is sent with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PushKind {
+    /// A message the user should be alerted to
+    Message,
+    /// A message sent with `notify: false`
+    SilentMessage,
+    /// Receipts, prekey takes and actor updates the client only has to sync
+    Background,
+}
+
+impl PushKind {
+    pub fn for_activity(activity: &Activity) -> Self {
+        match activity {
+            Activity::Create(create) if create.notify == Some(false) => PushKind::SilentMessage,
+            Activity::Create(_) => PushKind::Message,
+            _ => PushKind::Background,
+        }
+    }
+
+    /// Marks the topic, so a low urgency wake never replaces a pending alert
+    fn topic_prefix(self) -> char {
+        match self {
+            PushKind::Message => 'm',
+            PushKind::SilentMessage => 's',
+            PushKind::Background => 'b',
+        }
+    }
+}
+
+/// `TTL` and `Urgency` headers of a wake
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PushOptions {
+    /// Seconds the push service keeps the wake for an unreachable device
+    pub ttl: u32,
+    pub urgency: Urgency,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushPolicy {
+    pub message: PushOptions,
+    pub silent_message: PushOptions,
+    pub background: PushOptions,
+    /// Sends a `Topic` per device and kind, so the push service collapses a burst of wakes
+    /// the device has not picked up into one
+    pub topics: bool,
+}
+
+impl Default for PushPolicy {
+    fn default() -> Self {
+        PushPolicy {
+            message: PushOptions {
+                ttl: DEFAULT_MESSAGE_TTL,
+                urgency: Urgency::High,
+            },
+            silent_message: PushOptions {
+                ttl: DEFAULT_MESSAGE_TTL,
+                urgency: Urgency::Low,
+            },
+            background: PushOptions {
+                ttl: DEFAULT_BACKGROUND_TTL,
+                urgency: Urgency::Low,
+            },
+            topics: true,
+        }
+    }
+}
+
+impl PushPolicy {
+    pub fn options(&self, kind: PushKind) -> PushOptions {
+        match kind {
+            PushKind::Message => self.message,
+            PushKind::SilentMessage => self.silent_message,
+            PushKind::Background => self.background,
+        }
+    }
+
+    /// At most 32 characters of the URL safe base64 alphabet, as Web Push requires
+    pub fn topic(&self, did: DeviceId, kind: PushKind) -> Option<String> {
+        self.topics.then(|| {
+            format!(
+                "{}{}",
+                kind.topic_prefix(),
+                URL_SAFE_NO_PAD.encode(did.as_uuid().as_bytes())
+            )
+        })
+    }
+}
+
+pub fn parse_urgency(value: &str) -> Option<Urgency> {
+    match value.to_ascii_lowercase().as_str() {
+        "very-low" => Some(Urgency::VeryLow),
+        "low" => Some(Urgency::Low),
+        "normal" => Some(Urgency::Normal),
+        "high" => Some(Urgency::High),
+        _ => None,
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    env::var,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde::Serialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::warn;
+use web_push::Urgency;
+
+use crate::notifications::{
+    outcome::PushOutcome,
+    policy::PushKind,
+    providers::{PushProvider, PushSubscription, Wake},
+};
+
+pub const APNS_ENDPOINT: &str = "https://api.push.apple.com";
+/// Apple rejects provider tokens older than an hour, and throttles ones renewed more often than
+/// every 20 minutes
+const TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+#[derive(Serialize)]
+struct ProviderClaims<'a> {
+    iss: &'a str,
+    iat: i64,
+}
+
+/// Apple Push Notification service, authenticated with a signing key (`.p8`) of the team
+pub struct ApnsProvider {
+    client: reqwest::Client,
+    key: EncodingKey,
+    key_id: String,
+    team_id: String,
+    /// Bundle id of the app
+    topic: String,
+    endpoint: String,
+    provider_token: Mutex<Option<(String, Instant)>>,
+}
+
+impl ApnsProvider {
+    pub fn new(
+        client: reqwest::Client,
+        key_pem: &[u8],
+        key_id: String,
+        team_id: String,
+        topic: String,
+        endpoint: String,
+    ) -> anyhow::Result<Self> {
+        Ok(ApnsProvider {
+            client,
+            key: EncodingKey::from_ec_pem(key_pem).context("Invalid APNs signing key")?,
+            key_id,
+            team_id,
+            topic,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            provider_token: Mutex::new(None),
+        })
+    }
+
+    /// A client for APNs, which only accepts HTTP/2. Every request goes out as HTTP/2 without
+    /// negotiating: over TLS rustls offers only `h2` through ALPN, and cleartext endpoints are
+    /// assumed to speak it
+    pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .use_rustls_tls()
+            .http2_prior_knowledge()
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Enabled by `APNS_KEY_PATH`, which needs `APNS_KEY_ID`, `APNS_TEAM_ID` and `APNS_TOPIC`.
+    /// `APNS_ENDPOINT` selects the sandbox or another endpoint
+    pub fn new_from_env(client: reqwest::Client) -> anyhow::Result<Option<Self>> {
+        let Ok(path) = var("APNS_KEY_PATH") else {
+            return Ok(None);
+        };
+        let key = std::fs::read(&path)
+            .with_context(|| format!("Failed to read APNs signing key at: {}", path))?;
+        Self::new(
+            client,
+            &key,
+            var("APNS_KEY_ID").context("APNS_KEY_ID must be set with APNS_KEY_PATH")?,
+            var("APNS_TEAM_ID").context("APNS_TEAM_ID must be set with APNS_KEY_PATH")?,
+            var("APNS_TOPIC").context("APNS_TOPIC must be set with APNS_KEY_PATH")?,
+            var("APNS_ENDPOINT").unwrap_or_else(|_| APNS_ENDPOINT.to_string()),
+        )
+        .map(Some)
+    }
+
+    /// The provider token in use, or a newly signed one once it is due
+    fn provider_token(&self) -> anyhow::Result<String> {
+        let mut cached = self.provider_token.lock().unwrap();
+        if let Some((token, signed)) = cached.as_ref()
+            && signed.elapsed() < TOKEN_LIFETIME
+        {
+            return Ok(token.clone());
+        }
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+        let claims = ProviderClaims {
+            iss: &self.team_id,
+            iat: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        let token = encode(&header, &claims, &self.key)?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for ApnsProvider {
+    async fn send(&self, subscription: &PushSubscription, wake: &Wake<'_>) -> PushOutcome {
+        let token = match self.provider_token() {
+            Ok(token) => token,
+            Err(e) => {
+                return PushOutcome::Rejected(format!("Failed to sign APNs token: {}", e));
+            }
+        };
+
+        // Messages are shown by the app's notification service extension once it has synced.
+        // Anything else only wakes the app
+        let (push_type, body) = if wake.kind == PushKind::Message {
+            (
+                "alert",
+                json!({ "aps": { "alert": "wake", "mutable-content": 1 } }),
+            )
+        } else {
+            ("background", json!({ "aps": { "content-available": 1 } }))
+        };
+        // Background pushes must not be sent at priority 10
+        let priority = if wake.options.urgency == Urgency::High && push_type == "alert" {
+            "10"
+        } else {
+            "5"
+        };
+        let expiration = OffsetDateTime::now_utc().unix_timestamp() + i64::from(wake.options.ttl);
+
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/3/device/{}",
+                self.endpoint, subscription.endpoint
+            ))
+            .bearer_auth(token)
+            .header("apns-topic", &self.topic)
+            .header("apns-push-type", push_type)
+            .header("apns-priority", priority)
+            .header("apns-expiration", expiration.to_string());
+        if let Some(topic) = wake.topic {
+            request = request.header("apns-collapse-id", topic);
+        }
+        match request.json(&body).send().await {
+            Ok(response) => {
+                if response.status() == reqwest::StatusCode::FORBIDDEN {
+                    // Possibly an expired provider token. Signed anew for the next push
+                    *self.provider_token.lock().unwrap() = None;
+                }
+                PushOutcome::from_response(response.status(), response.headers())
+            }
+            Err(e) => {
+                warn!("POST to APNs failed: {}", e);
+                PushOutcome::Unavailable(None)
+            }
+        }
+    }
+}
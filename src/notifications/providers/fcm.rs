@@ -0,0 +1,181 @@
+use std::{
+    env::var,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::warn;
+use web_push::Urgency;
+
+use crate::notifications::{
+    outcome::PushOutcome,
+    providers::{PushProvider, PushSubscription, Wake},
+};
+
+pub const FCM_ENDPOINT: &str = "https://fcm.googleapis.com";
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+/// Lifetime asked for in the token grant. Google allows at most an hour
+const GRANT_LIFETIME: i64 = 60 * 60;
+/// Access tokens are renewed this long before they expire
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// The fields of a Google service account key file that are needed to send
+#[derive(Debug, Deserialize)]
+struct ServiceAccount {
+    project_id: String,
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct GrantClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Firebase Cloud Messaging through the HTTP v1 API, authenticated as a service account
+pub struct FcmProvider {
+    client: reqwest::Client,
+    account: ServiceAccount,
+    key: EncodingKey,
+    endpoint: String,
+    access_token: Mutex<Option<(String, Instant)>>,
+}
+
+impl FcmProvider {
+    /// `credentials` is the JSON key file of a service account allowed to send messages
+    pub fn new(
+        client: reqwest::Client,
+        credentials: &str,
+        endpoint: String,
+    ) -> anyhow::Result<Self> {
+        let account: ServiceAccount =
+            serde_json::from_str(credentials).context("Invalid FCM service account")?;
+        let key = EncodingKey::from_rsa_pem(account.private_key.as_bytes())
+            .context("Invalid private key in FCM service account")?;
+        Ok(FcmProvider {
+            client,
+            account,
+            key,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            access_token: Mutex::new(None),
+        })
+    }
+
+    /// Enabled by `FCM_CREDENTIALS`, the path of the service account key file.
+    /// `FCM_ENDPOINT` overrides the Google endpoint
+    pub fn new_from_env(client: reqwest::Client) -> anyhow::Result<Option<Self>> {
+        let Ok(path) = var("FCM_CREDENTIALS") else {
+            return Ok(None);
+        };
+        let credentials = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read FCM credentials at: {}", path))?;
+        let endpoint = var("FCM_ENDPOINT").unwrap_or_else(|_| FCM_ENDPOINT.to_string());
+        Self::new(client, &credentials, endpoint).map(Some)
+    }
+
+    /// A cached OAuth access token, or a new one from the service account's token URI
+    async fn access_token(&self) -> anyhow::Result<String> {
+        let mut cached = self.access_token.lock().await;
+        if let Some((token, expires)) = cached.as_ref()
+            && Instant::now() + TOKEN_MARGIN < *expires
+        {
+            return Ok(token.clone());
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let claims = GrantClaims {
+            iss: &self.account.client_email,
+            scope: FCM_SCOPE,
+            aud: &self.account.token_uri,
+            iat: now,
+            exp: now + GRANT_LIFETIME,
+        };
+        let assertion = encode(&Header::new(Algorithm::RS256), &claims, &self.key)?;
+        let response: TokenResponse = self
+            .client
+            .post(&self.account.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let expires = Instant::now() + Duration::from_secs(response.expires_in);
+        *cached = Some((response.access_token.clone(), expires));
+        Ok(response.access_token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmProvider {
+    async fn send(&self, subscription: &PushSubscription, wake: &Wake<'_>) -> PushOutcome {
+        let token = match self.access_token().await {
+            Ok(token) => token,
+            Err(e) => {
+                warn!("Failed to get an FCM access token: {:?}", e);
+                return PushOutcome::Unavailable(None);
+            }
+        };
+
+        // Data only, so the app decides what to show once it has synced
+        let mut android = json!({
+            "priority": if wake.options.urgency == Urgency::High { "HIGH" } else { "NORMAL" },
+            "ttl": format!("{}s", wake.options.ttl),
+        });
+        if let Some(topic) = wake.topic {
+            android["collapse_key"] = json!(topic);
+        }
+        let body = json!({
+            "message": {
+                "token": subscription.endpoint,
+                "data": { "type": "wake" },
+                "android": android,
+            }
+        });
+
+        let url = format!(
+            "{}/v1/projects/{}/messages:send",
+            self.endpoint, self.account.project_id
+        );
+        match self
+            .client
+            .post(url)
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                    *self.access_token.lock().await = None;
+                }
+                PushOutcome::from_response(response.status(), response.headers())
+            }
+            Err(e) => {
+                warn!("POST to FCM failed: {}", e);
+                PushOutcome::Unavailable(None)
+            }
+        }
+    }
+}
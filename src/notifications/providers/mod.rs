@@ -0,0 +1,77 @@
+pub mod apns;
+pub mod fcm;
+pub mod webpush;
+
+pub use apns::ApnsProvider;
+pub use fcm::FcmProvider;
+pub use webpush::WebPushProvider;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use web_push::SubscriptionKeys;
+
+use crate::notifications::{
+    outcome::PushOutcome,
+    policy::{PushKind, PushOptions},
+};
+
+/// Push services a device can register with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PushProviderKind {
+    /// Browsers, and apps without a native push service
+    #[default]
+    WebPush,
+    /// Firebase Cloud Messaging, for Android
+    Fcm,
+    /// Apple Push Notification service
+    Apns,
+}
+
+impl PushProviderKind {
+    /// Name stored in the `provider` column, the same as in JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PushProviderKind::WebPush => "webpush",
+            PushProviderKind::Fcm => "fcm",
+            PushProviderKind::Apns => "apns",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "webpush" => Some(PushProviderKind::WebPush),
+            "fcm" => Some(PushProviderKind::Fcm),
+            "apns" => Some(PushProviderKind::Apns),
+            _ => None,
+        }
+    }
+}
+
+/// A device's registration with a push service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushSubscription {
+    pub provider: PushProviderKind,
+    /// Web Push endpoint URL, or the FCM or APNs device token
+    pub endpoint: String,
+    /// Encryption keys of a Web Push subscription
+    pub keys: Option<SubscriptionKeys>,
+    /// VAPID public key a Web Push subscription was made with
+    pub vapid_key: Option<String>,
+}
+
+/// A wake as the [`PushPolicy`](crate::notifications::PushPolicy) wants it sent
+#[derive(Debug, Clone, Copy)]
+pub struct Wake<'a> {
+    pub kind: PushKind,
+    pub options: PushOptions,
+    /// Wakes with the same topic replace each other while the device is unreachable
+    pub topic: Option<&'a str>,
+}
+
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    /// Sends one wake. Failures are part of the outcome, as they decide what happens to the
+    /// subscription
+    async fn send(&self, subscription: &PushSubscription, wake: &Wake<'_>) -> PushOutcome;
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tracing::{error, warn};
+use web_push::{ContentEncoding, SubscriptionInfo, WebPushMessageBuilder, request_builder};
+
+use crate::notifications::{
+    outcome::PushOutcome,
+    providers::{PushProvider, PushSubscription, Wake},
+    vapid::VapidKeys,
+};
+
+/// Payload of a wake
+pub const WAKE_PAYLOAD: &str = "wake";
+/// Payload of a wake to a subscription made with a VAPID key that is being rotated out. The
+/// client syncs as for any wake, then subscribes again with the current key
+pub const RESUBSCRIBE_PAYLOAD: &str = "resubscribe";
+
+/// Web Push with VAPID, payload encrypted to the subscription's keys
+pub struct WebPushProvider {
+    client: reqwest::Client,
+    vapid: Arc<VapidKeys>,
+}
+
+impl WebPushProvider {
+    pub fn new(client: reqwest::Client, vapid: Arc<VapidKeys>) -> Self {
+        WebPushProvider { client, vapid }
+    }
+}
+
+#[async_trait]
+impl PushProvider for WebPushProvider {
+    async fn send(&self, subscription: &PushSubscription, wake: &Wake<'_>) -> PushOutcome {
+        let Some(keys) = subscription.keys.clone() else {
+            return PushOutcome::Rejected("Web Push subscription has no keys".to_string());
+        };
+        let sub = SubscriptionInfo {
+            endpoint: subscription.endpoint.clone(),
+            keys,
+        };
+        let vapid_key = subscription
+            .vapid_key
+            .as_deref()
+            .unwrap_or(&self.vapid.current.public_key);
+        // Made with a retired key, so no push to it can be signed anymore
+        let Some(key) = self.vapid.signing_key(vapid_key) else {
+            return PushOutcome::Gone;
+        };
+        let payload = if key.public_key == self.vapid.current.public_key {
+            WAKE_PAYLOAD
+        } else {
+            RESUBSCRIBE_PAYLOAD
+        };
+
+        let mut signer = key.signer().add_sub_info(&sub);
+        if let Some(subject) = &self.vapid.subject {
+            signer.add_claim("sub", subject.as_str());
+        }
+        let Ok(sig) = signer.build() else {
+            return PushOutcome::Rejected("Failed to build vapid signature".to_string());
+        };
+        let mut message = WebPushMessageBuilder::new(&sub);
+        message.set_vapid_signature(sig);
+        message.set_payload(ContentEncoding::Aes128Gcm, payload.as_bytes());
+        message.set_ttl(wake.options.ttl);
+        message.set_urgency(wake.options.urgency);
+        if let Some(topic) = wake.topic {
+            message.set_topic(topic.to_string());
+        }
+
+        let Ok(payload) = message.build() else {
+            error!("Failed to build notifiaction");
+            return PushOutcome::Rejected("Failed to build notifiaction".to_string());
+        };
+
+        // Sent with our own client, as web-push's drops `Retry-After` on a 429
+        let request = request_builder::build_request::<Vec<u8>>(payload);
+        let mut builder = self.client.post(request.uri().to_string());
+        for (name, value) in request.headers() {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        match builder.body(request.into_body()).send().await {
+            Ok(response) => PushOutcome::from_response(response.status(), response.headers()),
+            Err(e) => {
+                warn!("POST to push service failed: {}", e);
+                PushOutcome::Unavailable(None)
+            }
+        }
+    }
+}
@@ -1,76 +1,283 @@
-use std::{env::var, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use anyhow::{self, Context};
-use tracing::info;
-use web_push::{
-    ContentEncoding, HyperWebPushClient, PartialVapidSignatureBuilder, SubscriptionInfo,
-    VapidSignatureBuilder, WebPushClient, WebPushMessageBuilder,
-};
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
 
 use crate::{
-    devices::DeviceId, errors::AppError, notifications::vapid::maybe_create_vapid_key,
+    devices::DeviceId,
+    errors::AppError,
+    notifications::{
+        metrics::{PushMetrics, PushMetricsSnapshot},
+        outcome::PushOutcome,
+        policy::{PushKind, PushPolicy},
+        providers::{
+            ApnsProvider, FcmProvider, PushProvider, PushProviderKind, PushSubscription, Wake,
+            WebPushProvider,
+        },
+        vapid::VapidKeys,
+    },
     storage::Storage,
 };
 
+/// Attempts per push before giving up on a subscription for this wake
+pub const MAX_PUSH_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled for each further one
+pub const PUSH_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// Longer waits are not sat out in the sending task. The subscription is skipped until then
+pub const MAX_PUSH_RETRY_WAIT: Duration = Duration::from_secs(30);
+/// How long a rate limited subscription is left alone when the service does not say
+pub const DEFAULT_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+pub const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct NotificationService {
     storage: Arc<Storage>,
-    client: HyperWebPushClient,
-    vapid: PartialVapidSignatureBuilder,
-    pub public_key: String,
+    client: reqwest::Client,
+    vapid: Arc<VapidKeys>,
+    providers: HashMap<PushProviderKind, Arc<dyn PushProvider>>,
+    metrics: PushMetrics,
+    retry_backoff: Duration,
+    policy: PushPolicy,
 }
 
 impl NotificationService {
+    /// Web Push is always enabled, with the keys of [`VapidKeys::from_env`]. FCM and APNs are
+    /// enabled when configured, see [`FcmProvider::new_from_env`] and
+    /// [`ApnsProvider::new_from_env`]
     pub async fn new(storage: Arc<Storage>) -> anyhow::Result<Self> {
-        let pem_path = var("VAPID_KEY_PATH").expect("VAPID_KEY_PATH should be set in enviroment");
-        let public_key = maybe_create_vapid_key(&pem_path)
+        let vapid = Arc::new(VapidKeys::from_env().await?);
+        // Subscriptions from before keys were recorded were made with the key in use until now
+        let untracked_key = vapid.active_previous().unwrap_or(&vapid.current);
+        storage
+            .notifications
+            .assign_vapid_key(&untracked_key.public_key)
             .await
-            .with_context(|| format!("Failed to create/load VAPID key at: {}", pem_path))?;
-        let file = std::fs::File::open(&pem_path)
-            .with_context(|| format!("Failed to open VAPID key file at: {}", pem_path))?;
+            .map_err(|e| anyhow::anyhow!("Failed to assign VAPID keys: {:?}", e))?;
+        let client = reqwest::Client::builder().timeout(PUSH_TIMEOUT).build()?;
+
+        let mut providers: HashMap<PushProviderKind, Arc<dyn PushProvider>> = HashMap::new();
+        providers.insert(
+            PushProviderKind::WebPush,
+            Arc::new(WebPushProvider::new(client.clone(), vapid.clone())),
+        );
+        if let Some(fcm) = FcmProvider::new_from_env(client.clone())? {
+            info!("Sending pushes through FCM");
+            providers.insert(PushProviderKind::Fcm, Arc::new(fcm));
+        }
+        if let Some(apns) = ApnsProvider::new_from_env(ApnsProvider::client(PUSH_TIMEOUT)?)? {
+            info!("Sending pushes through APNs");
+            providers.insert(PushProviderKind::Apns, Arc::new(apns));
+        }
+
         Ok(NotificationService {
             storage,
-            client: HyperWebPushClient::new(),
-            vapid: VapidSignatureBuilder::from_pem_no_sub(file)
-                .with_context(|| format!("Failed to parse VAPID key from: {}", pem_path))?,
-            public_key,
+            client,
+            vapid,
+            providers,
+            metrics: PushMetrics::default(),
+            retry_backoff: PUSH_RETRY_BACKOFF,
+            policy: PushPolicy::default(),
         })
     }
+    pub fn with_retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+    /// Sends pushes of `kind` through `provider`, replacing the configured one
+    pub fn with_provider(
+        mut self,
+        kind: PushProviderKind,
+        provider: Arc<dyn PushProvider>,
+    ) -> Self {
+        self.providers.insert(kind, provider);
+        self
+    }
+    /// Signs Web Push with `keys`, replacing the configured ones
+    pub fn with_vapid_keys(mut self, keys: VapidKeys) -> Self {
+        self.vapid = Arc::new(keys);
+        self.providers.insert(
+            PushProviderKind::WebPush,
+            Arc::new(WebPushProvider::new(
+                self.client.clone(),
+                self.vapid.clone(),
+            )),
+        );
+        self
+    }
+    pub fn with_policy(mut self, policy: PushPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    pub fn vapid(&self) -> &VapidKeys {
+        &self.vapid
+    }
+    pub fn metrics(&self) -> PushMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+    /// Providers devices can register with
+    pub fn providers(&self) -> Vec<PushProviderKind> {
+        let mut providers: Vec<_> = self.providers.keys().copied().collect();
+        providers.sort_by_key(|kind| kind.as_str());
+        providers
+    }
+    /// Web Push subscriptions without a VAPID key are taken to be made with the current one
     pub async fn register(
         &self,
         did: DeviceId,
-        endpoint: &SubscriptionInfo,
+        mut endpoint: PushSubscription,
     ) -> Result<(), AppError> {
+        if !self.providers.contains_key(&endpoint.provider) {
+            return Err(AppError::BadRequest(format!(
+                "Push provider {} is not enabled",
+                endpoint.provider.as_str()
+            )));
+        }
+        if endpoint.provider == PushProviderKind::WebPush {
+            let vapid_key = endpoint
+                .vapid_key
+                .get_or_insert_with(|| self.vapid.current.public_key.clone());
+            if self.vapid.signing_key(vapid_key).is_none() {
+                return Err(AppError::BadRequest(
+                    "Unknown application server key".to_string(),
+                ));
+            }
+        }
         self.storage
             .notifications
-            .upsert_endpoint(did, endpoint)
+            .upsert_endpoint(did, &endpoint)
             .await?;
         Ok(())
     }
-    pub async fn notify(&self, did: DeviceId) -> Result<(), AppError> {
-        info!("Sending {} notification", did);
-        let endpoint = self
+    /// Removes one of the device's subscriptions, or all of them without an `endpoint`
+    pub async fn revoke(&self, did: DeviceId, endpoint: Option<&str>) -> Result<(), AppError> {
+        match endpoint {
+            Some(endpoint) => {
+                if !self
+                    .storage
+                    .notifications
+                    .delete_endpoint(did, endpoint)
+                    .await?
+                {
+                    return Err(AppError::NotFound("Subscription not found".to_string()));
+                }
+            }
+            None => {
+                self.storage.notifications.delete_endpoints(did).await?;
+            }
+        }
+        Ok(())
+    }
+    /// Wakes every subscription of the device that is not waiting out a `Retry-After`. Fails
+    /// only if none could be reached
+    pub async fn notify(&self, did: DeviceId, kind: PushKind) -> Result<(), AppError> {
+        info!("Sending {} {:?} notification", did, kind);
+        let now = OffsetDateTime::now_utc();
+        let subscriptions: Vec<PushSubscription> = self
             .storage
             .notifications
-            .retrive_endpoint(did)
-            .await
-            .ok_or(anyhow::anyhow!("No endpoint found"))?;
-        let (sub, did) = endpoint;
-        let Ok(sig) = self.vapid.clone().add_sub_info(&sub).build() else {
-            return Err(anyhow::anyhow!("Failed to build vapid signature").into());
-        };
-        let mut message = WebPushMessageBuilder::new(&sub);
-        message.set_vapid_signature(sig);
-        message.set_payload(ContentEncoding::Aes128Gcm, "wake".as_bytes());
+            .get_endpoints(did)
+            .await?
+            .into_iter()
+            .filter(|stored| stored.retry_after.is_none_or(|at| at <= now))
+            .map(|stored| stored.subscription)
+            .collect();
+        if subscriptions.is_empty() {
+            return Err(anyhow::anyhow!("No endpoint found").into());
+        }
 
-        let Ok(payload) = message.build() else {
-            tracing::error!("Failed to build notifiaction");
-            return Err(anyhow::anyhow!("Failed to build notifiaction").into());
+        let topic = self.policy.topic(did, kind);
+        let wake = Wake {
+            kind,
+            options: self.policy.options(kind),
+            topic: topic.as_deref(),
         };
-        if let Err(e) = self.client.send(payload).await {
-            let _ = self.storage.notifications.delete_endpoint(did).await;
-            return Err(anyhow::anyhow!("POST failed: {}", e).into());
+        let mut sent = false;
+        for sub in &subscriptions {
+            // Left in place in case the provider is enabled again
+            let Some(provider) = self.providers.get(&sub.provider) else {
+                warn!("Push provider {} is not enabled", sub.provider.as_str());
+                continue;
+            };
+            sent |= self
+                .send_with_retry(did, provider.as_ref(), sub, &wake)
+                .await;
+        }
+        if !sent {
+            return Err(anyhow::anyhow!("No endpoint of {} could be reached", did).into());
         }
         tracing::debug!("Sent Notification to: {}", did);
         Ok(())
     }
+    /// Sends until the push goes through or retrying stops making sense. Only a subscription
+    /// the push service says is gone is deleted. Failing to record the outcome is logged, as
+    /// it must not keep the device's other subscriptions from being woken
+    async fn send_with_retry(
+        &self,
+        did: DeviceId,
+        provider: &dyn PushProvider,
+        sub: &PushSubscription,
+        wake: &Wake<'_>,
+    ) -> bool {
+        let notifications = &self.storage.notifications;
+        let mut backoff = self.retry_backoff;
+        for attempt in 1..=MAX_PUSH_ATTEMPTS {
+            let outcome = provider.send(sub, wake).await;
+            self.metrics.record(&outcome);
+
+            let retry_after = match outcome {
+                PushOutcome::Delivered => {
+                    if let Err(e) = notifications.record_push_success(&sub.endpoint).await {
+                        error!("Failed to record push success of {}: {:?}", did, e);
+                    }
+                    return true;
+                }
+                PushOutcome::Gone => {
+                    info!("Push subscription of {} is gone, deleting it", did);
+                    if let Err(e) = notifications.delete_endpoint(did, &sub.endpoint).await {
+                        error!("Failed to delete push subscription of {}: {:?}", did, e);
+                    }
+                    return false;
+                }
+                PushOutcome::Rejected(reason) => {
+                    warn!("Push to {} was rejected: {}", did, reason);
+                    self.record_failure(did, sub, None).await;
+                    return false;
+                }
+                PushOutcome::RateLimited(after) => {
+                    warn!("Push to {} was rate limited", did);
+                    Some(after.unwrap_or(DEFAULT_RATE_LIMIT_WAIT))
+                }
+                PushOutcome::Unavailable(after) => {
+                    warn!("Push service of {} is unavailable", did);
+                    after
+                }
+            };
+
+            let wait = retry_after.unwrap_or(backoff);
+            if attempt == MAX_PUSH_ATTEMPTS || wait > MAX_PUSH_RETRY_WAIT {
+                // Later wakes skip the subscription until the service is ready again
+                let hold_until = retry_after.map(|after| OffsetDateTime::now_utc() + after);
+                self.record_failure(did, sub, hold_until).await;
+                return false;
+            }
+            self.metrics.record_retry();
+            tokio::time::sleep(wait).await;
+            backoff *= 2;
+        }
+        false
+    }
+    async fn record_failure(
+        &self,
+        did: DeviceId,
+        sub: &PushSubscription,
+        hold_until: Option<OffsetDateTime>,
+    ) {
+        if let Err(e) = self
+            .storage
+            .notifications
+            .record_push_failure(&sub.endpoint, hold_until)
+            .await
+        {
+            error!("Failed to record push failure of {}: {:?}", did, e);
+        }
+    }
 }
@@ -4,14 +4,147 @@ use openssl::bn::BigNumContext;
 use openssl::ec::{EcGroup, EcKey, PointConversionForm};
 use openssl::nid::Nid;
 use openssl::pkey::PKey;
+use std::env::var;
 use std::path::{Path, PathBuf};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+use web_push::{PartialVapidSignatureBuilder, VapidSignatureBuilder};
 
 fn b64(b: Vec<u8>) -> String {
     URL_SAFE_NO_PAD.encode(b)
 }
 
+/// A key pair push subscriptions are made with
+#[derive(Clone)]
+pub struct VapidKey {
+    /// Uncompressed public key in base64url, as clients pass it to `pushManager.subscribe`
+    pub public_key: String,
+    builder: PartialVapidSignatureBuilder,
+}
+
+impl VapidKey {
+    /// Loads the PEM key at `path`, creating it first if there is none
+    pub async fn load_or_create(path: &str) -> Result<Self> {
+        maybe_create_vapid_key(path)
+            .await
+            .with_context(|| format!("Failed to create/load VAPID key at: {}", path))?;
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to open VAPID key file at: {}", path))?;
+        Self::from_pem(&pem).with_context(|| format!("Failed to parse VAPID key from: {}", path))
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let builder = VapidSignatureBuilder::from_pem_no_sub(pem)?;
+        Ok(VapidKey {
+            public_key: b64(builder.get_public_key()),
+            builder,
+        })
+    }
+
+    pub fn signer(&self) -> PartialVapidSignatureBuilder {
+        self.builder.clone()
+    }
+}
+
+/// The keys Web Push is signed with. After a rotation the previous key keeps signing pushes to
+/// the subscriptions made with it, which are asked to subscribe again with the current one,
+/// until it retires
+#[derive(Clone)]
+pub struct VapidKeys {
+    pub current: VapidKey,
+    pub previous: Option<VapidKey>,
+    /// When the previous key stops being used. Kept until it is removed from the config if unset
+    pub retires_at: Option<OffsetDateTime>,
+    /// `mailto:` or `https:` contact sent as the `sub` claim, which some push services require
+    pub subject: Option<String>,
+}
+
+impl VapidKeys {
+    pub fn new(current: VapidKey) -> Self {
+        VapidKeys {
+            current,
+            previous: None,
+            retires_at: None,
+            subject: None,
+        }
+    }
+
+    /// Key at `VAPID_KEY_PATH`, with the subject from `VAPID_SUBJECT`. During a rotation the old
+    /// key is at `VAPID_PREVIOUS_KEY_PATH`, retiring at `VAPID_PREVIOUS_KEY_RETIRES` (RFC 3339)
+    pub async fn from_env() -> Result<Self> {
+        let path = var("VAPID_KEY_PATH").context("VAPID_KEY_PATH should be set in enviroment")?;
+        let mut keys = VapidKeys::new(VapidKey::load_or_create(&path).await?);
+
+        match var("VAPID_SUBJECT") {
+            Ok(subject) => keys = keys.with_subject(subject)?,
+            Err(_) => {
+                warn!("VAPID_SUBJECT is not set, some push services reject pushes without one")
+            }
+        }
+
+        if let Ok(previous_path) = var("VAPID_PREVIOUS_KEY_PATH") {
+            let pem = std::fs::read(&previous_path).with_context(|| {
+                format!(
+                    "Failed to open previous VAPID key file at: {}",
+                    previous_path
+                )
+            })?;
+            let previous = VapidKey::from_pem(&pem).with_context(|| {
+                format!("Failed to parse previous VAPID key from: {}", previous_path)
+            })?;
+            let retires_at = match var("VAPID_PREVIOUS_KEY_RETIRES") {
+                Ok(at) => Some(
+                    OffsetDateTime::parse(&at, &Rfc3339)
+                        .with_context(|| format!("Invalid VAPID_PREVIOUS_KEY_RETIRES: '{}'", at))?,
+                ),
+                Err(_) => None,
+            };
+            info!(
+                "Rotating VAPID key {} to {}",
+                previous.public_key, keys.current.public_key
+            );
+            keys = keys.with_previous(previous, retires_at);
+        }
+        Ok(keys)
+    }
+
+    pub fn with_subject(mut self, subject: String) -> Result<Self> {
+        if !subject.starts_with("mailto:") && !subject.starts_with("https://") {
+            anyhow::bail!(
+                "VAPID subject must be a mailto: or https: URL, got '{}'",
+                subject
+            );
+        }
+        self.subject = Some(subject);
+        Ok(self)
+    }
+
+    pub fn with_previous(mut self, previous: VapidKey, retires_at: Option<OffsetDateTime>) -> Self {
+        self.previous = Some(previous);
+        self.retires_at = retires_at;
+        self
+    }
+
+    /// The previous key while it is still in use
+    pub fn active_previous(&self) -> Option<&VapidKey> {
+        let retired = self
+            .retires_at
+            .is_some_and(|at| at <= OffsetDateTime::now_utc());
+        self.previous.as_ref().filter(|_| !retired)
+    }
+
+    /// The key to sign for a subscription made with `public_key`, `None` once that key retired
+    pub fn signing_key(&self, public_key: &str) -> Option<&VapidKey> {
+        if public_key == self.current.public_key {
+            return Some(&self.current);
+        }
+        self.active_previous()
+            .filter(|previous| previous.public_key == public_key)
+    }
+}
+
 pub async fn maybe_create_vapid_key(path: &str) -> Result<String> {
     if Path::new(path).exists() {
         // Load existing key and extract public key
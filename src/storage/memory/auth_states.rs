@@ -0,0 +1,39 @@
+use async_trait::async_trait;
+use dashmap::DashMap;
+use time::OffsetDateTime;
+
+use crate::{
+    errors::AppError,
+    storage::{models::StoredAuthState, traits::AuthStateStore},
+};
+
+/// Keeps OIDC login state in process memory. Only suitable when a single node serves the
+/// OIDC callback
+#[derive(Default)]
+pub struct MemoryAuthStateStore {
+    states: DashMap<String, StoredAuthState>,
+}
+
+impl MemoryAuthStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AuthStateStore for MemoryAuthStateStore {
+    async fn insert_auth_state(&self, state: &StoredAuthState) -> Result<(), AppError> {
+        self.states.insert(state.csrf_token.clone(), state.clone());
+        Ok(())
+    }
+
+    async fn take_auth_state(&self, csrf_token: &str) -> Result<Option<StoredAuthState>, AppError> {
+        Ok(self.states.remove(csrf_token).map(|(_, state)| state))
+    }
+
+    async fn delete_expired_auth_states(&self, now: OffsetDateTime) -> Result<u64, AppError> {
+        let before = self.states.len();
+        self.states.retain(|_, state| state.expires_at > now);
+        Ok(before.saturating_sub(self.states.len()) as u64)
+    }
+}
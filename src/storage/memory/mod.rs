@@ -0,0 +1,3 @@
+pub mod auth_states;
+
+pub use auth_states::MemoryAuthStateStore;
@@ -1,3 +1,4 @@
+pub mod memory;
 pub mod models;
 pub mod postgres;
 pub mod traits;
@@ -13,4 +14,10 @@ pub struct Storage {
     pub actors: Arc<dyn ActorStore>,
     pub users: Arc<dyn UserStore>,
     pub groups: Arc<dyn GroupStore>,
+    pub signing_keys: Arc<dyn SigningKeyStore>,
+    pub auth_states: Arc<dyn AuthStateStore>,
+    pub exports: Arc<dyn DataExportStore>,
+    pub profiles: Arc<dyn ProfileStore>,
+    pub socket_routes: Arc<dyn SocketRouteStore>,
+    pub socket_tickets: Arc<dyn SocketTicketStore>,
 }
@@ -45,6 +45,40 @@ pub struct RotatedRefreshToken {
     pub expires_at: OffsetDateTime,
 }
 
+/// A device as shown to its owner, with details of the last token refresh
+#[derive(Debug, Clone)]
+pub struct StoredDevice {
+    pub did: DeviceId,
+    pub device_name: Option<String>,
+    pub is_approved: bool,
+    pub created_at: OffsetDateTime,
+    pub last_ip_address: Option<String>,
+    pub last_user_agent: Option<String>,
+    pub last_refreshed_at: Option<OffsetDateTime>,
+}
+
+/// A push subscription of a device, with how pushes to it have been failing
+#[derive(Debug, Clone)]
+pub struct StoredSubscription {
+    pub subscription: crate::notifications::PushSubscription,
+    /// Failed pushes since the last one that went through
+    pub failure_count: i32,
+    pub last_failure_at: Option<OffsetDateTime>,
+    /// The push service asked not to be sent to before this
+    pub retry_after: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RefreshTokenRotation {
+    Rotated(RotatedRefreshToken),
+    /// The token was already rotated away. Its family has been revoked
+    Reused {
+        uid: String,
+        did: DeviceId,
+    },
+    Invalid,
+}
+
 #[derive(Debug, Clone)]
 pub struct StoredUser {
     pub uid: String,
@@ -66,3 +100,76 @@ pub struct StoredGroupState {
     pub encrypted_content: Vec<u8>,
     pub encoding: String,
 }
+
+#[derive(Debug, Clone)]
+pub struct StoredSigningKey {
+    pub kid: String,
+    pub purpose: String,
+    pub algorithm: String,
+    pub private_key: Vec<u8>,
+    pub created_at: OffsetDateTime,
+}
+
+/// An OIDC login that has been started but not yet called back
+#[derive(Debug, Clone)]
+pub struct StoredAuthState {
+    pub csrf_token: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub provider: String,
+    pub expires_at: OffsetDateTime,
+}
+
+/// A socket ticket that has been issued but not yet used
+#[derive(Debug, Clone)]
+pub struct StoredSocketTicket {
+    pub jti: Uuid,
+    pub did: DeviceId,
+    pub expires_at: OffsetDateTime,
+}
+
+/// A requested data export. `status` is `pending`, `ready` or `failed`
+#[derive(Debug, Clone)]
+pub struct StoredDataExport {
+    pub id: Uuid,
+    pub uid: String,
+    pub status: String,
+    pub created_at: OffsetDateTime,
+    pub completed_at: Option<OffsetDateTime>,
+    pub downloaded_at: Option<OffsetDateTime>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// An activity waiting in a device's inbox, without its content
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredPendingDelivery {
+    pub activity_id: String,
+    pub activity_type: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// An activity in a device's inbox with its position there
+#[derive(Debug)]
+pub struct StoredInboxActivity {
+    pub seq: i64,
+    pub activity: crate::activitypub::Activity,
+}
+
+/// Profile fields a user sets themselves
+#[derive(Debug, Clone, Default)]
+pub struct StoredProfile {
+    pub display_name: Option<String>,
+    pub summary: Option<String>,
+    pub avatar_id: Option<Uuid>,
+}
+
+/// An avatar image and its thumbnail, both encoded as `content_type`
+#[derive(Debug, Clone)]
+pub struct StoredAvatar {
+    pub id: Uuid,
+    pub content_type: String,
+    pub image: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+}
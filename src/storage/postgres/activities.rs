@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::activitypub::types::eko_types::EncryptedMessageView;
 use async_trait::async_trait;
@@ -8,6 +8,7 @@ use crate::activitypub::types::activity::{ActivityType, CreateView};
 use crate::activitypub::{Activity, Create, EncryptedMessageEntry};
 use crate::devices::DeviceId;
 use crate::errors::AppError;
+use crate::storage::models::{StoredInboxActivity, StoredPendingDelivery};
 use crate::storage::traits::ActivityStore;
 
 pub struct PostgresActivityStore {
@@ -19,15 +20,19 @@ impl PostgresActivityStore {
     pub fn new(domain: Arc<String>, pool: PgPool) -> Self {
         Self { domain, pool }
     }
-}
 
-#[async_trait]
-impl ActivityStore for PostgresActivityStore {
-    async fn inbox_activities(&self, did: DeviceId) -> Result<Vec<Activity>, AppError> {
-        // Fetch all activities that have a delivery request for this device
+    /// Up to `limit` activities with a delivery request for the device after `after_seq`, in
+    /// sequence order, with the ids of the activities that are not a `Create`
+    async fn fetch_inbox(
+        &self,
+        did: DeviceId,
+        after_seq: i64,
+        limit: Option<i64>,
+    ) -> Result<(Vec<StoredInboxActivity>, Vec<String>), AppError> {
         let rows = sqlx::query!(
             r#"
             SELECT 
+                d.seq,
                 ia.id,
                 ia.type::text as "activity_type!",
                 ia.activity_json,
@@ -36,10 +41,13 @@ impl ActivityStore for PostgresActivityStore {
             FROM inbox_activities ia
             JOIN deliveries d ON ia.id = d.activity_id
             LEFT JOIN message_entries me ON ia.id = me.activity_id AND me.to_did = d.to_did
-            WHERE d.to_did = $1
-            ORDER BY ia.created_at ASC
+            WHERE d.to_did = $1 AND d.seq > $2
+            ORDER BY d.seq ASC
+            LIMIT $3
             "#,
-            did.as_uuid()
+            did.as_uuid(),
+            after_seq,
+            limit
         )
         .fetch_all(&self.pool)
         .await?;
@@ -60,24 +68,76 @@ impl ActivityStore for PostgresActivityStore {
                         }];
                     }
                 }
-                Activity::Take(_) | Activity::Delivered(_) => {
+                Activity::Take(_)
+                | Activity::Delivered(_)
+                | Activity::Update(_)
+                | Activity::Delete(_) => {
                     activity_ids_to_delete.push(row.id.clone());
                 }
             };
 
-            activities.push(activity);
+            activities.push(StoredInboxActivity {
+                seq: row.seq,
+                activity,
+            });
         }
 
+        Ok((activities, activity_ids_to_delete))
+    }
+}
+
+#[async_trait]
+impl ActivityStore for PostgresActivityStore {
+    async fn inbox_activities(&self, did: DeviceId) -> Result<Vec<Activity>, AppError> {
+        let (activities, activity_ids_to_delete) = self.fetch_inbox(did, 0, None).await?;
+
         // Delete all deliveries at once
         if !activity_ids_to_delete.is_empty() {
             self.delete_deliveries(&activity_ids_to_delete, &did)
                 .await?;
         }
 
+        Ok(activities.into_iter().map(|a| a.activity).collect())
+    }
+
+    async fn pending_activities(
+        &self,
+        did: DeviceId,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<StoredInboxActivity>, AppError> {
+        let (activities, _) = self.fetch_inbox(did, after_seq, Some(limit)).await?;
         Ok(activities)
     }
 
-    async fn insert_create(&self, create: &Create) -> Result<(), AppError> {
+    async fn acknowledge_deliveries(
+        &self,
+        activity_ids: &[String],
+        did: &DeviceId,
+    ) -> Result<u64, AppError> {
+        if activity_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM deliveries d
+            USING inbox_activities ia
+            WHERE ia.id = d.activity_id
+              AND d.activity_id = ANY($1)
+              AND d.to_did = $2
+              AND ia.type <> 'Create'
+            "#,
+            activity_ids,
+            did.as_uuid()
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_create(&self, create: &Create) -> Result<HashMap<DeviceId, i64>, AppError> {
         let activity_id = create
             .id
             .as_ref()
@@ -98,6 +158,7 @@ impl ActivityStore for PostgresActivityStore {
                 to: &create.object.to,
             },
             to: &create.to,
+            notify: create.notify,
             type_field: "Create",
         };
         let activity_json = serde_json::to_value(&activity_view)?;
@@ -116,10 +177,18 @@ impl ActivityStore for PostgresActivityStore {
         .execute(&mut *tx)
         .await?;
 
-        // Insert message entries and delivery requests for each device
-        for entry in &create.object.content {
-            let to_did = DeviceId::from_url(&entry.to)?;
+        // Insert message entries and delivery requests for each device. Sequence rows are
+        // locked in device order so concurrent inserts cannot deadlock
+        let mut entries = create
+            .object
+            .content
+            .iter()
+            .map(|entry| Ok((DeviceId::from_url(&entry.to)?, entry)))
+            .collect::<Result<Vec<_>, AppError>>()?;
+        entries.sort_by_key(|(did, _)| did.as_uuid());
 
+        let mut seqs = HashMap::new();
+        for (to_did, entry) in entries {
             // Insert message entry
             sqlx::query!(
                 r#"
@@ -135,27 +204,29 @@ impl ActivityStore for PostgresActivityStore {
             .await?;
 
             // Insert delivery request
-            sqlx::query!(
+            let row = sqlx::query!(
                 r#"
                 INSERT INTO deliveries (activity_id, to_did)
                 VALUES ($1, $2)
+                RETURNING seq
                 "#,
                 activity_id,
                 to_did.as_uuid()
             )
-            .execute(&mut *tx)
+            .fetch_one(&mut *tx)
             .await?;
+            seqs.insert(to_did, row.seq);
         }
 
         tx.commit().await?;
-        Ok(())
+        Ok(seqs)
     }
 
     async fn insert_non_create(
         &self,
         activity: &Activity,
         dids: &[DeviceId],
-    ) -> Result<(), AppError> {
+    ) -> Result<HashMap<DeviceId, i64>, AppError> {
         let activity_id = activity
             .as_base()
             .id()
@@ -180,22 +251,28 @@ impl ActivityStore for PostgresActivityStore {
         .execute(&mut *tx)
         .await?;
 
-        // Insert delivery requests for each device
+        // Insert delivery requests for each device, in the same order as insert_create
+        let mut dids = dids.to_vec();
+        dids.sort_by_key(|did| did.as_uuid());
+
+        let mut seqs = HashMap::new();
         for did in dids {
-            sqlx::query!(
+            let row = sqlx::query!(
                 r#"
                 INSERT INTO deliveries (activity_id, to_did)
                 VALUES ($1, $2)
+                RETURNING seq
                 "#,
                 activity_id,
                 did.as_uuid()
             )
-            .execute(&mut *tx)
+            .fetch_one(&mut *tx)
             .await?;
+            seqs.insert(did, row.seq);
         }
 
         tx.commit().await?;
-        Ok(())
+        Ok(seqs)
     }
 
     async fn delete_delivery(&self, activity_id: &str, did: &DeviceId) -> Result<bool, AppError> {
@@ -255,4 +332,25 @@ impl ActivityStore for PostgresActivityStore {
         let was_claimed = result.is_some();
         Ok(was_claimed)
     }
+
+    async fn pending_deliveries(
+        &self,
+        did: DeviceId,
+    ) -> Result<Vec<StoredPendingDelivery>, AppError> {
+        let deliveries = sqlx::query_as!(
+            StoredPendingDelivery,
+            r#"
+            SELECT ia.id AS activity_id, ia.type::text AS "activity_type!", ia.created_at
+            FROM inbox_activities ia
+            JOIN deliveries d ON ia.id = d.activity_id
+            WHERE d.to_did = $1
+            ORDER BY ia.created_at ASC
+            "#,
+            did.as_uuid()
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
 }
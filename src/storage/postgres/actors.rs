@@ -52,4 +52,47 @@ impl ActorStore for PostgresActorStore {
 
         Ok(row.map(|r| r.is_local).unwrap_or(false))
     }
+
+    async fn record_contact(&self, actor_id: &str, contact_id: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO actor_contacts (actor_id, contact_id)
+            VALUES ($1, $2)
+            ON CONFLICT (actor_id, contact_id)
+            DO UPDATE SET last_interaction_at = NOW()
+            "#,
+            actor_id,
+            contact_id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_contacts(&self, actor_id: &str) -> Result<Vec<String>, AppError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT contact_id AS "id!" FROM actor_contacts WHERE actor_id = $1
+            UNION
+            SELECT actor_id AS "id!" FROM actor_contacts WHERE contact_id = $1
+            "#,
+            actor_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.id).collect())
+    }
+
+    async fn is_tombstoned(&self, actor_id: &str) -> Result<bool, AppError> {
+        let row = sqlx::query!(
+            r#"SELECT EXISTS(SELECT 1 FROM actor_tombstones WHERE id = $1) AS "exists!""#,
+            actor_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.exists)
+    }
 }
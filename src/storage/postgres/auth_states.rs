@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+
+use crate::{
+    errors::AppError,
+    storage::{models::StoredAuthState, traits::AuthStateStore},
+};
+
+pub struct PostgresAuthStateStore {
+    pool: PgPool,
+}
+
+impl PostgresAuthStateStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl AuthStateStore for PostgresAuthStateStore {
+    async fn insert_auth_state(&self, state: &StoredAuthState) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO oidc_auth_states (csrf_token, nonce, pkce_verifier, provider, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            state.csrf_token,
+            state.nonce,
+            state.pkce_verifier,
+            state.provider,
+            state.expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_auth_state(&self, csrf_token: &str) -> Result<Option<StoredAuthState>, AppError> {
+        let state = sqlx::query_as!(
+            StoredAuthState,
+            r#"
+            DELETE FROM oidc_auth_states
+            WHERE csrf_token = $1
+            RETURNING csrf_token, nonce, pkce_verifier, provider, expires_at
+            "#,
+            csrf_token
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(state)
+    }
+
+    async fn delete_expired_auth_states(&self, now: OffsetDateTime) -> Result<u64, AppError> {
+        let result = sqlx::query!("DELETE FROM oidc_auth_states WHERE expires_at < $1", now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -1,7 +1,10 @@
 use crate::storage::Storage;
 use crate::storage::postgres::{
     PostgresNotificationStore, activities::PostgresActivityStore, actors::PostgresActorStore,
-    devices::PostgresDeviceStore, groups::PostgresGroupStore, users::PostgresUserStore,
+    auth_states::PostgresAuthStateStore, data_exports::PostgresDataExportStore,
+    devices::PostgresDeviceStore, groups::PostgresGroupStore, profiles::PostgresProfileStore,
+    signing_keys::PostgresSigningKeyStore, socket_routes::PostgresSocketRouteStore,
+    socket_tickets::PostgresSocketTicketStore, users::PostgresUserStore,
 };
 use sqlx::PgPool;
 use std::sync::Arc;
@@ -13,6 +16,12 @@ pub fn postgres_storage(domain: Arc<String>, pool: PgPool) -> Storage {
         actors: Arc::new(PostgresActorStore::new(pool.clone())),
         devices: Arc::new(PostgresDeviceStore::new(domain, pool.clone())),
         groups: Arc::new(PostgresGroupStore::new(pool.clone())),
+        signing_keys: Arc::new(PostgresSigningKeyStore::new(pool.clone())),
+        auth_states: Arc::new(PostgresAuthStateStore::new(pool.clone())),
+        exports: Arc::new(PostgresDataExportStore::new(pool.clone())),
+        profiles: Arc::new(PostgresProfileStore::new(pool.clone())),
+        socket_routes: Arc::new(PostgresSocketRouteStore::new(pool.clone())),
+        socket_tickets: Arc::new(PostgresSocketTicketStore::new(pool.clone())),
         users: Arc::new(PostgresUserStore::new(pool)),
     }
 }
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    storage::{models::StoredDataExport, traits::DataExportStore},
+};
+
+pub struct PostgresDataExportStore {
+    pool: PgPool,
+}
+
+impl PostgresDataExportStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl DataExportStore for PostgresDataExportStore {
+    async fn create_export(&self, uid: &str, expires_at: OffsetDateTime) -> Result<Uuid, AppError> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO data_exports (uid, expires_at)
+            VALUES ($1, $2)
+            RETURNING id
+            "#,
+            uid,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.id)
+    }
+
+    async fn complete_export(&self, id: Uuid, archive: &serde_json::Value) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE data_exports
+            SET status = 'ready', archive = $2, completed_at = NOW()
+            WHERE id = $1
+            "#,
+            id,
+            archive
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fail_export(&self, id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE data_exports
+            SET status = 'failed', completed_at = NOW()
+            WHERE id = $1
+            "#,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_export(&self, uid: &str, id: Uuid) -> Result<Option<StoredDataExport>, AppError> {
+        let export = sqlx::query_as!(
+            StoredDataExport,
+            r#"
+            SELECT id, uid, status, created_at, completed_at, downloaded_at, expires_at
+            FROM data_exports
+            WHERE id = $1 AND uid = $2
+            "#,
+            id,
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    async fn take_export_archive(
+        &self,
+        uid: &str,
+        id: Uuid,
+    ) -> Result<Option<serde_json::Value>, AppError> {
+        // The old row is read in a sub-select since RETURNING only sees the updated values
+        let row = sqlx::query!(
+            r#"
+            UPDATE data_exports e
+            SET archive = NULL, downloaded_at = NOW()
+            FROM (SELECT id, archive FROM data_exports WHERE id = $1 FOR UPDATE) old
+            WHERE e.id = old.id
+              AND e.uid = $2
+              AND e.status = 'ready'
+              AND e.downloaded_at IS NULL
+              AND e.expires_at > NOW()
+            RETURNING old.archive
+            "#,
+            id,
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(|r| r.archive))
+    }
+
+    async fn delete_expired_exports(&self, now: OffsetDateTime) -> Result<u64, AppError> {
+        let result = sqlx::query!("DELETE FROM data_exports WHERE expires_at < $1", now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
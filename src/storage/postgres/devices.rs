@@ -14,7 +14,7 @@ use crate::{
     devices::DeviceId,
     errors::AppError,
     storage::{
-        models::{RegisterDeviceResult, RotatedRefreshToken},
+        models::{RefreshTokenRotation, RegisterDeviceResult, RotatedRefreshToken, StoredDevice},
         traits::DeviceStore,
     },
 };
@@ -141,12 +141,12 @@ impl DeviceStore for PostgresDeviceStore {
         old_token: &Uuid,
         ip_address: &str,
         user_agent: &str,
-    ) -> Result<Option<RotatedRefreshToken>, AppError> {
+    ) -> Result<RefreshTokenRotation, AppError> {
         let mut tx = self.pool.begin().await?;
 
         let row = sqlx::query!(
             r#"
-            SELECT d.did, d.uid, r.user_agent, r.expires_at
+            SELECT d.did, d.uid, r.user_agent, r.expires_at, r.family_id
             FROM refresh_tokens r
             JOIN devices d ON d.did = r.did
             WHERE r.token = $1
@@ -159,16 +159,69 @@ impl DeviceStore for PostgresDeviceStore {
         let row = match row {
             Some(r) => r,
             None => {
+                let reused = sqlx::query!(
+                    r#"
+                    SELECT d.did, d.uid, r.family_id
+                    FROM retired_refresh_tokens r
+                    JOIN devices d ON d.did = r.did
+                    WHERE r.token = $1 AND r.expires_at > NOW()
+                    "#,
+                    old_token
+                )
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                let Some(reused) = reused else {
+                    tx.commit().await?;
+                    return Ok(RefreshTokenRotation::Invalid);
+                };
+
+                sqlx::query!(
+                    "DELETE FROM refresh_tokens WHERE family_id = $1",
+                    reused.family_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM retired_refresh_tokens WHERE family_id = $1",
+                    reused.family_id
+                )
+                .execute(&mut *tx)
+                .await?;
+
                 tx.commit().await?;
-                return Ok(None);
+                return Ok(RefreshTokenRotation::Reused {
+                    uid: reused.uid,
+                    did: DeviceId::new(reused.did),
+                });
             }
         };
 
         if row.expires_at <= time::OffsetDateTime::now_utc() || row.user_agent != user_agent {
             tx.commit().await?;
-            return Ok(None);
+            return Ok(RefreshTokenRotation::Invalid);
         }
 
+        sqlx::query!(
+            "DELETE FROM retired_refresh_tokens WHERE did = $1 AND expires_at <= NOW()",
+            row.did
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO retired_refresh_tokens (token, did, family_id, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            old_token,
+            row.did,
+            row.family_id,
+            row.expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
         sqlx::query!("DELETE FROM refresh_tokens WHERE did = $1", row.did)
             .execute(&mut *tx)
             .await?;
@@ -178,21 +231,22 @@ impl DeviceStore for PostgresDeviceStore {
 
         let new_token = sqlx::query!(
             r#"
-            INSERT INTO refresh_tokens (did, ip_address, user_agent, expires_at)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO refresh_tokens (did, ip_address, user_agent, expires_at, family_id)
+            VALUES ($1, $2, $3, $4, $5)
             RETURNING token
             "#,
             row.did,
             ip_address,
             user_agent,
-            expires_at
+            expires_at,
+            row.family_id
         )
         .fetch_one(&mut *tx)
         .await?
         .token;
 
         tx.commit().await?;
-        Ok(Some(RotatedRefreshToken {
+        Ok(RefreshTokenRotation::Rotated(RotatedRefreshToken {
             refresh_token: new_token,
             uid: row.uid,
             did: DeviceId::new(row.did),
@@ -200,23 +254,93 @@ impl DeviceStore for PostgresDeviceStore {
         }))
     }
 
-    async fn logout_device(&self, refresh_token: &Uuid) -> Result<(), AppError> {
+    async fn logout_device(&self, refresh_token: &Uuid) -> Result<Option<DeviceId>, AppError> {
         //TODO This is a placeholder to keep everything working. Client needs to generate the
         //revoke eventually
-        sqlx::query!(
+        Ok(sqlx::query_scalar!(
             r#"
             INSERT INTO device_actions(is_add, did, uid)
             SELECT FALSE, r.did, d.uid
             FROM refresh_tokens r
             JOIN devices d ON d.did = r.did
             WHERE r.token = $1
+            RETURNING did
             "#,
             refresh_token
         )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(DeviceId::new))
+    }
+
+    async fn list_devices(&self, uid: &str) -> Result<Vec<StoredDevice>, AppError> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT d.did, d.is_approved, d.created_at, a.device_name,
+                   r.ip_address AS "last_ip_address?",
+                   r.user_agent AS "last_user_agent?",
+                   r.issued_at AS "last_refreshed_at?"
+            FROM devices d
+            LEFT JOIN device_actions a ON a.did = d.did AND a.is_add = TRUE
+            LEFT JOIN refresh_tokens r ON r.did = d.did
+            WHERE d.uid = $1
+            ORDER BY d.created_at ASC
+            "#,
+            uid
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| StoredDevice {
+            did: DeviceId::new(r.did),
+            device_name: r.device_name,
+            is_approved: r.is_approved,
+            created_at: r.created_at,
+            last_ip_address: r.last_ip_address,
+            last_user_agent: r.last_user_agent,
+            last_refreshed_at: r.last_refreshed_at,
+        })
+        .collect())
+    }
+
+    async fn revoke_device(&self, uid: &str, did: DeviceId) -> Result<bool, AppError> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO device_actions(is_add, did, uid)
+            SELECT FALSE, did, uid
+            FROM devices
+            WHERE did = $1 AND uid = $2
+            "#,
+            did.as_uuid(),
+            uid
+        )
         .execute(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_devices(
+        &self,
+        uid: &str,
+        keep: Option<DeviceId>,
+    ) -> Result<Vec<DeviceId>, AppError> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            INSERT INTO device_actions(is_add, did, uid)
+            SELECT FALSE, did, uid
+            FROM devices
+            WHERE uid = $1 AND did IS DISTINCT FROM $2
+            RETURNING did
+            "#,
+            uid,
+            keep.map(|did| did.as_uuid())
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(DeviceId::new)
+        .collect())
     }
 
     async fn device_actions_for_user(&self, uid: &str) -> Result<Vec<DeviceAction>, AppError> {
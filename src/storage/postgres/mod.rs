@@ -1,14 +1,26 @@
 pub mod activities;
 pub mod actors;
+pub mod auth_states;
 pub mod connection;
+pub mod data_exports;
 pub mod devices;
 pub mod groups;
 pub mod notifications;
+pub mod profiles;
+pub mod signing_keys;
+pub mod socket_routes;
+pub mod socket_tickets;
 pub mod users;
 
 pub use activities::PostgresActivityStore;
 pub use actors::PostgresActorStore;
+pub use auth_states::PostgresAuthStateStore;
+pub use data_exports::PostgresDataExportStore;
 pub use devices::PostgresDeviceStore;
 pub use groups::PostgresGroupStore;
 pub use notifications::PostgresNotificationStore;
+pub use profiles::PostgresProfileStore;
+pub use signing_keys::PostgresSigningKeyStore;
+pub use socket_routes::PostgresSocketRouteStore;
+pub use socket_tickets::PostgresSocketTicketStore;
 pub use users::PostgresUserStore;
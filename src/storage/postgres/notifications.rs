@@ -1,8 +1,14 @@
 use async_trait::async_trait;
 use sqlx::PgPool;
-use web_push::{SubscriptionInfo, SubscriptionKeys};
+use time::OffsetDateTime;
+use web_push::SubscriptionKeys;
 
-use crate::{devices::DeviceId, errors::AppError, storage::traits::NotificationStore};
+use crate::{
+    devices::DeviceId,
+    errors::AppError,
+    notifications::{PushProviderKind, PushSubscription},
+    storage::{models::StoredSubscription, traits::NotificationStore},
+};
 
 pub struct PostgresNotificationStore {
     pool: PgPool,
@@ -18,57 +24,136 @@ impl NotificationStore for PostgresNotificationStore {
     async fn upsert_endpoint(
         &self,
         did: DeviceId,
-        endpoint: &SubscriptionInfo,
+        endpoint: &PushSubscription,
     ) -> Result<(), AppError> {
+        let keys = endpoint.keys.as_ref();
         sqlx::query!(
             r#"
-            INSERT INTO notifications (did, endpoint, p256dh, auth)
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT (did) 
+            INSERT INTO notifications (did, endpoint, p256dh, auth, provider, vapid_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (endpoint) 
             DO UPDATE SET
-                endpoint = EXCLUDED.endpoint, p256dh = EXCLUDED.p256dh, auth = EXCLUDED.auth
+                did = EXCLUDED.did, p256dh = EXCLUDED.p256dh, auth = EXCLUDED.auth,
+                provider = EXCLUDED.provider, vapid_key = EXCLUDED.vapid_key,
+                failure_count = 0, last_failure_at = NULL, retry_after = NULL
             "#,
             did.as_uuid(),
             endpoint.endpoint,
-            endpoint.keys.p256dh,
-            endpoint.keys.auth
+            keys.map(|keys| keys.p256dh.as_str()),
+            keys.map(|keys| keys.auth.as_str()),
+            endpoint.provider.as_str(),
+            endpoint.vapid_key,
         )
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
-    async fn retrive_endpoint(&self, did: DeviceId) -> Option<(SubscriptionInfo, DeviceId)> {
-        // let uuid_dids: Vec<_> = dids.iter().map(|d| d.as_uuid()).collect();
-        if let Some(row) = sqlx::query!(
+
+    async fn get_endpoints(&self, did: DeviceId) -> Result<Vec<StoredSubscription>, AppError> {
+        let rows = sqlx::query!(
             r#"
-            SELECT did, endpoint, p256dh, auth from notifications WHERE did = $1
+            SELECT endpoint, p256dh, auth, provider, vapid_key, failure_count, last_failure_at,
+                retry_after
+            FROM notifications WHERE did = $1 ORDER BY endpoint
             "#,
             did.as_uuid(),
         )
-        .fetch_optional(&self.pool)
-        .await
-        .ok()?
-        {
-            let endpoint: (SubscriptionInfo, DeviceId) = (
-                SubscriptionInfo {
-                    endpoint: row.endpoint,
-                    keys: SubscriptionKeys {
-                        p256dh: row.p256dh,
-                        auth: row.auth,
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let provider = PushProviderKind::parse(&row.provider).ok_or_else(|| {
+                    AppError::InternalError(anyhow::anyhow!(
+                        "Unknown push provider: {}",
+                        row.provider
+                    ))
+                })?;
+                let keys = match (row.p256dh, row.auth) {
+                    (Some(p256dh), Some(auth)) => Some(SubscriptionKeys { p256dh, auth }),
+                    _ => None,
+                };
+                Ok(StoredSubscription {
+                    subscription: PushSubscription {
+                        provider,
+                        endpoint: row.endpoint,
+                        keys,
+                        vapid_key: row.vapid_key,
                     },
-                },
-                DeviceId::new(row.did),
-            );
-
-            Some(endpoint)
-        } else {
-            None
-        }
+                    failure_count: row.failure_count,
+                    last_failure_at: row.last_failure_at,
+                    retry_after: row.retry_after,
+                })
+            })
+            .collect()
+    }
+
+    async fn assign_vapid_key(&self, public_key: &str) -> Result<u64, AppError> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE notifications SET vapid_key = $1
+            WHERE provider = 'webpush' AND vapid_key IS NULL
+            "#,
+            public_key,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn record_push_success(&self, endpoint: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE notifications
+            SET failure_count = 0, retry_after = NULL
+            WHERE endpoint = $1 AND failure_count > 0
+            "#,
+            endpoint,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
 
-    async fn delete_endpoint(&self, did: DeviceId) -> Result<(), AppError> {
+    async fn record_push_failure(
+        &self,
+        endpoint: &str,
+        retry_after: Option<OffsetDateTime>,
+    ) -> Result<(), AppError> {
         sqlx::query!(
+            r#"
+            UPDATE notifications
+            SET failure_count = failure_count + 1, last_failure_at = NOW(), retry_after = $2
+            WHERE endpoint = $1
+            "#,
+            endpoint,
+            retry_after,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_endpoint(&self, did: DeviceId, endpoint: &str) -> Result<bool, AppError> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM notifications WHERE did = $1 AND endpoint = $2
+            "#,
+            did.as_uuid(),
+            endpoint,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_endpoints(&self, did: DeviceId) -> Result<u64, AppError> {
+        let result = sqlx::query!(
             r#"
             DELETE FROM notifications WHERE did = $1
             "#,
@@ -77,6 +162,6 @@ impl NotificationStore for PostgresNotificationStore {
         .execute(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected())
     }
 }
@@ -0,0 +1,114 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    storage::{
+        models::{StoredAvatar, StoredProfile},
+        traits::ProfileStore,
+    },
+};
+
+pub struct PostgresProfileStore {
+    pool: PgPool,
+}
+
+impl PostgresProfileStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl ProfileStore for PostgresProfileStore {
+    async fn get_profile(&self, uid: &str) -> Result<Option<StoredProfile>, AppError> {
+        let profile = sqlx::query_as!(
+            StoredProfile,
+            r#"
+            SELECT u.display_name, u.summary, a.id AS "avatar_id?"
+            FROM users u
+            LEFT JOIN avatars a ON a.uid = u.uid
+            WHERE u.uid = $1
+            "#,
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(profile)
+    }
+
+    async fn update_profile(
+        &self,
+        uid: &str,
+        display_name: Option<&str>,
+        summary: Option<&str>,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            "UPDATE users SET display_name = $2, summary = $3 WHERE uid = $1",
+            uid,
+            display_name,
+            summary
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_avatar(
+        &self,
+        uid: &str,
+        content_type: &str,
+        image: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<Uuid, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM avatars WHERE uid = $1", uid)
+            .execute(&mut *tx)
+            .await?;
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO avatars (uid, content_type, image, thumbnail)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            uid,
+            content_type,
+            image,
+            thumbnail
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(row.id)
+    }
+
+    async fn delete_avatar(&self, uid: &str) -> Result<bool, AppError> {
+        let result = sqlx::query!("DELETE FROM avatars WHERE uid = $1", uid)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_avatar(&self, id: Uuid) -> Result<Option<StoredAvatar>, AppError> {
+        let avatar = sqlx::query_as!(
+            StoredAvatar,
+            r#"
+            SELECT id, content_type, image, thumbnail
+            FROM avatars
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(avatar)
+    }
+}
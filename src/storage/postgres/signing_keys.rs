@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use crate::{
+    errors::AppError,
+    storage::{models::StoredSigningKey, traits::SigningKeyStore},
+};
+
+pub struct PostgresSigningKeyStore {
+    pool: PgPool,
+}
+
+impl PostgresSigningKeyStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SigningKeyStore for PostgresSigningKeyStore {
+    async fn get_signing_keys(&self, purpose: &str) -> Result<Vec<StoredSigningKey>, AppError> {
+        let keys = sqlx::query_as!(
+            StoredSigningKey,
+            r#"
+            SELECT kid, purpose, algorithm, private_key, created_at
+            FROM signing_keys
+            WHERE purpose = $1
+            ORDER BY created_at ASC
+            "#,
+            purpose
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn insert_signing_key(&self, key: &StoredSigningKey) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO signing_keys (kid, purpose, algorithm, private_key, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            key.kid,
+            key.purpose,
+            key.algorithm,
+            key.private_key,
+            key.created_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_signing_key(&self, kid: &str) -> Result<(), AppError> {
+        sqlx::query!("DELETE FROM signing_keys WHERE kid = $1", kid)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream};
+use sqlx::{PgPool, postgres::PgListener};
+use time::OffsetDateTime;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{devices::DeviceId, errors::AppError, storage::traits::SocketRouteStore};
+
+/// NOTIFY payloads must be shorter than 8000 bytes. Larger ones are staged in a table and
+/// the notification carries a reference instead
+const MAX_NOTIFY_PAYLOAD: usize = 7000;
+const PAYLOAD_REF_PREFIX: &str = "ref:";
+
+pub struct PostgresSocketRouteStore {
+    pool: PgPool,
+}
+
+impl PostgresSocketRouteStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn channel(node_id: Uuid) -> String {
+    format!("eko_socket_{}", node_id.simple())
+}
+
+/// Resolves staged payloads. Returns `None` if the payload is gone
+async fn resolve_payload(pool: &PgPool, payload: String) -> Option<String> {
+    let Some(id) = payload.strip_prefix(PAYLOAD_REF_PREFIX) else {
+        return Some(payload);
+    };
+    let id = Uuid::parse_str(id).ok()?;
+    match sqlx::query!(
+        "DELETE FROM socket_relay_payloads WHERE id = $1 RETURNING payload",
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(row) => row.map(|r| r.payload),
+        Err(e) => {
+            error!("Failed to load relayed payload {}: {:?}", id, e);
+            None
+        }
+    }
+}
+
+#[async_trait]
+impl SocketRouteStore for PostgresSocketRouteStore {
+    async fn heartbeat_node(&self, node_id: Uuid) -> Result<bool, AppError> {
+        // xmax is only zero for freshly inserted rows
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO socket_nodes (node_id)
+            VALUES ($1)
+            ON CONFLICT (node_id) DO UPDATE SET last_seen = NOW()
+            RETURNING (xmax = 0) AS "inserted!"
+            "#,
+            node_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.inserted)
+    }
+
+    async fn delete_stale_nodes(&self, cutoff: OffsetDateTime) -> Result<u64, AppError> {
+        let result = sqlx::query!("DELETE FROM socket_nodes WHERE last_seen < $1", cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        // Staged payloads are deleted on receipt, anything older was never picked up
+        sqlx::query!(
+            "DELETE FROM socket_relay_payloads WHERE created_at < $1",
+            cutoff
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn set_route(&self, did: DeviceId, node_id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO socket_routes (did, node_id)
+            VALUES ($1, $2)
+            ON CONFLICT (did) DO UPDATE SET node_id = EXCLUDED.node_id, connected_at = NOW()
+            "#,
+            did.as_uuid(),
+            node_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_route(&self, did: DeviceId, node_id: Uuid) -> Result<(), AppError> {
+        sqlx::query!(
+            "DELETE FROM socket_routes WHERE did = $1 AND node_id = $2",
+            did.as_uuid(),
+            node_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_route(
+        &self,
+        did: DeviceId,
+        alive_since: OffsetDateTime,
+    ) -> Result<Option<Uuid>, AppError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT r.node_id
+            FROM socket_routes r
+            JOIN socket_nodes n ON n.node_id = r.node_id
+            WHERE r.did = $1 AND n.last_seen >= $2
+            "#,
+            did.as_uuid(),
+            alive_since
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.node_id))
+    }
+
+    async fn publish(&self, node_id: Uuid, payload: &str) -> Result<(), AppError> {
+        let payload = if payload.len() > MAX_NOTIFY_PAYLOAD {
+            let row = sqlx::query!(
+                "INSERT INTO socket_relay_payloads (payload) VALUES ($1) RETURNING id",
+                payload
+            )
+            .fetch_one(&self.pool)
+            .await?;
+            format!("{}{}", PAYLOAD_REF_PREFIX, row.id)
+        } else {
+            payload.to_string()
+        };
+
+        sqlx::query!("SELECT pg_notify($1, $2)", channel(node_id), payload)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, node_id: Uuid) -> Result<BoxStream<'static, String>, AppError> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(&channel(node_id)).await?;
+
+        let pool = self.pool.clone();
+        Ok(Box::pin(stream::unfold(
+            (listener, pool),
+            |(mut listener, pool)| async move {
+                loop {
+                    // The listener reconnects by itself, notifications sent meanwhile are lost
+                    match listener.recv().await {
+                        Ok(notification) => {
+                            let payload = notification.payload().to_string();
+                            if let Some(payload) = resolve_payload(&pool, payload).await {
+                                return Some((payload, (listener, pool)));
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Socket relay listener failed: {:?}", e);
+                            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            },
+        )))
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    devices::DeviceId,
+    errors::AppError,
+    storage::{models::StoredSocketTicket, traits::SocketTicketStore},
+};
+
+pub struct PostgresSocketTicketStore {
+    pool: PgPool,
+}
+
+impl PostgresSocketTicketStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SocketTicketStore for PostgresSocketTicketStore {
+    async fn insert_ticket(&self, ticket: &StoredSocketTicket) -> Result<(), AppError> {
+        sqlx::query!(
+            "INSERT INTO socket_tickets (jti, did, expires_at) VALUES ($1, $2, $3)",
+            ticket.jti,
+            ticket.did.as_uuid(),
+            ticket.expires_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_ticket(&self, jti: Uuid) -> Result<Option<StoredSocketTicket>, AppError> {
+        let ticket = sqlx::query!(
+            "DELETE FROM socket_tickets WHERE jti = $1 RETURNING jti, did, expires_at",
+            jti
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(ticket.map(|row| StoredSocketTicket {
+            jti: row.jti,
+            did: DeviceId::new(row.did),
+            expires_at: row.expires_at,
+        }))
+    }
+
+    async fn delete_expired_tickets(&self, now: OffsetDateTime) -> Result<u64, AppError> {
+        let result = sqlx::query!("DELETE FROM socket_tickets WHERE expires_at < $1", now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
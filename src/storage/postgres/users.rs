@@ -4,6 +4,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 pub struct PostgresUserStore {
     pool: PgPool,
@@ -106,8 +107,262 @@ impl UserStore for PostgresUserStore {
             oidc_sub
         )
         .execute(&self.pool)
+        .await
+        .map_err(user_conflict)?;
+
+        Ok(())
+    }
+
+    async fn create_local_user(
+        &self,
+        uid: &str,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            INSERT INTO users (uid, username, email, password_hash)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            uid,
+            username,
+            email,
+            password_hash
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(user_conflict)?;
+
+        Ok(())
+    }
+
+    async fn get_password_hash(&self, uid: &str) -> Result<Option<String>, AppError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT password_hash
+            FROM users
+            WHERE uid = $1
+            "#,
+            uid
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(|r| r.password_hash))
+    }
+
+    async fn set_password_hash(&self, uid: &str, password_hash: &str) -> Result<(), AppError> {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2
+            WHERE uid = $1
+            "#,
+            uid,
+            password_hash
+        )
+        .execute(&self.pool)
         .await?;
 
         Ok(())
     }
+
+    async fn create_password_reset_token(
+        &self,
+        uid: &str,
+        expires_at: time::OffsetDateTime,
+    ) -> Result<Uuid, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM password_reset_tokens WHERE uid = $1", uid)
+            .execute(&mut *tx)
+            .await?;
+
+        let token = sqlx::query!(
+            r#"
+            INSERT INTO password_reset_tokens (uid, expires_at)
+            VALUES ($1, $2)
+            RETURNING token
+            "#,
+            uid,
+            expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .token;
+
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    async fn consume_password_reset_token(&self, token: &Uuid) -> Result<Option<String>, AppError> {
+        let row = sqlx::query!(
+            r#"
+            DELETE FROM password_reset_tokens
+            WHERE token = $1
+            RETURNING uid, expires_at
+            "#,
+            token
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row
+            .filter(|r| r.expires_at > time::OffsetDateTime::now_utc())
+            .map(|r| r.uid))
+    }
+
+    async fn change_username(
+        &self,
+        uid: &str,
+        username: &str,
+        previous_expires_at: time::OffsetDateTime,
+    ) -> Result<Option<String>, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(previous) =
+            sqlx::query!("SELECT username FROM users WHERE uid = $1 FOR UPDATE", uid)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|r| r.username)
+        else {
+            return Ok(None);
+        };
+
+        // Taking back one of your own old names ends its grace period
+        sqlx::query!(
+            "DELETE FROM username_history WHERE username = $1 AND uid = $2",
+            username,
+            uid
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let updated = sqlx::query!(
+            "UPDATE users SET username = $1 WHERE uid = $2",
+            username,
+            uid
+        )
+        .execute(&mut *tx)
+        .await;
+        match updated {
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(AppError::BadRequest("Username already taken".to_string()));
+            }
+            result => result?,
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO username_history (username, uid, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (username)
+            DO UPDATE SET uid = EXCLUDED.uid, changed_at = NOW(), expires_at = EXCLUDED.expires_at
+            "#,
+            previous,
+            uid,
+            previous_expires_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(previous))
+    }
+
+    async fn get_uid_by_previous_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<String>, AppError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT uid
+            FROM username_history
+            WHERE username = $1 AND expires_at > NOW()
+            "#,
+            username
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.uid))
+    }
+
+    async fn get_previous_usernames(&self, uid: &str) -> Result<Vec<String>, AppError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT username
+            FROM username_history
+            WHERE uid = $1 AND expires_at > NOW()
+            ORDER BY changed_at DESC
+            "#,
+            uid
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.username).collect())
+    }
+
+    async fn delete_account(&self, uid: &str, actor_id: &str) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Revoke through the device chain so the devices table trigger cleans up after them
+        sqlx::query!(
+            r#"
+            INSERT INTO device_actions (is_add, did, uid)
+            SELECT FALSE, did, uid
+            FROM devices
+            WHERE uid = $1
+            "#,
+            uid
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM device_actions WHERE uid = $1", uid)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "DELETE FROM actor_contacts WHERE actor_id = $1 OR contact_id = $1",
+            actor_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM actors WHERE id = $1", actor_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO actor_tombstones (id) VALUES ($1) ON CONFLICT (id) DO NOTHING",
+            actor_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Group states, reset tokens and username history cascade
+        sqlx::query!("DELETE FROM users WHERE uid = $1", uid)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// A signup that lost a race for its username or email gets the error the availability check
+/// would have given it
+fn user_conflict(e: sqlx::Error) -> AppError {
+    match &e {
+        sqlx::Error::Database(db) if db.constraint() == Some("users_username_key") => {
+            AppError::BadRequest("Username already taken".to_string())
+        }
+        sqlx::Error::Database(db) if db.constraint() == Some("users_email_key") => {
+            AppError::BadRequest("User already exists".to_string())
+        }
+        _ => e.into(),
+    }
 }
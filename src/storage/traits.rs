@@ -3,9 +3,17 @@ use crate::{
     auth::handlers::DeviceRegistration,
     devices::DeviceId,
     errors::AppError,
-    storage::models::{RegisterDeviceResult, RotatedRefreshToken, StoredGroupState},
+    storage::models::{
+        RefreshTokenRotation, RegisterDeviceResult, StoredAuthState, StoredAvatar,
+        StoredDataExport, StoredDevice, StoredGroupState, StoredInboxActivity,
+        StoredPendingDelivery, StoredProfile, StoredSigningKey, StoredSocketTicket,
+        StoredSubscription,
+    },
 };
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 #[async_trait]
@@ -15,15 +23,34 @@ pub trait ActivityStore: Send + Sync {
     /// removed
     async fn inbox_activities(&self, did: DeviceId) -> Result<Vec<Activity>, AppError>;
 
+    /// Returns up to `limit` of the activities `inbox_activities` would, after sequence number
+    /// `after_seq` and without removing anything. Used by transports where the device
+    /// acknowledges what it received
+    async fn pending_activities(
+        &self,
+        did: DeviceId,
+        after_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<StoredInboxActivity>, AppError>;
+
+    /// Removes the device's delivery requests for activities it acknowledged. A `Create` is
+    /// only removed by a `Delivered`, so they are skipped. Returns the number removed
+    async fn acknowledge_deliveries(
+        &self,
+        activity_ids: &[String],
+        did: &DeviceId,
+    ) -> Result<u64, AppError>;
+
     /// Stores a create this should mark the message as needing delivery for all devices in the
-    async fn insert_create(&self, create: &Create) -> Result<(), AppError>;
+    /// Returns the sequence number of each device's delivery
+    async fn insert_create(&self, create: &Create) -> Result<HashMap<DeviceId, i64>, AppError>;
     /// Stores an Activity. If the activity is a deliver it will have a side affect of removing
-    /// related message entries.
+    /// related message entries. Returns the sequence number of each device's delivery
     async fn insert_non_create(
         &self,
         activity: &Activity,
         dids: &[DeviceId],
-    ) -> Result<(), AppError>;
+    ) -> Result<HashMap<DeviceId, i64>, AppError>;
 
     /// Deletes a delivery request for a specific activity and device.
     /// This will trigger cleanup of the activity and message entries if no other deliveries exist.
@@ -41,6 +68,12 @@ pub trait ActivityStore: Send + Sync {
 
     /// Checks if this is the first delivery for a given Create activity.
     async fn claim_first_delivery(&self, create_id: &str) -> Result<bool, AppError>;
+
+    /// Lists what is waiting in a device's inbox without consuming anything, oldest first
+    async fn pending_deliveries(
+        &self,
+        did: DeviceId,
+    ) -> Result<Vec<StoredPendingDelivery>, AppError>;
 }
 
 #[async_trait]
@@ -59,14 +92,33 @@ pub trait DeviceStore: Send + Sync {
         expires_at: time::OffsetDateTime,
     ) -> Result<RegisterDeviceResult, AppError>;
 
+    /// Replaces a refresh token with a new one from the same family. Presenting a token that
+    /// was already rotated revokes every token in its family.
     async fn rotate_refresh_token(
         &self,
         old_token: &Uuid,
         ip_address: &str,
         user_agent: &str,
-    ) -> Result<Option<RotatedRefreshToken>, AppError>;
+    ) -> Result<RefreshTokenRotation, AppError>;
 
-    async fn logout_device(&self, refresh_token: &Uuid) -> Result<(), AppError>;
+    /// Revokes the device the refresh token belongs to. Returns the device, or None if the
+    /// token is unknown
+    async fn logout_device(&self, refresh_token: &Uuid) -> Result<Option<DeviceId>, AppError>;
+
+    /// Lists the user's devices, oldest first
+    async fn list_devices(&self, uid: &str) -> Result<Vec<StoredDevice>, AppError>;
+
+    /// Appends a RevokeDevice action for one of the user's devices, which removes the device
+    /// and its tokens. Returns false if the user has no such device
+    async fn revoke_device(&self, uid: &str, did: DeviceId) -> Result<bool, AppError>;
+
+    /// Appends a RevokeDevice action for each of the user's devices but `keep`. Returns the
+    /// revoked devices
+    async fn revoke_devices(
+        &self,
+        uid: &str,
+        keep: Option<DeviceId>,
+    ) -> Result<Vec<DeviceId>, AppError>;
 
     async fn get_device_status(&self, did: DeviceId) -> Result<bool, AppError>;
 
@@ -88,20 +140,41 @@ pub trait ActorStore: Send + Sync {
 
     /// Returns true if the actor exists and is local
     async fn is_local_actor(&self, actor_id: &str) -> Result<bool, AppError>;
+
+    /// Records that `actor_id` sent something to `contact_id`
+    async fn record_contact(&self, actor_id: &str, contact_id: &str) -> Result<(), AppError>;
+
+    /// Actors that have exchanged messages with `actor_id` in either direction
+    async fn get_contacts(&self, actor_id: &str) -> Result<Vec<String>, AppError>;
+
+    /// Returns true if the actor belonged to an account that has been deleted
+    async fn is_tombstoned(&self, actor_id: &str) -> Result<bool, AppError>;
 }
 
 #[async_trait]
 pub trait NotificationStore: Send + Sync {
+    /// Adds a subscription to the device's, or updates it. An endpoint registered by another
+    /// device before moves to this one
     async fn upsert_endpoint(
         &self,
         did: DeviceId,
-        endpoint: &web_push::SubscriptionInfo,
+        endpoint: &crate::notifications::PushSubscription,
     ) -> Result<(), AppError>;
-    async fn delete_endpoint(&self, did: DeviceId) -> Result<(), AppError>;
-    async fn retrive_endpoint(
+    /// Records `public_key` as the VAPID key of Web Push subscriptions that have none
+    async fn assign_vapid_key(&self, public_key: &str) -> Result<u64, AppError>;
+    /// Removes one of the device's subscriptions. Returns false if it has no such endpoint
+    async fn delete_endpoint(&self, did: DeviceId, endpoint: &str) -> Result<bool, AppError>;
+    /// Removes all of the device's subscriptions and returns how many there were
+    async fn delete_endpoints(&self, did: DeviceId) -> Result<u64, AppError>;
+    async fn get_endpoints(&self, did: DeviceId) -> Result<Vec<StoredSubscription>, AppError>;
+    /// Clears the failures of an endpoint a push went through to
+    async fn record_push_success(&self, endpoint: &str) -> Result<(), AppError>;
+    /// Counts a failed push, holding off further pushes until `retry_after` if given
+    async fn record_push_failure(
         &self,
-        dids: DeviceId,
-    ) -> Option<(web_push::SubscriptionInfo, DeviceId)>;
+        endpoint: &str,
+        retry_after: Option<OffsetDateTime>,
+    ) -> Result<(), AppError>;
 }
 
 #[async_trait]
@@ -135,6 +208,53 @@ pub trait UserStore: Send + Sync {
         oidc_issuer: &str,
         oidc_sub: &str,
     ) -> Result<(), AppError>;
+
+    /// Creates a user that authenticates with a locally stored password hash
+    async fn create_local_user(
+        &self,
+        uid: &str,
+        username: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Result<(), AppError>;
+
+    /// Returns the stored password hash, or None if the user has no local password
+    async fn get_password_hash(&self, uid: &str) -> Result<Option<String>, AppError>;
+
+    async fn set_password_hash(&self, uid: &str, password_hash: &str) -> Result<(), AppError>;
+
+    /// Issues a password reset token, replacing any outstanding tokens for the user
+    async fn create_password_reset_token(
+        &self,
+        uid: &str,
+        expires_at: time::OffsetDateTime,
+    ) -> Result<Uuid, AppError>;
+
+    /// Consumes a password reset token. Returns the uid it was issued for if the token
+    /// exists and has not expired
+    async fn consume_password_reset_token(&self, token: &Uuid) -> Result<Option<String>, AppError>;
+
+    /// Renames the user, keeping the old name reserved for them until `previous_expires_at`.
+    /// Returns the old name, or None if the user does not exist
+    async fn change_username(
+        &self,
+        uid: &str,
+        username: &str,
+        previous_expires_at: time::OffsetDateTime,
+    ) -> Result<Option<String>, AppError>;
+
+    /// Returns the uid that gave up `username`, if it is still within its grace period
+    async fn get_uid_by_previous_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<String>, AppError>;
+
+    /// Names the user gave up that are still within their grace period, newest first
+    async fn get_previous_usernames(&self, uid: &str) -> Result<Vec<String>, AppError>;
+
+    /// Revokes every device of the user and deletes everything stored about them, leaving a
+    /// tombstone for `actor_id`. Keys, push endpoints and pending deliveries go with the devices
+    async fn delete_account(&self, uid: &str, actor_id: &str) -> Result<(), AppError>;
 }
 
 #[async_trait]
@@ -156,3 +276,117 @@ pub trait GroupStore: Send + Sync {
     /// Delete an encrypted group state. Returns true if a row was deleted.
     async fn delete_group_state(&self, user_id: &str, group_id: &Uuid) -> Result<bool, AppError>;
 }
+
+#[async_trait]
+pub trait SigningKeyStore: Send + Sync {
+    /// Returns every key for `purpose`, oldest first
+    async fn get_signing_keys(&self, purpose: &str) -> Result<Vec<StoredSigningKey>, AppError>;
+
+    async fn insert_signing_key(&self, key: &StoredSigningKey) -> Result<(), AppError>;
+
+    async fn delete_signing_key(&self, kid: &str) -> Result<(), AppError>;
+}
+
+#[async_trait]
+pub trait AuthStateStore: Send + Sync {
+    async fn insert_auth_state(&self, state: &StoredAuthState) -> Result<(), AppError>;
+
+    /// Removes and returns the state for `csrf_token`, so each state can only be used once.
+    /// Expired states are returned as well, callers check `expires_at`
+    async fn take_auth_state(&self, csrf_token: &str) -> Result<Option<StoredAuthState>, AppError>;
+
+    /// Deletes states that expired before `now`. Returns the number deleted
+    async fn delete_expired_auth_states(&self, now: OffsetDateTime) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait SocketTicketStore: Send + Sync {
+    async fn insert_ticket(&self, ticket: &StoredSocketTicket) -> Result<(), AppError>;
+
+    /// Removes and returns the ticket, so each ticket can only be used once. Expired tickets
+    /// are returned as well, callers check `expires_at`
+    async fn take_ticket(&self, jti: Uuid) -> Result<Option<StoredSocketTicket>, AppError>;
+
+    /// Deletes tickets that expired before `now`. Returns the number deleted
+    async fn delete_expired_tickets(&self, now: OffsetDateTime) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait DataExportStore: Send + Sync {
+    /// Records a pending export, returning its id
+    async fn create_export(&self, uid: &str, expires_at: OffsetDateTime) -> Result<Uuid, AppError>;
+
+    async fn complete_export(&self, id: Uuid, archive: &serde_json::Value) -> Result<(), AppError>;
+
+    async fn fail_export(&self, id: Uuid) -> Result<(), AppError>;
+
+    async fn get_export(&self, uid: &str, id: Uuid) -> Result<Option<StoredDataExport>, AppError>;
+
+    /// Returns the archive of a ready, unexpired export and discards it, so it can only be
+    /// downloaded once
+    async fn take_export_archive(
+        &self,
+        uid: &str,
+        id: Uuid,
+    ) -> Result<Option<serde_json::Value>, AppError>;
+
+    /// Deletes exports that expired before `now`. Returns the number deleted
+    async fn delete_expired_exports(&self, now: OffsetDateTime) -> Result<u64, AppError>;
+}
+
+#[async_trait]
+pub trait ProfileStore: Send + Sync {
+    /// Returns `None` if the user does not exist
+    async fn get_profile(&self, uid: &str) -> Result<Option<StoredProfile>, AppError>;
+
+    async fn update_profile(
+        &self,
+        uid: &str,
+        display_name: Option<&str>,
+        summary: Option<&str>,
+    ) -> Result<(), AppError>;
+
+    /// Replaces the user's avatar, returning the id of the new one
+    async fn set_avatar(
+        &self,
+        uid: &str,
+        content_type: &str,
+        image: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<Uuid, AppError>;
+
+    /// Returns false if the user had no avatar
+    async fn delete_avatar(&self, uid: &str) -> Result<bool, AppError>;
+
+    async fn get_avatar(&self, id: Uuid) -> Result<Option<StoredAvatar>, AppError>;
+}
+
+/// Which node holds each connected device's socket, so nodes can hand deliveries to each other
+#[async_trait]
+pub trait SocketRouteStore: Send + Sync {
+    /// Records that the node is alive. Returns true if the node was not registered, such as
+    /// after it was considered dead and its routes dropped
+    async fn heartbeat_node(&self, node_id: Uuid) -> Result<bool, AppError>;
+
+    /// Forgets nodes that have not heartbeated since `cutoff`, with their routes. Returns the
+    /// number of nodes deleted
+    async fn delete_stale_nodes(&self, cutoff: OffsetDateTime) -> Result<u64, AppError>;
+
+    async fn set_route(&self, did: DeviceId, node_id: Uuid) -> Result<(), AppError>;
+
+    /// Removes the route only while it still points at `node_id`
+    async fn delete_route(&self, did: DeviceId, node_id: Uuid) -> Result<(), AppError>;
+
+    /// The node holding the device's socket, if that node has heartbeated since `alive_since`
+    async fn get_route(
+        &self,
+        did: DeviceId,
+        alive_since: OffsetDateTime,
+    ) -> Result<Option<Uuid>, AppError>;
+
+    /// Sends `payload` to the node's subscription
+    async fn publish(&self, node_id: Uuid, payload: &str) -> Result<(), AppError>;
+
+    /// Payloads published to `node_id`, for as long as the stream is polled
+    async fn subscribe(&self, node_id: Uuid) -> Result<BoxStream<'static, String>, AppError>;
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    activitypub::actor_url,
+    auth::jwt::EXPORT_DOWNLOAD_LIFESPAN,
+    errors::AppError,
+    storage::{Storage, models::StoredDataExport},
+    users::ProfileService,
+};
+
+/// How long a finished export can be downloaded before it is deleted
+pub const EXPORT_RETENTION: time::Duration = time::Duration::days(7);
+const EXPORT_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Claims of the token in an export's download URL
+#[derive(Debug, Serialize, Deserialize)]
+struct DownloadClaims {
+    sub: String,
+    export: Uuid,
+    exp: usize,
+    iat: usize,
+}
+
+/// Service for exporting everything the server stores about a user
+pub struct ExportService;
+
+impl ExportService {
+    /// Records the export and builds the archive in the background. Returns the export's id
+    pub async fn request_export(state: &AppState, uid: &str) -> Result<Uuid, AppError> {
+        let expires_at = OffsetDateTime::now_utc() + EXPORT_RETENTION;
+        let id = state.storage.exports.create_export(uid, expires_at).await?;
+
+        info!("Started data export {} for {}", id, uid);
+
+        let state = state.clone();
+        let uid = uid.to_string();
+        tokio::spawn(async move {
+            let result = match Self::build_archive(&state, &uid).await {
+                Ok(archive) => state.storage.exports.complete_export(id, &archive).await,
+                Err(e) => {
+                    error!("Failed to build export {} for {}: {:?}", id, uid, e);
+                    state.storage.exports.fail_export(id).await
+                }
+            };
+            if let Err(e) = result {
+                error!("Failed to store export {} for {}: {:?}", id, uid, e);
+            }
+        });
+
+        Ok(id)
+    }
+
+    pub async fn get_export(
+        state: &AppState,
+        uid: &str,
+        id: Uuid,
+    ) -> Result<StoredDataExport, AppError> {
+        state
+            .storage
+            .exports
+            .get_export(uid, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Export not found".to_string()))
+    }
+
+    /// Signs a short lived URL the archive can be downloaded from without other credentials
+    pub fn download_url(state: &AppState, uid: &str, id: Uuid) -> Result<String, AppError> {
+        let now = OffsetDateTime::now_utc();
+        let claims = DownloadClaims {
+            sub: uid.to_string(),
+            export: id,
+            exp: (now + EXPORT_DOWNLOAD_LIFESPAN).unix_timestamp() as usize,
+            iat: now.unix_timestamp() as usize,
+        };
+        let token = state.export_jwt.sign(&claims).map_err(|e| {
+            AppError::InternalError(anyhow::anyhow!("Failed to create token: {}", e))
+        })?;
+
+        Ok(format!(
+            "{}/exports/{}/download?token={}",
+            state.domain, id, token
+        ))
+    }
+
+    /// Returns the archive if the token was signed for this export. Each archive can only be
+    /// downloaded once
+    pub async fn take_archive(state: &AppState, id: Uuid, token: &str) -> Result<Value, AppError> {
+        let claims = state
+            .export_jwt
+            .verify::<DownloadClaims>(token)
+            .map_err(|e| AppError::Unauthorized(format!("Invalid download token: {}", e)))?
+            .claims;
+        if claims.export != id {
+            return Err(AppError::Unauthorized(
+                "Download token is for another export".to_string(),
+            ));
+        }
+
+        state
+            .storage
+            .exports
+            .take_export_archive(&claims.sub, id)
+            .await?
+            .ok_or_else(|| AppError::Gone("Export is no longer available".to_string()))
+    }
+
+    async fn build_archive(state: &AppState, uid: &str) -> Result<Value, AppError> {
+        let storage = &state.storage;
+        let user = storage
+            .users
+            .get_user_by_uid(uid)
+            .await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+        let actor = ProfileService::person(state, uid).await?;
+        let previous_usernames = storage.users.get_previous_usernames(uid).await?;
+        let device_chain = storage.devices.device_actions_for_user(uid).await?;
+        let contacts = storage
+            .actors
+            .get_contacts(&actor_url(&state.domain, uid))
+            .await?;
+        let group_states = storage.groups.get_all_group_states(uid).await?;
+
+        let mut devices = Vec::new();
+        for device in storage.devices.list_devices(uid).await? {
+            let push_endpoints: Vec<String> = storage
+                .notifications
+                .get_endpoints(device.did)
+                .await?
+                .into_iter()
+                .map(|stored| stored.subscription.endpoint)
+                .collect();
+            let pending_inbox = storage.activities.pending_deliveries(device.did).await?;
+            devices.push(json!({
+                "id": device.did.to_url(&state.domain),
+                "deviceName": device.device_name,
+                "approved": device.is_approved,
+                "createdAt": rfc3339(device.created_at)?,
+                "lastIpAddress": device.last_ip_address,
+                "lastUserAgent": device.last_user_agent,
+                "lastRefreshedAt": device.last_refreshed_at.map(rfc3339).transpose()?,
+                "pushEndpoints": push_endpoints,
+                "pendingInbox": pending_inbox,
+            }));
+        }
+
+        Ok(json!({
+            "exportedAt": rfc3339(OffsetDateTime::now_utc())?,
+            "profile": {
+                "uid": user.uid,
+                "username": user.username,
+                "email": user.email,
+                "oidcIssuer": user.oidc_issuer,
+                "createdAt": rfc3339(user.created_at)?,
+                "previousUsernames": previous_usernames,
+                "actor": actor,
+            },
+            "deviceChain": device_chain,
+            "devices": devices,
+            "groupStates": group_states,
+            "contacts": contacts,
+        }))
+    }
+
+    /// Periodically deletes exports past their retention
+    pub fn spawn_sweep(storage: Arc<Storage>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPORT_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                match storage
+                    .exports
+                    .delete_expired_exports(OffsetDateTime::now_utc())
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(n) => info!("Deleted {} expired data exports", n),
+                    Err(e) => error!("Failed to delete expired data exports: {:?}", e),
+                }
+            }
+        });
+    }
+}
+
+fn rfc3339(t: OffsetDateTime) -> Result<String, AppError> {
+    Ok(t.format(&time::format_description::well_known::Rfc3339)?)
+}
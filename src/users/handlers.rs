@@ -0,0 +1,286 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::Bytes,
+    debug_handler,
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    activitypub::Person,
+    auth::Claims,
+    errors::AppError,
+    users::{ExportService, ProfileService, UserService},
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeUsernameRequest {
+    pub username: String,
+}
+
+/// The user confirms deletion with their password, or with the verification token of a fresh
+/// OIDC sign-in
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAccountRequest {
+    /// Defaults to the email stored for the user
+    pub email: Option<String>,
+    pub password: Option<String>,
+    #[cfg_attr(not(feature = "auth-oidc"), allow(dead_code))]
+    pub verification_token: Option<String>,
+}
+
+/// Replaces both fields. Omitted or blank fields are cleared
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileRequest {
+    pub name: Option<String>,
+    pub summary: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportStatusResponse {
+    pub id: Uuid,
+    /// `pending`, `ready`, `failed` or `downloaded`
+    pub status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub completed_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    /// Signed, single use link to the archive. Only present while it can be downloaded
+    pub download_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadExportQuery {
+    pub token: String,
+}
+
+/// PUT /users/{uid}/username
+#[debug_handler]
+pub async fn change_username_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<ChangeUsernameRequest>,
+) -> Result<Json<Person>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot rename another user".to_string(),
+        ));
+    }
+
+    let person = UserService::change_username(&state, &uid, &req.username).await?;
+    Ok(Json(person))
+}
+
+/// DELETE /users/{uid}
+#[debug_handler]
+pub async fn delete_account_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<StatusCode, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot delete another user's account".to_string(),
+        ));
+    }
+
+    UserService::delete_account(&state, &uid, req).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /users/{uid}/exports
+#[debug_handler]
+pub async fn request_export_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<(StatusCode, Json<ExportStatusResponse>), AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot export another user's data".to_string(),
+        ));
+    }
+
+    let id = ExportService::request_export(&state, &uid).await?;
+    let export = ExportService::get_export(&state, &uid, id).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(ExportStatusResponse {
+            id,
+            status: export.status,
+            created_at: export.created_at,
+            completed_at: export.completed_at,
+            expires_at: export.expires_at,
+            download_url: None,
+        }),
+    ))
+}
+
+/// GET /users/{uid}/exports/{id}
+#[debug_handler]
+pub async fn get_export_handler(
+    State(state): State<AppState>,
+    Path((uid, id)): Path<(String, Uuid)>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<Json<ExportStatusResponse>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot view another user's exports".to_string(),
+        ));
+    }
+
+    let export = ExportService::get_export(&state, &uid, id).await?;
+    let downloadable = export.status == "ready"
+        && export.downloaded_at.is_none()
+        && export.expires_at > OffsetDateTime::now_utc();
+    let download_url = if downloadable {
+        Some(ExportService::download_url(&state, &uid, id)?)
+    } else {
+        None
+    };
+    let status = if export.downloaded_at.is_some() {
+        "downloaded".to_string()
+    } else {
+        export.status
+    };
+
+    Ok(Json(ExportStatusResponse {
+        id,
+        status,
+        created_at: export.created_at,
+        completed_at: export.completed_at,
+        expires_at: export.expires_at,
+        download_url,
+    }))
+}
+
+/// GET /exports/{id}/download?token=
+///
+/// Authenticated by the signed token in the URL rather than a bearer token, so the link can
+/// be opened in a browser
+#[debug_handler]
+pub async fn download_export_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DownloadExportQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let archive: Value = ExportService::take_archive(&state, id, &query.token).await?;
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"eko-export-{}.json\"", id),
+        )],
+        Json(archive),
+    ))
+}
+
+/// PUT /users/{uid}/profile
+#[debug_handler]
+pub async fn update_profile_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Json(req): Json<UpdateProfileRequest>,
+) -> Result<Json<Person>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot edit another user's profile".to_string(),
+        ));
+    }
+
+    let person = ProfileService::update_profile(&state, &uid, req.name, req.summary).await?;
+    Ok(Json(person))
+}
+
+/// PUT /users/{uid}/avatar
+///
+/// The body is the image itself, described by the Content-Type header
+#[debug_handler]
+pub async fn update_avatar_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Person>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot edit another user's profile".to_string(),
+        ));
+    }
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let person = ProfileService::set_avatar(&state, &uid, content_type, body.to_vec()).await?;
+    Ok(Json(person))
+}
+
+/// DELETE /users/{uid}/avatar
+#[debug_handler]
+pub async fn delete_avatar_handler(
+    State(state): State<AppState>,
+    Path(uid): Path<String>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<Json<Person>, AppError> {
+    if claims.sub != uid {
+        return Err(AppError::Forbidden(
+            "Cannot edit another user's profile".to_string(),
+        ));
+    }
+
+    let person = ProfileService::delete_avatar(&state, &uid).await?;
+    Ok(Json(person))
+}
+
+/// Avatar ids change with every upload, so responses never go stale
+const AVATAR_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// GET /avatars/{id}
+#[debug_handler]
+pub async fn get_avatar_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let avatar = ProfileService::get_avatar(&state, id).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, avatar.content_type),
+            (header::CACHE_CONTROL, AVATAR_CACHE_CONTROL.to_string()),
+        ],
+        avatar.image,
+    ))
+}
+
+/// GET /avatars/{id}/thumbnail
+#[debug_handler]
+pub async fn get_avatar_thumbnail_handler(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let avatar = ProfileService::get_avatar(&state, id).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, avatar.content_type),
+            (header::CACHE_CONTROL, AVATAR_CACHE_CONTROL.to_string()),
+        ],
+        avatar.thumbnail,
+    ))
+}
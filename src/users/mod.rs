@@ -0,0 +1,13 @@
+pub mod export;
+pub mod handlers;
+pub mod profile;
+pub mod service;
+
+pub use export::ExportService;
+pub use handlers::{
+    change_username_handler, delete_account_handler, delete_avatar_handler,
+    download_export_handler, get_avatar_handler, get_avatar_thumbnail_handler, get_export_handler,
+    request_export_handler, update_avatar_handler, update_profile_handler,
+};
+pub use profile::ProfileService;
+pub use service::UserService;
@@ -0,0 +1,172 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageReader, Limits, imageops::FilterType};
+
+use crate::{
+    AppState, activitypub::Person, errors::AppError, storage::models::StoredAvatar,
+    users::UserService,
+};
+
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+pub const MAX_SUMMARY_LENGTH: usize = 500;
+pub const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+/// Larger images are rejected before they are decoded
+pub const MAX_AVATAR_DIMENSION: u32 = 4096;
+/// Avatars are cropped to a square of at most this many pixels
+pub const AVATAR_SIZE: u32 = 512;
+pub const THUMBNAIL_SIZE: u32 = 96;
+
+/// Service for the profile shown on a user's actor
+pub struct ProfileService;
+
+impl ProfileService {
+    /// The user's actor, with the profile they set
+    pub async fn person(state: &AppState, uid: &str) -> Result<Person, AppError> {
+        let mut person = state.auth.provider.person_from_uid(uid).await?;
+        if let Some(profile) = state.storage.profiles.get_profile(uid).await? {
+            person.name = profile.display_name.or(person.name);
+            person.summary = profile.summary.or(person.summary);
+            if let Some(id) = profile.avatar_id {
+                person.profile_picture = Some(format!("{}/avatars/{}", state.domain, id));
+            }
+        }
+        Ok(person)
+    }
+
+    /// Replaces the display name and summary. Blank values clear the field
+    pub async fn update_profile(
+        state: &AppState,
+        uid: &str,
+        display_name: Option<String>,
+        summary: Option<String>,
+    ) -> Result<Person, AppError> {
+        let display_name = normalize(display_name, "Display name", MAX_DISPLAY_NAME_LENGTH)?;
+        let summary = normalize(summary, "Summary", MAX_SUMMARY_LENGTH)?;
+
+        state
+            .storage
+            .profiles
+            .update_profile(uid, display_name.as_deref(), summary.as_deref())
+            .await?;
+
+        UserService::broadcast_profile_update(state, uid).await
+    }
+
+    pub async fn set_avatar(
+        state: &AppState,
+        uid: &str,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<Person, AppError> {
+        if bytes.len() > MAX_AVATAR_BYTES {
+            return Err(AppError::BadRequest(format!(
+                "Avatars must be at most {} bytes",
+                MAX_AVATAR_BYTES
+            )));
+        }
+        let format = match content_type {
+            "image/png" => ImageFormat::Png,
+            "image/jpeg" => ImageFormat::Jpeg,
+            "image/webp" => ImageFormat::WebP,
+            _ => {
+                return Err(AppError::BadRequest(
+                    "Avatars must be PNG, JPEG or WebP images".to_string(),
+                ));
+            }
+        };
+
+        // Decoding and resizing are CPU bound, keep them off the async workers
+        let (content_type, image, thumbnail) =
+            tokio::task::spawn_blocking(move || process_avatar(format, &bytes)).await??;
+
+        state
+            .storage
+            .profiles
+            .set_avatar(uid, content_type, &image, &thumbnail)
+            .await?;
+
+        UserService::broadcast_profile_update(state, uid).await
+    }
+
+    pub async fn delete_avatar(state: &AppState, uid: &str) -> Result<Person, AppError> {
+        if !state.storage.profiles.delete_avatar(uid).await? {
+            return Err(AppError::NotFound("No avatar to delete".to_string()));
+        }
+
+        UserService::broadcast_profile_update(state, uid).await
+    }
+
+    pub async fn get_avatar(state: &AppState, id: uuid::Uuid) -> Result<StoredAvatar, AppError> {
+        state
+            .storage
+            .profiles
+            .get_avatar(id)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Avatar not found".to_string()))
+    }
+}
+
+fn normalize(
+    value: Option<String>,
+    field: &str,
+    max_length: usize,
+) -> Result<Option<String>, AppError> {
+    let Some(value) = value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(None);
+    };
+    if value.chars().count() > max_length {
+        return Err(AppError::BadRequest(format!(
+            "{} must be at most {} characters",
+            field, max_length
+        )));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\n') {
+        return Err(AppError::BadRequest(format!(
+            "{} contains invalid characters",
+            field
+        )));
+    }
+    Ok(Some(value))
+}
+
+/// Crops the image to a square and re-encodes it, which also drops any metadata such as
+/// location. JPEGs stay JPEG, everything else becomes PNG
+fn process_avatar(
+    format: ImageFormat,
+    bytes: &[u8],
+) -> Result<(&'static str, Vec<u8>, Vec<u8>), AppError> {
+    let invalid = |e: image::ImageError| AppError::BadRequest(format!("Invalid image: {}", e));
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_AVATAR_DIMENSION);
+    limits.max_image_height = Some(MAX_AVATAR_DIMENSION);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let decoded = reader.decode().map_err(invalid)?;
+
+    let size = AVATAR_SIZE.min(decoded.width()).min(decoded.height());
+    let avatar = decoded.resize_to_fill(size, size, FilterType::Lanczos3);
+    let thumbnail = avatar.resize_to_fill(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Lanczos3);
+
+    let (content_type, output_format) = match format {
+        ImageFormat::Jpeg => ("image/jpeg", ImageFormat::Jpeg),
+        _ => ("image/png", ImageFormat::Png),
+    };
+    let encode = |image: image::DynamicImage| -> Result<Vec<u8>, AppError> {
+        // JPEG has no alpha channel
+        let image = match output_format {
+            ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(image.to_rgb8()),
+            _ => image,
+        };
+        let mut out = Cursor::new(Vec::new());
+        image.write_to(&mut out, output_format).map_err(|e| {
+            AppError::InternalError(anyhow::anyhow!("Failed to encode avatar: {}", e))
+        })?;
+        Ok(out.into_inner())
+    };
+
+    Ok((content_type, encode(avatar)?, encode(thumbnail)?))
+}
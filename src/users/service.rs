@@ -0,0 +1,183 @@
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    activitypub::{
+        Activity, Delete, Person, Update, actor_url, types::actor::default_context_value,
+    },
+    auth::validate_username,
+    errors::AppError,
+    messaging::MessagingService,
+    users::{ProfileService, handlers::DeleteAccountRequest},
+};
+
+/// How long a previous username keeps resolving to its old owner
+pub const USERNAME_GRACE_PERIOD: time::Duration = time::Duration::days(30);
+
+/// Service for changes a user makes to their own account
+pub struct UserService;
+
+impl UserService {
+    /// Rename the user and tell their contacts. The old name redirects to the new one for
+    /// `USERNAME_GRACE_PERIOD`
+    pub async fn change_username(
+        state: &AppState,
+        uid: &str,
+        username: &str,
+    ) -> Result<Person, AppError> {
+        validate_username(username)?;
+        if !state.auth.provider.owns_username(uid).await? {
+            return Err(AppError::BadRequest(
+                "This account's username is managed by its identity provider".to_string(),
+            ));
+        }
+
+        let users = &state.storage.users;
+        if let Some(owner) = users.get_user_by_username(username).await? {
+            return Err(AppError::BadRequest(if owner.uid == uid {
+                "That is already your username".to_string()
+            } else {
+                "Username already taken".to_string()
+            }));
+        }
+        if users
+            .get_uid_by_previous_username(username)
+            .await?
+            .is_some_and(|owner| owner != uid)
+        {
+            return Err(AppError::BadRequest("Username already taken".to_string()));
+        }
+
+        let expires_at = time::OffsetDateTime::now_utc() + USERNAME_GRACE_PERIOD;
+        if users
+            .change_username(uid, username, expires_at)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::NotFound("User not found".to_string()));
+        }
+
+        Self::broadcast_profile_update(state, uid).await
+    }
+
+    /// Sends an `Update` of the user's actor to everyone they have messaged, and to their own
+    /// devices, so cached copies are refreshed. Returns the updated actor
+    pub async fn broadcast_profile_update(state: &AppState, uid: &str) -> Result<Person, AppError> {
+        let person = ProfileService::person(state, uid).await?;
+        let actor = actor_url(&state.domain, uid);
+
+        let mut recipients = state.storage.actors.get_contacts(&actor).await?;
+        recipients.push(actor.clone());
+
+        for to in recipients {
+            let update = Activity::Update(Update {
+                context: default_context_value(),
+                id: Some(format!("{}/activities/{}", state.domain, Uuid::new_v4())),
+                actor: actor.clone(),
+                to,
+                object: person.clone(),
+            });
+            if let Err(e) = MessagingService::process_server_activity(state, &update).await {
+                warn!(
+                    "Failed to send profile update to {}: {:?}",
+                    update.as_base().to(),
+                    e
+                );
+            }
+        }
+
+        Ok(person)
+    }
+
+    /// Checks that the request carries fresh credentials for `uid`
+    async fn reauthenticate(
+        state: &AppState,
+        uid: &str,
+        req: DeleteAccountRequest,
+    ) -> Result<(), AppError> {
+        let verified_uid = match (req.password, req.verification_token) {
+            (Some(password), _) => {
+                let email = match req.email {
+                    Some(email) => email,
+                    None => state
+                        .storage
+                        .users
+                        .get_user_by_uid(uid)
+                        .await?
+                        .map(|user| user.email)
+                        .ok_or_else(|| AppError::BadRequest("Email is required".to_string()))?,
+                };
+                let (_, verified_uid) = state
+                    .auth
+                    .provider
+                    .login_with_email(email, password)
+                    .await?;
+                verified_uid
+            }
+            #[cfg(feature = "auth-oidc")]
+            (None, Some(token)) => {
+                let oidc = state
+                    .oidc_provider
+                    .as_ref()
+                    .ok_or_else(|| AppError::BadRequest("OIDC is not configured".to_string()))?;
+                oidc.verify_verification_token(&token)?
+                    .uid
+                    .ok_or_else(|| AppError::Unauthorized("Unknown OIDC identity".to_string()))?
+            }
+            _ => {
+                return Err(AppError::BadRequest(
+                    "Confirm with a password or a verification token".to_string(),
+                ));
+            }
+        };
+
+        if verified_uid != uid {
+            return Err(AppError::Unauthorized(
+                "Credentials do not belong to this account".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Deletes the account after re-authenticating the user, then tells everyone they have
+    /// messaged that the actor is gone
+    pub async fn delete_account(
+        state: &AppState,
+        uid: &str,
+        req: DeleteAccountRequest,
+    ) -> Result<(), AppError> {
+        Self::reauthenticate(state, uid, req).await?;
+
+        let actor = actor_url(&state.domain, uid);
+        let devices = state.storage.devices.list_devices(uid).await?;
+        let contacts = state.storage.actors.get_contacts(&actor).await?;
+
+        state.storage.users.delete_account(uid, &actor).await?;
+        for device in devices {
+            state
+                .sockets
+                .disconnect(&device.did, "Account deleted")
+                .await;
+        }
+        info!("Deleted account {}", uid);
+
+        for to in contacts {
+            let delete = Activity::Delete(Delete {
+                context: default_context_value(),
+                id: Some(format!("{}/activities/{}", state.domain, Uuid::new_v4())),
+                actor: actor.clone(),
+                to,
+                object: actor.clone(),
+            });
+            if let Err(e) = MessagingService::process_server_activity(state, &delete).await {
+                warn!(
+                    "Failed to send account deletion to {}: {:?}",
+                    delete.as_base().to(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{Claims, jwt::SOCKET_TICKET_LIFESPAN},
+    devices::DeviceId,
+    errors::AppError,
+    storage::models::StoredSocketTicket,
+};
+
+/// Claims of a ticket a socket can be opened with instead of an `Authorization` header
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketClaims {
+    sub: String,
+    did: DeviceId,
+    roles: Vec<String>,
+    exp: usize,
+    iat: usize,
+    /// Expiry of the access token the ticket was issued for. The socket lives until then
+    session_exp: usize,
+    /// Recorded in the ticket store until the ticket is used
+    jti: Uuid,
+}
+
+/// Issues a short lived, single use ticket for opening a socket as the device of `claims`.
+/// Tickets travel in URLs, which end up in logs, so a copy must not open another socket
+pub async fn issue_ticket(state: &AppState, claims: &Claims) -> Result<(String, usize), AppError> {
+    let now = OffsetDateTime::now_utc();
+    let expires_at = now + SOCKET_TICKET_LIFESPAN;
+    let exp = expires_at.unix_timestamp() as usize;
+    let jti = Uuid::new_v4();
+    state
+        .storage
+        .socket_tickets
+        .insert_ticket(&StoredSocketTicket {
+            jti,
+            did: claims.did,
+            expires_at,
+        })
+        .await?;
+    let ticket = TicketClaims {
+        sub: claims.sub.clone(),
+        did: claims.did,
+        roles: claims.roles.clone(),
+        exp,
+        iat: now.unix_timestamp() as usize,
+        session_exp: claims.exp,
+        jti,
+    };
+    let ticket = state
+        .socket_ticket_jwt
+        .sign(&ticket)
+        .map_err(|e| AppError::InternalError(anyhow::anyhow!("Failed to create ticket: {}", e)))?;
+    Ok((ticket, exp))
+}
+
+/// Verifies an access token for a socket
+pub async fn authenticate_token(state: &AppState, token: &str) -> Result<Claims, AppError> {
+    let claims = state.auth.verify_access_token(token)?;
+    check_device(state, claims.did).await?;
+    Ok(claims)
+}
+
+/// Verifies a ticket from `issue_ticket` and uses it up. The socket gets the expiry of the
+/// token it was issued for
+pub async fn authenticate_ticket(state: &AppState, ticket: &str) -> Result<Claims, AppError> {
+    let ticket = state
+        .socket_ticket_jwt
+        .verify::<TicketClaims>(ticket)
+        .map_err(|e| AppError::Unauthorized(format!("Invalid ticket: {}", e)))?
+        .claims;
+    let unused = state
+        .storage
+        .socket_tickets
+        .take_ticket(ticket.jti)
+        .await?
+        .is_some_and(|stored| {
+            stored.did == ticket.did && stored.expires_at > OffsetDateTime::now_utc()
+        });
+    if !unused {
+        return Err(AppError::Unauthorized(
+            "Ticket has already been used".to_string(),
+        ));
+    }
+    check_device(state, ticket.did).await?;
+    Ok(Claims {
+        sub: ticket.sub,
+        did: ticket.did,
+        exp: ticket.session_exp,
+        iat: ticket.iat,
+        roles: ticket.roles,
+    })
+}
+
+/// Authenticates a streaming request by its `Authorization` header or else its ticket.
+/// `None` if it has neither
+pub async fn authenticate_request(
+    state: &AppState,
+    header: Option<TypedHeader<Authorization<Bearer>>>,
+    ticket: Option<&str>,
+) -> Result<Option<Claims>, AppError> {
+    if let Some(TypedHeader(header)) = header {
+        Ok(Some(authenticate_token(state, header.token()).await?))
+    } else if let Some(ticket) = ticket {
+        Ok(Some(authenticate_ticket(state, ticket).await?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Tokens stay valid for a while after their device was revoked, so check it still exists
+async fn check_device(state: &AppState, did: DeviceId) -> Result<(), AppError> {
+    match state.storage.devices.get_device_status(did).await {
+        Ok(_) => Ok(()),
+        Err(AppError::NotFound(_)) => Err(AppError::Unauthorized(
+            "Device has been revoked".to_string(),
+        )),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    AppState,
+    activitypub::{EncryptedMessageEntry, actor_uid, actor_url},
+    auth::Claims,
+    devices::{DeviceId, DeviceService},
+    errors::AppError,
+    websocket::ServerEvent,
+};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// How long an ephemeral event is worth delivering if the sender does not say
+pub const DEFAULT_EPHEMERAL_TTL: Duration = Duration::from_secs(10);
+pub const MAX_EPHEMERAL_TTL: Duration = Duration::from_secs(30);
+/// Encrypted bytes allowed per device entry. Typing and presence signals are tiny
+pub const MAX_EPHEMERAL_CONTENT: usize = 4096;
+/// Ephemeral events a device may send at once
+pub const EPHEMERAL_BURST: u32 = 20;
+/// After a burst, a device may send one ephemeral event per this long
+pub const EPHEMERAL_REFILL: Duration = Duration::from_millis(500);
+
+/// Token bucket per sending device
+pub struct EphemeralLimiter {
+    buckets: DashMap<DeviceId, Bucket>,
+    burst: u32,
+    refill: Duration,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Default for EphemeralLimiter {
+    fn default() -> Self {
+        Self::new(EPHEMERAL_BURST, EPHEMERAL_REFILL)
+    }
+}
+
+impl EphemeralLimiter {
+    pub fn new(burst: u32, refill: Duration) -> Self {
+        EphemeralLimiter {
+            buckets: DashMap::new(),
+            burst,
+            refill,
+        }
+    }
+
+    /// Takes a token for the device. False if it has used them all up
+    pub fn try_acquire(&self, did: DeviceId) -> bool {
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(did).or_insert(Bucket {
+            tokens: self.burst as f64,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / self.refill.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(self.burst as f64);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Drops the device's bucket once it has no connection left
+    pub fn forget(&self, did: &DeviceId) {
+        self.buckets.remove(did);
+    }
+}
+
+/// Forwards an ephemeral event, such as typing or presence, to the connected devices of `to`
+/// it has an entry for. Nothing is stored or pushed, so entries for offline devices are
+/// dropped without telling the sender
+pub async fn relay_ephemeral(
+    state: &AppState,
+    claims: &Claims,
+    to: &str,
+    ttl: Option<u64>,
+    content: Vec<EncryptedMessageEntry>,
+) -> Result<(), AppError> {
+    if !state.sockets.ephemeral_limiter().try_acquire(claims.did) {
+        return Err(AppError::TooManyRequests(
+            "Too many ephemeral events".to_string(),
+        ));
+    }
+
+    let from_url = claims.did.to_url(&state.domain);
+    for entry in &content {
+        if entry.from != from_url {
+            return Err(AppError::BadRequest(format!(
+                "Event sender does not match from: ({} != {})",
+                from_url, entry.from
+            )));
+        }
+        if entry.content.len() > MAX_EPHEMERAL_CONTENT {
+            return Err(AppError::BadRequest(
+                "Ephemeral event is too large".to_string(),
+            ));
+        }
+    }
+    if !state.storage.actors.is_local_actor(to).await? {
+        return Err(AppError::BadRequest(
+            "Ephemeral events are only relayed to local users".to_string(),
+        ));
+    }
+
+    let ttl = ttl
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_EPHEMERAL_TTL)
+        .min(MAX_EPHEMERAL_TTL);
+    let expires_at = (OffsetDateTime::now_utc() + ttl).unix_timestamp();
+    let actor = actor_url(&state.domain, &claims.sub);
+    let devices = DeviceService::list_device_ids(state, &actor_uid(to)?).await?;
+
+    for entry in content {
+        // Only devices of the addressed user, so this cannot reach arbitrary devices
+        if !devices.contains(&entry.to) {
+            continue;
+        }
+        let Ok(did) = DeviceId::from_url(&entry.to) else {
+            continue;
+        };
+        let event = ServerEvent::Ephemeral {
+            actor: actor.clone(),
+            content: entry,
+            expires_at,
+        };
+        state.sockets.send_ephemeral(did, &event, ttl).await;
+    }
+    Ok(())
+}
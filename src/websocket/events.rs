@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+use crate::activitypub::{Activity, EncryptedMessageEntry};
+
+/// Messages the server sends on its own behalf, as opposed to relayed activities
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum ServerEvent {
+    /// A refresh token of `did` was used after it had been rotated. The device has been
+    /// signed out because its token was probably stolen.
+    RefreshTokenReused { did: String },
+    /// A client request succeeded. `activity` is the activity as accepted by the outbox
+    Response {
+        request_id: String,
+        status: u16,
+        activity: Box<Activity>,
+    },
+    /// The socket was authenticated with an `Authenticate` frame. It is closed at
+    /// `expires_at`, a unix timestamp, unless refreshed
+    Authenticated { expires_at: usize },
+    /// A `Refresh` succeeded and the socket now lives until `expires_at`
+    Refreshed {
+        request_id: String,
+        expires_at: usize,
+    },
+    /// An ephemeral event from `actor`, such as typing or presence. `content` is the entry
+    /// for this device. Stale after `expires_at`, a unix timestamp
+    Ephemeral {
+        actor: String,
+        content: EncryptedMessageEntry,
+        expires_at: i64,
+    },
+    /// A `Received` frame was processed. `removed` counts the deliveries that were pending
+    Acknowledged { request_id: String, removed: u64 },
+    /// A client request failed. `request_id` is missing when the frame could not be parsed
+    Error {
+        request_id: Option<String>,
+        status: u16,
+        error: String,
+    },
+}
+
+/// Frames a client may send over its websocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum ClientMessage {
+    /// The first frame of a socket opened without an `Authorization` header or ticket, as
+    /// browsers cannot set headers
+    Authenticate { token: String },
+    /// Extend the socket's life with a fresh access token of the same device
+    Refresh { request_id: String, token: String },
+    /// Post an activity to the user's outbox, exactly like `POST /users/{uid}/outbox`
+    Send {
+        request_id: String,
+        activity: Box<Activity>,
+    },
+    /// Acknowledge a received message. Shorthand for sending a `Delivered` of `object` to
+    /// `to`, the actor that sent it
+    Ack {
+        request_id: String,
+        object: String,
+        to: String,
+    },
+    /// Confirm that activities other than a `Create` arrived, so they are not sent again on
+    /// the next connection. A `Create` is confirmed with `Ack`
+    Received {
+        request_id: String,
+        activities: Vec<String>,
+    },
+    /// Relay an encrypted event, such as typing or presence, to the devices of `to` that are
+    /// connected now. It is never stored or pushed, and expires after `ttl` seconds. No reply
+    /// is sent unless it is rejected
+    Ephemeral {
+        to: String,
+        ttl: Option<u64>,
+        content: Vec<EncryptedMessageEntry>,
+    },
+}
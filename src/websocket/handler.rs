@@ -1,79 +1,446 @@
-use crate::{AppState, auth::Claims, errors::AppError};
+use crate::{
+    AppState,
+    activitypub::{Activity, Delivered, actor_url, types::actor::default_context_value},
+    auth::Claims,
+    errors::AppError,
+    messaging::MessagingService,
+    websocket::{
+        ClientMessage, ServerEvent, SocketMessage, SocketReceiver,
+        auth::{authenticate_request, authenticate_token, issue_ticket},
+        ephemeral::relay_ephemeral,
+        service::sequenced_json,
+    },
+};
 use axum::{
-    Extension,
+    Extension, Json,
+    body::Bytes,
+    debug_handler,
     extract::{
-        State, WebSocketUpgrade,
-        ws::{Message, Utf8Bytes, WebSocket},
+        Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
     },
+    http::StatusCode,
     response::IntoResponse,
 };
-use std::sync::Arc;
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use time::OffsetDateTime;
 use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior, interval, sleep_until, timeout};
 use tracing::{info, warn};
 
+/// Activities read from the inbox at a time when replaying it to a socket
+pub const REPLAY_PAGE_SIZE: i64 = 100;
+/// Sockets opened without credentials are closed if no `Authenticate` frame arrives in time
+pub const AUTHENTICATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+pub struct SocketParams {
+    /// Sequence number of the last activity the client received, to resume after it
+    after: Option<i64>,
+    /// Ticket from `POST /ws/ticket`, for clients that cannot set an `Authorization` header
+    ticket: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TicketResponse {
+    ticket: String,
+    /// Unix timestamp after which the ticket is no longer accepted
+    expires_at: usize,
+}
+
+/// POST /ws/ticket
+#[debug_handler]
+pub async fn ticket_handler(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+) -> Result<Json<TicketResponse>, AppError> {
+    let (ticket, expires_at) = issue_ticket(&state, &claims).await?;
+    Ok(Json(TicketResponse { ticket, expires_at }))
+}
+
+/// GET /ws
+/// Authenticated with an `Authorization` header, a `ticket`, or an `Authenticate` frame
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    Extension(claims): Extension<Arc<Claims>>,
+    Query(params): Query<SocketParams>,
+    auth_header: Option<TypedHeader<Authorization<Bearer>>>,
 ) -> Result<impl IntoResponse, AppError> {
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, claims.clone())))
+    let claims = authenticate_request(&state, auth_header, params.ticket.as_deref()).await?;
+
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        let claims = match claims {
+            Some(claims) => claims,
+            None => match authenticate_first_frame(&mut socket, &state).await {
+                Some(claims) => claims,
+                None => return,
+            },
+        };
+        handle_socket(socket, state, claims, params).await
+    }))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: AppState, claims: Arc<Claims>) {
-    let (tx, mut rx) = mpsc::unbounded_channel();
+/// Waits for an `Authenticate` frame. Closes the socket and returns `None` if none arrives in
+/// time or its token is not accepted
+async fn authenticate_first_frame(socket: &mut WebSocket, state: &AppState) -> Option<Claims> {
+    let send_timeout = state.sockets.slow_consumer_timeout();
+    let result = match timeout(AUTHENTICATE_TIMEOUT, next_text(socket)).await {
+        Ok(None) => return None,
+        Ok(Some(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Authenticate { token }) => authenticate_token(state, &token).await,
+            _ => Err(AppError::Unauthorized(
+                "Authentication required".to_string(),
+            )),
+        },
+        Err(_) => Err(AppError::Unauthorized(
+            "Authentication timed out".to_string(),
+        )),
+    };
 
-    info!(
-        "Client {} - {} connected via WebSocket",
-        claims.sub, claims.did
-    );
-    state.sockets.insert(claims.did, tx.clone());
-
-    // Send messages from inbox to client
-    match state.storage.activities.inbox_activities(claims.did).await {
-        Ok(inbox_items) => {
-            for item in inbox_items {
-                if let Ok(message_json) = serde_json::to_string(&item)
-                    && tx
-                        .send(Message::Text(Utf8Bytes::from(message_json)))
-                        .is_err()
-                {
-                    warn!(
-                        "Failed to send offline message to {} - {}",
-                        claims.sub, claims.did
-                    );
-                    break;
-                }
-            }
+    match result {
+        Ok(claims) => {
+            let event = ServerEvent::Authenticated {
+                expires_at: claims.exp,
+            };
+            let json = serde_json::to_string(&event).ok()?;
+            send(socket, json.into(), send_timeout)
+                .await
+                .then_some(claims)
         }
         Err(e) => {
-            warn!(
-                "Failed to retrieve inbox for {} - {}: {:?}",
-                claims.sub, claims.did, e
-            );
+            let reason = match e {
+                AppError::Unauthorized(reason) => reason,
+                e => {
+                    warn!("Failed to authenticate socket: {:?}", e);
+                    "Authentication failed".to_string()
+                }
+            };
+            let frame = CloseFrame {
+                code: close_code::POLICY,
+                reason: reason.into(),
+            };
+            let _ = send(socket, Message::Close(Some(frame)), send_timeout).await;
+            None
+        }
+    }
+}
+
+/// The next text frame, or `None` once the socket closed
+async fn next_text(socket: &mut WebSocket) -> Option<String> {
+    while let Some(Ok(message)) = socket.recv().await {
+        match message {
+            Message::Text(text) => return Some(text.to_string()),
+            Message::Close(_) => return None,
+            _ => {}
         }
     }
+    None
+}
+
+/// When a token expiring at `exp`, a unix timestamp, runs out
+pub(crate) fn expiry_deadline(exp: usize) -> Instant {
+    let remaining = exp as i64 - OffsetDateTime::now_utc().unix_timestamp();
+    Instant::now() + Duration::from_secs(remaining.max(0) as u64)
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    mut claims: Claims,
+    params: SocketParams,
+) {
+    info!(
+        "Client {} - {} connected via WebSocket",
+        claims.sub, claims.did
+    );
+    let SocketReceiver {
+        connection_id,
+        mut messages,
+        mut close,
+    } = state.sockets.register(claims.did).await;
+    let send_timeout = state.sockets.slow_consumer_timeout();
+    let idle_timeout = state.sockets.idle_timeout();
+
+    // Send everything the device has not acknowledged yet, or only what came after the last
+    // activity it saw when resuming
+    let Some(replayed) = replay(
+        &mut socket,
+        &state,
+        &claims,
+        &mut messages,
+        params.after.unwrap_or(0),
+    )
+    .await
+    else {
+        warn!(
+            "Failed to send offline messages to {} - {}",
+            claims.sub, claims.did
+        );
+        state.sockets.remove(&claims.did, connection_id).await;
+        return;
+    };
+
+    // Pongs prove the client is still there. Without them a half open connection would keep
+    // taking messages that never arrive
+    let mut ping = interval(state.sockets.ping_interval());
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping.tick().await;
+    let mut last_seen = Instant::now();
+
+    // The socket lives as long as the token it was opened with, unless refreshed
+    let expiry = sleep_until(expiry_deadline(claims.exp));
+    tokio::pin!(expiry);
 
     loop {
         tokio::select! {
             // Send messages from channel to WebSocket
-            Some(msg) = rx.recv() => {
-                if socket.send(msg).await.is_err() {
+            Some(msg) = messages.recv() => {
+                // Stored before the replay finished, so it was already sent. Ephemeral events
+                // that waited too long are stale
+                if msg.seq.is_some_and(|seq| seq <= replayed) || msg.is_expired() {
+                    continue;
+                }
+                if !send(&mut socket, msg.message, send_timeout).await {
+                    warn!("{} - {} is not accepting messages", claims.sub, claims.did);
+                    break;
+                }
+            }
+            Some(frame) = close.recv() => {
+                let _ = send(&mut socket, Message::Close(Some(frame)), send_timeout).await;
+                break;
+            }
+            _ = &mut expiry => {
+                info!("Token of {} - {} expired", claims.sub, claims.did);
+                let frame = CloseFrame {
+                    code: close_code::POLICY,
+                    reason: "Token expired".into(),
+                };
+                let _ = send(&mut socket, Message::Close(Some(frame)), send_timeout).await;
+                break;
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() >= idle_timeout {
+                    warn!("{} - {} timed out", claims.sub, claims.did);
+                    let frame = CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "Idle timeout".into(),
+                    };
+                    let _ = send(&mut socket, Message::Close(Some(frame)), send_timeout).await;
+                    break;
+                }
+                if !send(&mut socket, Message::Ping(Bytes::new()), send_timeout).await {
                     break;
                 }
             }
             // Receive messages from WebSocket
             Some(msg) = socket.recv() => {
+                last_seen = Instant::now();
                 match msg {
+                    Ok(Message::Text(text)) => {
+                        // Requests are handled in order, so a client's sends are delivered in
+                        // the order it made them
+                        let exp = claims.exp;
+                        let event = handle_client_message(&state, &mut claims, &text).await;
+                        if claims.exp != exp {
+                            expiry.as_mut().reset(expiry_deadline(claims.exp));
+                        }
+                        if let Some(event) = event
+                            && let Ok(json) = serde_json::to_string(&event)
+                            && !send(&mut socket, json.into(), send_timeout).await
+                        {
+                            break;
+                        }
+                    }
                     Ok(Message::Close(_)) => break,
                     Err(_) => break,
                     // Pings are automatically responded to by axum with pongs
-                    // Ignore all other message types
+                    // Ignore all other message types, including pongs to our pings
                     _ => {}
                 }
             }
             else => break,
         }
     }
-    state.sockets.remove(&claims.did);
+    state.sockets.remove(&claims.did, connection_id).await;
     info!("Client {} - {} disconnected", claims.sub, claims.did);
 }
+
+/// Sends the stored activities after `after_seq` page by page, so a large backlog neither
+/// sits in memory nor blocks behind the queue. Returns the sequence number sent last, or
+/// `None` if the client stopped accepting messages
+async fn replay(
+    socket: &mut WebSocket,
+    state: &AppState,
+    claims: &Claims,
+    messages: &mut mpsc::Receiver<SocketMessage>,
+    after_seq: i64,
+) -> Option<i64> {
+    let send_timeout = state.sockets.slow_consumer_timeout();
+    let mut cursor = after_seq;
+    loop {
+        let page = match state
+            .storage
+            .activities
+            .pending_activities(claims.did, cursor, REPLAY_PAGE_SIZE)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                warn!(
+                    "Failed to retrieve inbox for {} - {}: {:?}",
+                    claims.sub, claims.did, e
+                );
+                return Some(cursor);
+            }
+        };
+        let done = (page.len() as i64) < REPLAY_PAGE_SIZE;
+
+        for item in page {
+            cursor = item.seq;
+            if let Ok(json) = sequenced_json(item.seq, &item.activity)
+                && !send(socket, json.into(), send_timeout).await
+            {
+                return None;
+            }
+        }
+        if done {
+            return Some(cursor);
+        }
+
+        // Stored activities queued meanwhile are committed, so a later page has them. Only
+        // server events are sent now, which also keeps the queue from filling up
+        while let Ok(msg) = messages.try_recv() {
+            if msg.seq.is_none()
+                && !msg.is_expired()
+                && !send(socket, msg.message, send_timeout).await
+            {
+                return None;
+            }
+        }
+    }
+}
+
+/// Sends a message, giving up if the client does not accept it within `limit`
+async fn send(socket: &mut WebSocket, message: Message, limit: Duration) -> bool {
+    matches!(timeout(limit, socket.send(message)).await, Ok(Ok(())))
+}
+
+fn error_event(request_id: Option<String>, e: AppError) -> ServerEvent {
+    let (status, error) = e.into_status();
+    ServerEvent::Error {
+        request_id,
+        status: status.as_u16(),
+        error,
+    }
+}
+
+/// Checks a token a client refreshes its socket with. It must be for the same device
+async fn refresh(state: &AppState, claims: &Claims, token: &str) -> Result<Claims, AppError> {
+    let refreshed = authenticate_token(state, token).await?;
+    if refreshed.sub != claims.sub || refreshed.did != claims.did {
+        return Err(AppError::Forbidden(
+            "Token is for another device".to_string(),
+        ));
+    }
+    Ok(refreshed)
+}
+
+/// Runs a request sent over the socket and builds the reply, if it gets one. A `Refresh`
+/// replaces `claims`
+async fn handle_client_message(
+    state: &AppState,
+    claims: &mut Claims,
+    text: &str,
+) -> Option<ServerEvent> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return Some(ServerEvent::Error {
+                request_id: None,
+                status: StatusCode::BAD_REQUEST.as_u16(),
+                error: format!("Invalid message: {}", e),
+            });
+        }
+    };
+
+    let (request_id, activity) = match message {
+        ClientMessage::Send {
+            request_id,
+            activity,
+        } => (request_id, *activity),
+        ClientMessage::Ack {
+            request_id,
+            object,
+            to,
+        } => (
+            request_id,
+            Activity::Delivered(Delivered {
+                id: None,
+                context: default_context_value(),
+                actor: actor_url(&state.domain, &claims.sub),
+                to,
+                object,
+            }),
+        ),
+        ClientMessage::Received {
+            request_id,
+            activities,
+        } => {
+            return Some(
+                match state
+                    .storage
+                    .activities
+                    .acknowledge_deliveries(&activities, &claims.did)
+                    .await
+                {
+                    Ok(removed) => ServerEvent::Acknowledged {
+                        request_id,
+                        removed,
+                    },
+                    Err(e) => error_event(Some(request_id), e),
+                },
+            );
+        }
+        ClientMessage::Refresh { request_id, token } => {
+            return Some(match refresh(state, claims, &token).await {
+                Ok(refreshed) => {
+                    *claims = refreshed;
+                    ServerEvent::Refreshed {
+                        request_id,
+                        expires_at: claims.exp,
+                    }
+                }
+                Err(e) => error_event(Some(request_id), e),
+            });
+        }
+        ClientMessage::Authenticate { .. } => {
+            return Some(error_event(
+                None,
+                AppError::BadRequest("Socket is already authenticated".to_string()),
+            ));
+        }
+        ClientMessage::Ephemeral { to, ttl, content } => {
+            return relay_ephemeral(state, claims, &to, ttl, content)
+                .await
+                .err()
+                .map(|e| error_event(None, e));
+        }
+    };
+
+    Some(
+        match MessagingService::accept_outbox_activity(state, claims, activity).await {
+            Ok(activity) => ServerEvent::Response {
+                request_id,
+                status: StatusCode::CREATED.as_u16(),
+                activity: Box::new(activity),
+            },
+            Err(e) => error_event(Some(request_id), e),
+        },
+    )
+}
@@ -1,4 +1,10 @@
+pub mod auth;
+pub mod ephemeral;
+pub mod events;
 pub mod handler;
+pub mod poll;
 pub mod service;
+pub mod sse;
 
-pub use service::WebSocketService;
+pub use events::{ClientMessage, ServerEvent};
+pub use service::{SinkGuard, SocketMessage, SocketReceiver, WebSocketService};
@@ -0,0 +1,102 @@
+use crate::{
+    AppState,
+    auth::Claims,
+    errors::AppError,
+    websocket::{SinkGuard, SocketMessage, handler::REPLAY_PAGE_SIZE, service::sequenced_value},
+};
+use axum::{
+    Extension, Json, debug_handler,
+    extract::{Query, State, ws::Message},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tokio::time::timeout;
+
+/// Longest a poll waits for something to arrive
+pub const MAX_POLL_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct PollParams {
+    /// Sequence number of the last activity the client received
+    after: Option<i64>,
+    /// Seconds to wait if nothing is pending, at most [`MAX_POLL_WAIT`]
+    wait: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PollResponse {
+    /// The same JSON a socket would have been sent, in order
+    messages: Vec<Value>,
+    /// Pass as `after` in the next poll
+    cursor: i64,
+}
+
+/// GET /inbox/poll
+/// Long-poll variant of the inbox for networks that block websockets. Activities stay pending
+/// until acknowledged, as on a socket
+#[debug_handler]
+pub async fn poll_handler(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Arc<Claims>>,
+    Query(params): Query<PollParams>,
+) -> Result<Json<PollResponse>, AppError> {
+    let after = params.after.unwrap_or(0);
+    let wait = params
+        .wait
+        .map(Duration::from_secs)
+        .unwrap_or(MAX_POLL_WAIT)
+        .min(MAX_POLL_WAIT);
+
+    // Registered before reading the inbox so nothing stored in between is missed
+    let mut receiver = state.sockets.register(claims.did).await;
+    let _guard = SinkGuard::new(state.sockets.clone(), claims.did, receiver.connection_id);
+
+    let mut messages = Vec::new();
+    let mut pending = state
+        .storage
+        .activities
+        .pending_activities(claims.did, after, REPLAY_PAGE_SIZE)
+        .await?;
+
+    if pending.is_empty() {
+        let arrived = timeout(wait, async {
+            tokio::select! {
+                Some(msg) = receiver.messages.recv() => Some(msg),
+                _ = receiver.close.recv() => None,
+            }
+        })
+        .await;
+        if let Ok(Some(msg)) = arrived {
+            // Stored activities are read back below, in sequence order
+            messages.extend(event_json(msg));
+            pending = state
+                .storage
+                .activities
+                .pending_activities(claims.did, after, REPLAY_PAGE_SIZE)
+                .await?;
+        }
+    }
+    while let Ok(msg) = receiver.messages.try_recv() {
+        messages.extend(event_json(msg));
+    }
+
+    let cursor = pending.last().map_or(after, |item| item.seq);
+    for item in pending {
+        messages.push(sequenced_value(item.seq, &item.activity)?);
+    }
+
+    Ok(Json(PollResponse { messages, cursor }))
+}
+
+/// A server event's JSON. Stored activities give `None`, they are read from the inbox
+fn event_json(msg: SocketMessage) -> Option<Value> {
+    if msg.is_expired() {
+        return None;
+    }
+    match (msg.seq, msg.message) {
+        (None, Message::Text(text)) => serde_json::from_str(text.as_str()).ok(),
+        _ => None,
+    }
+}
@@ -4,20 +4,155 @@ use crate::{
         types::activity::{ActivityBase, CreateView},
     },
     devices::DeviceId,
+    errors::AppError,
+    notifications::{NotificationService, PushKind},
+    storage::traits::SocketRouteStore,
+    websocket::{ServerEvent, ephemeral::EphemeralLimiter},
 };
-use axum::extract::ws::{Message, Utf8Bytes};
+use axum::extract::ws::{CloseFrame, Message, Utf8Bytes, close_code};
 use dashmap::DashMap;
-use serde::Serialize;
-use tokio::sync::mpsc;
-use tracing::{info, warn};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Messages waiting for a socket beyond this many are not sent over it
+pub const SOCKET_QUEUE_CAPACITY: usize = 256;
+/// Clients whose queue stays full this long, or that take this long to accept a single
+/// message, are disconnected
+pub const SLOW_CONSUMER_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the server pings each client
+pub const PING_INTERVAL: Duration = Duration::from_secs(20);
+/// Clients that send nothing, not even a pong, for this long are disconnected
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Close reason sent to a socket when its device connects again
+pub const REPLACED_REASON: &str = "Replaced by a newer connection";
+/// How often a clustered node records that it is still alive
+pub const NODE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Routes to nodes that have not heartbeated for this long are ignored, and the nodes dropped
+pub const NODE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub trait ActivityData: ActivityBase + Serialize {}
 
 impl ActivityData for Activity {}
 impl<'a> ActivityData for CreateView<'a> {}
 
+/// A message waiting to be written to a socket
+#[derive(Debug)]
+pub struct SocketMessage {
+    /// Sequence number of the stored delivery it carries. Server events have none
+    pub seq: Option<i64>,
+    pub message: Message,
+    /// Ephemeral events are dropped rather than sent after this
+    pub expires_at: Option<Instant>,
+}
+
+impl SocketMessage {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Instant::now())
+    }
+}
+
+/// An activity as sent over a socket, with its position in the device's inbox
+#[derive(Serialize)]
+struct Sequenced<'a, T> {
+    seq: i64,
+    #[serde(flatten)]
+    activity: &'a T,
+}
+
+struct Connection {
+    /// Tells this connection apart from earlier and later ones of the same device
+    id: u64,
+    tx: mpsc::Sender<SocketMessage>,
+    /// Closing must work when `tx` is full, so it has its own channel
+    close_tx: mpsc::Sender<CloseFrame>,
+    full_since: Mutex<Option<Instant>>,
+}
+
+/// Hands deliveries for sockets held by other nodes to those nodes
+struct Cluster {
+    node_id: Uuid,
+    routes: Arc<dyn SocketRouteStore>,
+}
+
+/// What one node asks of the node holding a device's socket
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum RelayCommand {
+    Deliver {
+        did: DeviceId,
+        seq: Option<i64>,
+        message: String,
+        /// Milliseconds an ephemeral event is still worth delivering
+        #[serde(default)]
+        ttl: Option<u64>,
+        /// How the holding node wakes the device if it cannot queue the message. The sender
+        /// took the relay as delivered, so nobody else will
+        #[serde(default)]
+        wake: Option<PushKind>,
+    },
+    Close {
+        did: DeviceId,
+        reason: String,
+    },
+}
+
+/// The receiving ends a socket task reads from
+pub struct SocketReceiver {
+    /// Pass to [`WebSocketService::remove`] so only this connection is removed
+    pub connection_id: u64,
+    pub messages: mpsc::Receiver<SocketMessage>,
+    /// Yields once when the server wants the socket closed
+    pub close: mpsc::Receiver<CloseFrame>,
+}
+
+/// Removes a registration when dropped. For transports whose requests can be cancelled at
+/// any await point, so removing it by hand could be skipped
+pub struct SinkGuard {
+    sockets: Arc<WebSocketService>,
+    did: DeviceId,
+    connection_id: u64,
+}
+
+impl SinkGuard {
+    pub fn new(sockets: Arc<WebSocketService>, did: DeviceId, connection_id: u64) -> Self {
+        SinkGuard {
+            sockets,
+            did,
+            connection_id,
+        }
+    }
+}
+
+impl Drop for SinkGuard {
+    fn drop(&mut self) {
+        let sockets = self.sockets.clone();
+        let (did, connection_id) = (self.did, self.connection_id);
+        tokio::spawn(async move { sockets.remove(&did, connection_id).await });
+    }
+}
+
 pub struct WebSocketService {
-    sockets: DashMap<DeviceId, mpsc::UnboundedSender<Message>>,
+    sockets: DashMap<DeviceId, Connection>,
+    queue_capacity: usize,
+    slow_consumer_timeout: Duration,
+    ping_interval: Duration,
+    idle_timeout: Duration,
+    next_connection_id: AtomicU64,
+    cluster: Option<Cluster>,
+    ephemeral_limiter: EphemeralLimiter,
+    /// Wakes devices whose relayed deliveries could not be queued
+    notifications: Option<Arc<NotificationService>>,
 }
 impl Default for WebSocketService {
     fn default() -> Self {
@@ -27,53 +162,444 @@ impl Default for WebSocketService {
 
 impl WebSocketService {
     pub fn new() -> Self {
+        Self::with_limits(SOCKET_QUEUE_CAPACITY, SLOW_CONSUMER_TIMEOUT)
+    }
+
+    pub fn with_limits(queue_capacity: usize, slow_consumer_timeout: Duration) -> Self {
         WebSocketService {
             sockets: DashMap::new(),
+            queue_capacity,
+            slow_consumer_timeout,
+            ping_interval: PING_INTERVAL,
+            idle_timeout: IDLE_TIMEOUT,
+            next_connection_id: AtomicU64::new(0),
+            cluster: None,
+            ephemeral_limiter: EphemeralLimiter::default(),
+            notifications: None,
+        }
+    }
+
+    pub fn with_ephemeral_limit(mut self, burst: u32, refill: Duration) -> Self {
+        self.ephemeral_limiter = EphemeralLimiter::new(burst, refill);
+        self
+    }
+
+    pub fn with_heartbeat(mut self, ping_interval: Duration, idle_timeout: Duration) -> Self {
+        self.ping_interval = ping_interval;
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Routes deliveries through `routes` so devices connected to other nodes can be reached.
+    /// [`Self::spawn_cluster_tasks`] must be called before the service is used
+    pub fn with_cluster(mut self, routes: Arc<dyn SocketRouteStore>) -> Self {
+        self.cluster = Some(Cluster {
+            node_id: Uuid::new_v4(),
+            routes,
+        });
+        self
+    }
+
+    /// Wakes devices through `notifications` when a delivery relayed from another node cannot
+    /// be queued for their socket
+    pub fn with_notifications(mut self, notifications: Arc<NotificationService>) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    /// This node's id, if it is part of a cluster
+    pub fn node_id(&self) -> Option<Uuid> {
+        self.cluster.as_ref().map(|c| c.node_id)
+    }
+
+    /// Registers the node and starts listening for deliveries relayed from other nodes.
+    /// Does nothing without a cluster
+    pub async fn spawn_cluster_tasks(self: Arc<Self>) -> Result<(), AppError> {
+        let Some(cluster) = &self.cluster else {
+            return Ok(());
+        };
+        cluster.routes.heartbeat_node(cluster.node_id).await?;
+        let mut relayed = cluster.routes.subscribe(cluster.node_id).await?;
+        info!("Joined socket cluster as node {}", cluster.node_id);
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            while let Some(payload) = relayed.next().await {
+                match serde_json::from_str::<RelayCommand>(&payload) {
+                    Ok(RelayCommand::Deliver {
+                        did,
+                        seq,
+                        message,
+                        ttl,
+                        wake,
+                    }) => {
+                        if !service
+                            .queue(did, seq, ttl.map(Duration::from_millis), message)
+                            .await
+                        {
+                            warn!("Could not queue message relayed to {}", did);
+                            if let Some(kind) = wake {
+                                service.wake(did, kind);
+                            }
+                        }
+                    }
+                    Ok(RelayCommand::Close { did, reason }) => {
+                        service.disconnect_local(&did, &reason).await;
+                    }
+                    Err(e) => warn!("Invalid relayed socket command: {}", e),
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let Some(cluster) = &self.cluster else {
+                return;
+            };
+            let mut interval = tokio::time::interval(NODE_HEARTBEAT_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match cluster.routes.heartbeat_node(cluster.node_id).await {
+                    // Other nodes took us for dead and dropped our routes
+                    Ok(true) => {
+                        warn!(
+                            "Node {} was dropped from the socket cluster",
+                            cluster.node_id
+                        );
+                        let dids: Vec<DeviceId> = self.sockets.iter().map(|e| *e.key()).collect();
+                        for did in dids {
+                            if let Err(e) = cluster.routes.set_route(did, cluster.node_id).await {
+                                error!("Failed to restore socket route of {}: {:?}", did, e);
+                            }
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to heartbeat node {}: {:?}", cluster.node_id, e),
+                }
+                match cluster.routes.delete_stale_nodes(alive_since()).await {
+                    Ok(0) => {}
+                    Ok(n) => info!("Dropped {} dead socket nodes", n),
+                    Err(e) => error!("Failed to drop dead socket nodes: {:?}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn slow_consumer_timeout(&self) -> Duration {
+        self.slow_consumer_timeout
+    }
+
+    pub fn ping_interval(&self) -> Duration {
+        self.ping_interval
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub fn ephemeral_limiter(&self) -> &EphemeralLimiter {
+        &self.ephemeral_limiter
+    }
+
+    /// Registers the device's socket, or the event stream or poll standing in for it. A
+    /// previous one of the device, on this or another node, is closed since the device
+    /// reconnected
+    pub async fn register(&self, did: DeviceId) -> SocketReceiver {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, messages) = mpsc::channel(self.queue_capacity);
+        let (close_tx, close) = mpsc::channel(1);
+        let previous = self.sockets.insert(
+            did,
+            Connection {
+                id: connection_id,
+                tx,
+                close_tx,
+                full_since: Mutex::new(None),
+            },
+        );
+        if let Some(previous) = previous {
+            info!("{} reconnected, closing its previous socket", did);
+            send_close(&previous, REPLACED_REASON);
+        }
+
+        if let Some(cluster) = &self.cluster {
+            let previous_node = self.remote_node(did).await;
+            if let Err(e) = cluster.routes.set_route(did, cluster.node_id).await {
+                error!("Failed to route socket of {} to this node: {:?}", did, e);
+            }
+            if let Some((cluster, node_id)) = previous_node {
+                let reason = REPLACED_REASON.to_string();
+                publish(cluster, node_id, did, RelayCommand::Close { did, reason }).await;
+            }
+        }
+        SocketReceiver {
+            connection_id,
+            messages,
+            close,
         }
     }
 
-    pub fn insert(
+    /// Removes the device's socket if it is still the given connection. A connection that
+    /// was replaced must not remove its successor
+    pub async fn remove(&self, did: &DeviceId, connection_id: u64) {
+        if self
+            .sockets
+            .remove_if(did, |_, connection| connection.id == connection_id)
+            .is_none()
+        {
+            return;
+        }
+        self.forget(did).await;
+    }
+
+    /// Drops what is kept about a device whose socket was removed from this node, including
+    /// its route, so other nodes stop relaying to a socket that is gone
+    async fn forget(&self, did: &DeviceId) {
+        self.ephemeral_limiter.forget(did);
+        let Some(cluster) = &self.cluster else {
+            return;
+        };
+        // The device reconnected here in the meantime and the route is its new socket's
+        if self.sockets.contains_key(did) {
+            return;
+        }
+        if let Err(e) = cluster.routes.delete_route(*did, cluster.node_id).await {
+            error!("Failed to delete socket route of {}: {:?}", did, e);
+        }
+    }
+
+    /// Whether the device has a socket on this or any live node
+    pub async fn is_online(&self, did: &DeviceId) -> bool {
+        self.sockets.contains_key(did) || self.remote_node(*did).await.is_some()
+    }
+
+    /// The other node holding the device's socket
+    async fn remote_node(&self, did: DeviceId) -> Option<(&Cluster, Uuid)> {
+        let cluster = self.cluster.as_ref()?;
+        match cluster.routes.get_route(did, alive_since()).await {
+            Ok(node_id) => node_id
+                .filter(|node_id| *node_id != cluster.node_id)
+                .map(|node_id| (cluster, node_id)),
+            Err(e) => {
+                error!("Failed to look up socket route of {}: {:?}", did, e);
+                None
+            }
+        }
+    }
+
+    /// Hands the command to the node holding the device's socket. Returns false if there is
+    /// no such node
+    async fn relay(&self, did: DeviceId, command: RelayCommand) -> bool {
+        let Some((cluster, node_id)) = self.remote_node(did).await else {
+            return false;
+        };
+        publish(cluster, node_id, did, command).await
+    }
+
+    /// Sends text to the device's socket on whichever node holds it. Relayed messages count
+    /// as sent once the other node was notified, and that node wakes the device with `wake`
+    /// if it cannot queue them. Messages with a `ttl` are dropped once it runs out
+    async fn deliver(
+        &self,
+        did: DeviceId,
+        seq: Option<i64>,
+        ttl: Option<Duration>,
+        wake: Option<PushKind>,
+        text: String,
+    ) -> bool {
+        if self.sockets.contains_key(&did) {
+            return self.queue(did, seq, ttl, text).await;
+        }
+        let command = RelayCommand::Deliver {
+            did,
+            seq,
+            message: text,
+            ttl: ttl.map(|ttl| ttl.as_millis() as u64),
+            wake,
+        };
+        self.relay(did, command).await
+    }
+
+    /// Wakes the device in the background, for a delivery that was relayed here but could not
+    /// be queued
+    fn wake(&self, did: DeviceId, kind: PushKind) {
+        let Some(notifications) = self.notifications.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = notifications.notify(did, kind).await {
+                warn!("Tried to notify {} Error: {:?}", did, e);
+            }
+        });
+    }
+
+    /// Queues text for a socket on this node without waiting. Returns false if the device is
+    /// offline or its queue is full, and disconnects it once the queue has been full for too
+    /// long
+    async fn queue(
         &self,
         did: DeviceId,
-        tx: mpsc::UnboundedSender<Message>,
-    ) -> Option<mpsc::UnboundedSender<Message>> {
-        self.sockets.insert(did, tx)
+        seq: Option<i64>,
+        ttl: Option<Duration>,
+        text: String,
+    ) -> bool {
+        let Some(connection) = self.sockets.get(&did) else {
+            return false;
+        };
+
+        let message = SocketMessage {
+            seq,
+            message: Message::Text(Utf8Bytes::from(text)),
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        };
+        let evict = match connection.tx.try_send(message) {
+            Ok(()) => {
+                *connection.full_since.lock().unwrap() = None;
+                return true;
+            }
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(_)) => {
+                let mut full_since = connection.full_since.lock().unwrap();
+                full_since.get_or_insert_with(Instant::now).elapsed() >= self.slow_consumer_timeout
+            }
+        };
+        // The map entry is still borrowed until here
+        drop(connection);
+
+        if evict {
+            self.disconnect_local(&did, "Too slow to receive messages")
+                .await;
+        } else {
+            warn!("Socket queue of {} is full", did);
+        }
+        false
     }
-    pub fn remove(&self, did: &DeviceId) -> Option<(DeviceId, mpsc::UnboundedSender<Message>)> {
-        self.sockets.remove(did)
+
+    /// Sends a server event to the device if it is online. Returns true if it was queued
+    pub async fn send_event(&self, did: DeviceId, event: &ServerEvent) -> bool {
+        match serde_json::to_string(event) {
+            Ok(json) => self.deliver(did, None, None, None, json).await,
+            Err(e) => {
+                warn!("Failed to serialize server event {:?}: {}", event, e);
+                false
+            }
+        }
     }
 
-    /// Try to deliver message via WebSocket to online recipient
-    /// Returns true if successfully delivered via WebSocket
+    /// Sends an event that is only worth delivering for `ttl`, if the device is online. It is
+    /// never stored, so an offline device misses it
+    pub async fn send_ephemeral(&self, did: DeviceId, event: &ServerEvent, ttl: Duration) -> bool {
+        if !self.sockets.contains_key(&did) && self.cluster.is_none() {
+            return false;
+        }
+        match serde_json::to_string(event) {
+            Ok(json) => self.deliver(did, None, Some(ttl), None, json).await,
+            Err(e) => {
+                warn!("Failed to serialize ephemeral event for {}: {}", did, e);
+                false
+            }
+        }
+    }
+
+    /// Closes the device's socket, if it has one on any node
+    pub async fn disconnect(&self, did: &DeviceId, reason: &str) {
+        if !self.disconnect_local(did, reason).await {
+            let reason = reason.to_string();
+            self.relay(*did, RelayCommand::Close { did: *did, reason })
+                .await;
+        }
+    }
+
+    /// Closes the device's socket on this node. Returns false if it has none here
+    async fn disconnect_local(&self, did: &DeviceId, reason: &str) -> bool {
+        let Some((_, connection)) = self.sockets.remove(did) else {
+            return false;
+        };
+        info!("Disconnecting {}: {}", did, reason);
+        send_close(&connection, reason);
+        // The socket task's own removal finds the entry gone, so the route is dropped here
+        self.forget(did).await;
+        true
+    }
+
+    /// Try to deliver a stored activity via WebSocket to online recipient. `seq` is the
+    /// sequence number of its delivery. Returns true if it was queued for the socket, or
+    /// relayed to the node holding it, which wakes the device with `kind` if it cannot queue
+    /// it. False means the device is offline or not keeping up, and gets the activity from its
+    /// inbox later
     pub async fn try_websocket_delivery<T: ActivityData>(
         &self,
         activity: &T,
         did: DeviceId,
+        seq: i64,
+        kind: PushKind,
     ) -> bool {
-        // Check if the recipient device is online
-        if let Some(sender) = self.sockets.get(&did) {
-            info!(
-                "{} - {} online, trying to send via socket",
-                activity.to(),
-                did
-            );
-
-            // Create message for WebSocket
-            if let Ok(message_json) = serde_json::to_string(&activity) {
-                // Try to send via WebSocket
-                if let Err(e) = sender.send(Message::Text(Utf8Bytes::from(message_json))) {
+        if !self.sockets.contains_key(&did) && self.cluster.is_none() {
+            return false;
+        }
+
+        match sequenced_json(seq, activity) {
+            Ok(message_json) => {
+                let queued = self
+                    .deliver(did, Some(seq), None, Some(kind), message_json)
+                    .await;
+                if queued {
+                    info!("{} - {} sent via socket", activity.to(), did);
+                } else if self.sockets.contains_key(&did) {
                     warn!(
-                        "Failed to send to online client {}, falling back to inbox: {}",
-                        activity.to(),
-                        e
+                        "Failed to send to online client {}, leaving it in the inbox",
+                        activity.to()
                     );
-                    return false;
                 }
-
-                return true;
+                queued
+            }
+            Err(e) => {
+                warn!("Failed to serialize activity for {}: {}", did, e);
+                false
             }
         }
+    }
+}
 
-        false
+fn send_close(connection: &Connection, reason: &str) {
+    let _ = connection.close_tx.try_send(CloseFrame {
+        code: close_code::POLICY,
+        reason: Utf8Bytes::from(reason),
+    });
+}
+
+async fn publish(cluster: &Cluster, node_id: Uuid, did: DeviceId, command: RelayCommand) -> bool {
+    let payload = match serde_json::to_string(&command) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("Failed to serialize relay command for {}: {}", did, e);
+            return false;
+        }
+    };
+    match cluster.routes.publish(node_id, &payload).await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to relay to node {} for {}: {:?}", node_id, did, e);
+            false
+        }
     }
 }
+
+fn alive_since() -> OffsetDateTime {
+    OffsetDateTime::now_utc() - NODE_TIMEOUT
+}
+
+/// Serializes a stored activity the way it is sent over a socket
+pub(crate) fn sequenced_json<T: Serialize>(seq: i64, activity: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&Sequenced { seq, activity })
+}
+
+/// [`sequenced_json`] as a value, for responses that bundle several activities
+pub(crate) fn sequenced_value<T: Serialize>(
+    seq: i64,
+    activity: &T,
+) -> serde_json::Result<serde_json::Value> {
+    serde_json::to_value(Sequenced { seq, activity })
+}
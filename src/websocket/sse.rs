@@ -0,0 +1,194 @@
+use crate::{
+    AppState,
+    auth::Claims,
+    errors::AppError,
+    websocket::{
+        SinkGuard, SocketMessage, SocketReceiver,
+        auth::authenticate_request,
+        handler::{REPLAY_PAGE_SIZE, expiry_deadline},
+        service::sequenced_json,
+    },
+};
+use axum::{
+    extract::{Query, State, ws::Message},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use futures::{Stream, stream};
+use serde::Deserialize;
+use std::{collections::VecDeque, convert::Infallible, pin::Pin};
+use tokio::time::{Sleep, sleep_until};
+use tracing::{info, warn};
+
+/// Header browsers resume an event stream with, carrying the id of the last event received
+const LAST_EVENT_ID: &str = "last-event-id";
+
+#[derive(Debug, Deserialize)]
+pub struct StreamParams {
+    /// Sequence number of the last activity the client received, to resume after it
+    after: Option<i64>,
+    /// Ticket from `POST /ws/ticket`, as `EventSource` cannot set an `Authorization` header
+    ticket: Option<String>,
+}
+
+/// GET /inbox/stream
+/// Server-Sent Events for networks that block websockets. Each event carries the same JSON a
+/// socket frame would, with the sequence number of stored activities as its id. A `close`
+/// event ends the stream
+pub async fn sse_handler(
+    State(state): State<AppState>,
+    Query(params): Query<StreamParams>,
+    headers: HeaderMap,
+    auth_header: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let claims = authenticate_request(&state, auth_header, params.ticket.as_deref())
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("Missing credentials".to_string()))?;
+    let after = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        .or(params.after)
+        .unwrap_or(0);
+
+    let receiver = state.sockets.register(claims.did).await;
+    info!(
+        "Client {} - {} opened an event stream",
+        claims.sub, claims.did
+    );
+
+    let events = EventStream {
+        _guard: SinkGuard::new(state.sockets.clone(), claims.did, receiver.connection_id),
+        expiry: Box::pin(sleep_until(expiry_deadline(claims.exp))),
+        state,
+        claims,
+        receiver,
+        cursor: after,
+        replaying: true,
+        buffer: VecDeque::new(),
+        closed: false,
+    };
+    let events = stream::unfold(events, |mut events| async move {
+        let event = events.next().await?;
+        Some((Ok(event), events))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Replays the stored activities like a socket does, then follows the device's deliveries
+struct EventStream {
+    state: AppState,
+    claims: Claims,
+    receiver: SocketReceiver,
+    /// Keeps the device registered while the client is connected
+    _guard: SinkGuard,
+    /// Sequence number replayed last
+    cursor: i64,
+    replaying: bool,
+    buffer: VecDeque<Event>,
+    expiry: Pin<Box<Sleep>>,
+    closed: bool,
+}
+
+impl EventStream {
+    /// The next event, or `None` once the stream is over
+    async fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.buffer.pop_front() {
+                return Some(event);
+            }
+            if self.closed {
+                return None;
+            }
+            if self.replaying {
+                self.replay_page().await;
+                continue;
+            }
+
+            tokio::select! {
+                Some(msg) = self.receiver.messages.recv() => {
+                    // Stored before the replay finished, so it was already sent
+                    if msg.seq.is_some_and(|seq| seq <= self.cursor) {
+                        continue;
+                    }
+                    if let Some(event) = message_event(msg) {
+                        return Some(event);
+                    }
+                }
+                Some(frame) = self.receiver.close.recv() => {
+                    self.closed = true;
+                    return Some(close_event(&frame.reason));
+                }
+                _ = &mut self.expiry => {
+                    info!("Token of {} - {} expired", self.claims.sub, self.claims.did);
+                    self.closed = true;
+                    return Some(close_event("Token expired"));
+                }
+                else => return None,
+            }
+        }
+    }
+
+    /// Buffers the next page of stored activities
+    async fn replay_page(&mut self) {
+        let page = match self
+            .state
+            .storage
+            .activities
+            .pending_activities(self.claims.did, self.cursor, REPLAY_PAGE_SIZE)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                warn!(
+                    "Failed to retrieve inbox for {} - {}: {:?}",
+                    self.claims.sub, self.claims.did, e
+                );
+                self.replaying = false;
+                return;
+            }
+        };
+        self.replaying = page.len() as i64 == REPLAY_PAGE_SIZE;
+
+        for item in page {
+            self.cursor = item.seq;
+            if let Ok(json) = sequenced_json(item.seq, &item.activity) {
+                self.buffer
+                    .push_back(Event::default().id(item.seq.to_string()).data(json));
+            }
+        }
+
+        // As with sockets, stored activities queued meanwhile come with a later page
+        if self.replaying {
+            while let Ok(msg) = self.receiver.messages.try_recv() {
+                if msg.seq.is_none()
+                    && let Some(event) = message_event(msg)
+                {
+                    self.buffer.push_back(event);
+                }
+            }
+        }
+    }
+}
+
+fn message_event(msg: SocketMessage) -> Option<Event> {
+    if msg.is_expired() {
+        return None;
+    }
+    let Message::Text(text) = msg.message else {
+        return None;
+    };
+    let event = Event::default().data(text.as_str());
+    Some(match msg.seq {
+        Some(seq) => event.id(seq.to_string()),
+        None => event,
+    })
+}
+
+fn close_event(reason: &str) -> Event {
+    Event::default().event("close").data(reason)
+}
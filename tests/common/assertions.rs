@@ -170,11 +170,10 @@ pub async fn assert_all_devices_received_message(
                             if let Ok(decoded) = base64::Engine::decode(
                                 &base64::engine::general_purpose::STANDARD,
                                 content_str,
-                            ) {
-                                if decoded == expected_bytes {
-                                    found_matching_content = true;
-                                    break;
-                                }
+                            ) && decoded == expected_bytes
+                            {
+                                found_matching_content = true;
+                                break;
                             }
                         } else if let Some(content) = entry["content"].as_array() {
                             // Content is an array of bytes
@@ -5,18 +5,29 @@ use serde_json::{Value, json};
 use uuid::Uuid;
 
 pub struct TestDevice {
+    #[allow(dead_code)]
     pub id: DeviceId,
     pub url: String,
     pub token: String,
+    #[allow(dead_code)]
+    pub refresh_token: Uuid,
+    #[allow(dead_code)]
     pub name: String,
 }
 
 impl TestDevice {
-    pub fn new(id: DeviceId, url: String, token: String, name: String) -> Self {
+    pub fn new(
+        id: DeviceId,
+        url: String,
+        token: String,
+        refresh_token: Uuid,
+        name: String,
+    ) -> Self {
         Self {
             id,
             url,
             token,
+            refresh_token,
             name,
         }
     }
@@ -38,7 +49,7 @@ impl TestUser {
         let email = format!("{}@example.com", username);
         let password = "password";
 
-        app.signup_http(&username, &email, password).await;
+        app.signup_http(username, &email, password).await;
 
         // Login to get credentials (and the first device)
         let login_response = app.login_http(&email, password).await;
@@ -49,6 +60,7 @@ impl TestUser {
             did,
             login_response.did.clone(),
             login_response.access_token.clone(),
+            login_response.refresh_token,
             "default".to_string(),
         );
 
@@ -100,6 +112,7 @@ impl TestUser {
             new_did,
             login_response.did.clone(),
             login_response.access_token.clone(),
+            login_response.refresh_token,
             device_name.to_string(),
         );
 
@@ -291,6 +304,7 @@ impl TestUser {
             actor: self.actor_id.clone(),
             to: envelope.to.clone(),
             object: envelope,
+            notify: None,
         })
     }
 
@@ -26,7 +26,7 @@ impl IdentityProvider for LocalIdentityProvider {
     async fn login_with_email(
         &self,
         email: String,
-        password: String,
+        _password: String,
     ) -> Result<(Person, String), AppError> {
         let user = self
             .storage
@@ -78,6 +78,10 @@ impl IdentityProvider for LocalIdentityProvider {
         Ok(user.uid)
     }
 
+    async fn owns_username(&self, _uid: &str) -> Result<bool, AppError> {
+        Ok(true)
+    }
+
     async fn signup(&self, req: SignupRequest) -> Result<StatusCode, AppError> {
         if self
             .storage
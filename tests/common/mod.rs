@@ -1,32 +1,52 @@
 mod assertions;
 mod fixtures;
 mod local_auth;
+mod push;
 
 pub use assertions::*;
 pub use fixtures::*;
 pub use local_auth::LocalIdentityProvider;
+#[allow(unused_imports)]
+pub use push::{
+    MOCK_APNS_KEY_ID, MOCK_APNS_TOPIC, MOCK_FCM_ACCESS_TOKEN, MOCK_FCM_PROJECT, MockPushService,
+    generate_vapid_key, register_push,
+};
 
 #[cfg(feature = "auth-firebase")]
 use ::eko_messenger::auth::FirebaseAuth;
+#[cfg(feature = "auth-local")]
+use ::eko_messenger::auth::{CompositeIdentityProvider, LocalAuth};
 
 use eko_messenger::{
     AppState, app,
-    auth::{Auth, LoginRequest, LoginResponse, PreKey, SignedPreKey},
-    notifications::NotificationService,
+    auth::{Auth, JwtHelper, KeyPurpose, LoginRequest, LoginResponse, PreKey, SignedPreKey},
+    notifications::{NotificationService, PushProviderKind, vapid::VapidKeys},
     storage::{Storage, postgres::connection::postgres_storage},
     websocket::WebSocketService,
 };
+use futures_util::{SinkExt, StreamExt};
 use reqwest::Client;
+use serde_json::Value;
 use sqlx::PgPool;
 use sqlx::postgres::PgPoolOptions;
-use std::{env, net::SocketAddr, sync::Arc};
-use tokio::net::TcpListener;
+use std::{env, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, time::timeout};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Message, client::IntoClientRequest, http::HeaderValue},
+};
 use uuid::Uuid;
 
 pub struct TestApp {
     pub domain: Arc<String>,
     pub address: String,
+    #[allow(dead_code)]
     pub storage: Arc<Storage>,
+    #[allow(dead_code)]
+    pub notifications: Arc<NotificationService>,
+    /// Where FCM and APNs pushes go
+    #[allow(dead_code)]
+    pub push: MockPushService,
     pub client: Client,
 }
 
@@ -40,11 +60,22 @@ pub enum IdentityBackend {
     Test,
     #[cfg(feature = "auth-firebase")]
     Firebase,
+    #[cfg(feature = "auth-local")]
+    Local,
+    /// Test provider by default, with `example.com` routed to the local password provider
+    #[cfg(feature = "auth-local")]
+    Composite,
 }
 
 pub struct SpawnOptions {
     pub storage: StorageBackend,
     pub identity: IdentityBackend,
+    /// Route socket deliveries across nodes like a production deployment
+    pub cluster: bool,
+    /// Ping interval and idle timeout of sockets, instead of the production ones
+    pub socket_heartbeat: Option<(Duration, Duration)>,
+    /// VAPID keys instead of the one at `VAPID_KEY_PATH`
+    pub vapid_keys: Option<VapidKeys>,
 }
 
 impl Default for SpawnOptions {
@@ -52,6 +83,9 @@ impl Default for SpawnOptions {
         Self {
             storage: StorageBackend::Postgres,
             identity: IdentityBackend::Test,
+            cluster: false,
+            socket_heartbeat: None,
+            vapid_keys: None,
         }
     }
 }
@@ -60,6 +94,7 @@ pub async fn spawn_app() -> TestApp {
     spawn_app_with_options(SpawnOptions::default()).await
 }
 
+#[allow(dead_code)]
 pub async fn spawn_app_with_storage(storage: StorageBackend) -> TestApp {
     spawn_app_with_options(SpawnOptions {
         storage,
@@ -68,6 +103,33 @@ pub async fn spawn_app_with_storage(storage: StorageBackend) -> TestApp {
     .await
 }
 
+#[allow(dead_code)]
+pub async fn spawn_app_clustered() -> TestApp {
+    spawn_app_with_options(SpawnOptions {
+        cluster: true,
+        ..Default::default()
+    })
+    .await
+}
+
+#[cfg(feature = "auth-local")]
+pub async fn spawn_app_local() -> TestApp {
+    spawn_app_with_options(SpawnOptions {
+        identity: IdentityBackend::Local,
+        ..Default::default()
+    })
+    .await
+}
+
+#[cfg(feature = "auth-local")]
+pub async fn spawn_app_composite() -> TestApp {
+    spawn_app_with_options(SpawnOptions {
+        identity: IdentityBackend::Composite,
+        ..Default::default()
+    })
+    .await
+}
+
 #[cfg(feature = "auth-firebase")]
 // NOTE this function is never called. If we want to use the entire test bench with firebase auth
 // then we would have to do some restructure.
@@ -83,13 +145,7 @@ pub async fn spawn_app_with_options(options: SpawnOptions) -> TestApp {
     tracing_subscriber::fmt()
         .with_env_filter("info")
         .try_init()
-        .unwrap_or_else(|_| {});
-
-    if env::var("JWT_SECRET").is_err() {
-        unsafe {
-            env::set_var("JWT_SECRET", "test-jwt-secret-do-not-use-in-prod");
-        }
-    }
+        .unwrap_or(());
 
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
@@ -105,11 +161,18 @@ pub async fn spawn_app_with_options(options: SpawnOptions) -> TestApp {
         }
     };
 
+    let jwt_helper = Arc::new(
+        JwtHelper::new(storage.clone(), KeyPurpose::Access)
+            .await
+            .expect("Failed to create JwtHelper"),
+    );
+
     let auth_service = match options.identity {
         IdentityBackend::Test => Auth::new(
             domain.clone(),
             LocalIdentityProvider::new(domain.clone(), storage.clone()),
             storage.clone(),
+            jwt_helper.clone(),
         ),
         #[cfg(feature = "auth-firebase")]
         IdentityBackend::Firebase => {
@@ -117,21 +180,90 @@ pub async fn spawn_app_with_options(options: SpawnOptions) -> TestApp {
             let firebase_auth = FirebaseAuth::new_from_env(domain.clone(), client.clone())
                 .await
                 .expect("Failed to create FirebaseAuth from env");
-            Auth::new(domain.clone(), firebase_auth, storage.clone())
+            Auth::new(
+                domain.clone(),
+                firebase_auth,
+                storage.clone(),
+                jwt_helper.clone(),
+            )
+        }
+        #[cfg(feature = "auth-local")]
+        IdentityBackend::Local => Auth::new(
+            domain.clone(),
+            LocalAuth::new(domain.clone(), storage.clone()),
+            storage.clone(),
+            jwt_helper.clone(),
+        ),
+        #[cfg(feature = "auth-local")]
+        IdentityBackend::Composite => {
+            let mut composite = CompositeIdentityProvider::new(storage.clone());
+            composite.add(
+                "test",
+                LocalIdentityProvider::new(domain.clone(), storage.clone()),
+            );
+            composite.add("local", LocalAuth::new(domain.clone(), storage.clone()));
+            composite
+                .route_email_domains("example.com=local")
+                .expect("Failed to route email domains");
+            Auth::new(
+                domain.clone(),
+                composite,
+                storage.clone(),
+                jwt_helper.clone(),
+            )
         }
     };
 
-    let notification_service = NotificationService::new(storage.clone())
+    // Retries are what is tested, not the wait between them
+    let push = MockPushService::start().await;
+    let mut notification_service = NotificationService::new(storage.clone())
         .await
-        .expect("Failed to create notification_service");
+        .expect("Failed to create notification_service")
+        .with_retry_backoff(Duration::from_millis(10))
+        .with_provider(PushProviderKind::Fcm, Arc::new(push.fcm_provider()))
+        .with_provider(PushProviderKind::Apns, Arc::new(push.apns_provider()));
+    if let Some(keys) = options.vapid_keys {
+        notification_service = notification_service.with_vapid_keys(keys);
+    }
+    let notification_service = Arc::new(notification_service);
+
+    let mut sockets = WebSocketService::new();
+    if let Some((ping_interval, idle_timeout)) = options.socket_heartbeat {
+        sockets = sockets.with_heartbeat(ping_interval, idle_timeout);
+    }
+    let sockets = if options.cluster {
+        let sockets = Arc::new(
+            sockets
+                .with_cluster(storage.socket_routes.clone())
+                .with_notifications(notification_service.clone()),
+        );
+        sockets
+            .clone()
+            .spawn_cluster_tasks()
+            .await
+            .expect("Failed to join the socket cluster");
+        sockets
+    } else {
+        Arc::new(sockets)
+    };
 
     let app_state = AppState {
         domain: domain.clone(),
         auth: Arc::new(auth_service),
         storage: storage.clone(),
-        sockets: Arc::new(WebSocketService::new()),
-        notification_service: Arc::new(notification_service),
+        sockets,
+        notification_service: notification_service.clone(),
         oidc_provider: None,
+        export_jwt: Arc::new(
+            JwtHelper::new(storage.clone(), KeyPurpose::ExportDownload)
+                .await
+                .expect("Failed to create export JwtHelper"),
+        ),
+        socket_ticket_jwt: Arc::new(
+            JwtHelper::new(storage.clone(), KeyPurpose::SocketTicket)
+                .await
+                .expect("Failed to create socket ticket JwtHelper"),
+        ),
     };
 
     let app_router = app(app_state, "ConnectInfo".to_string())
@@ -148,8 +280,10 @@ pub async fn spawn_app_with_options(options: SpawnOptions) -> TestApp {
 
     TestApp {
         address,
-        domain: domain,
-        storage: storage,
+        domain,
+        storage,
+        notifications: notification_service,
+        push,
         client: Client::new(),
     }
 }
@@ -233,6 +367,76 @@ impl TestApp {
 
         serde_json::from_str::<LoginResponse>(&body).expect("Failed to parse login response")
     }
+
+    /// Sends a refresh request the way the test client does
+    #[allow(dead_code)]
+    pub async fn refresh_http(&self, refresh_token: &Uuid) -> reqwest::Response {
+        self.client
+            .post(format!("{}/auth/v1/refresh", &self.address))
+            .header("User-Agent", "test-client")
+            .json(&serde_json::json!({ "refreshToken": refresh_token }))
+            .send()
+            .await
+            .expect("HTTP refresh failed")
+    }
+
+    /// Opens an authenticated websocket for a device
+    #[allow(dead_code)]
+    pub async fn connect_websocket(&self, token: &str) -> WsStream {
+        self.connect_websocket_to("/ws", token).await
+    }
+
+    /// Opens a websocket that resumes after the activity with sequence number `after`
+    #[allow(dead_code)]
+    pub async fn resume_websocket(&self, token: &str, after: i64) -> WsStream {
+        self.connect_websocket_to(&format!("/ws?after={}", after), token)
+            .await
+    }
+
+    async fn connect_websocket_to(&self, path: &str, token: &str) -> WsStream {
+        let ws_url = format!("{}{}", self.address.replace("http://", "ws://"), path);
+        let mut request = ws_url.into_client_request().unwrap();
+        request.headers_mut().insert(
+            "Authorization",
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        let (stream, _) = connect_async(request)
+            .await
+            .expect("WebSocket connection failed");
+        stream
+    }
+}
+
+#[allow(dead_code)]
+pub type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+#[allow(dead_code)]
+pub async fn send_frame(ws: &mut WsStream, frame: &Value) {
+    ws.send(Message::Text(frame.to_string())).await.unwrap();
+}
+
+/// The next text frame `filter` accepts, skipping any others, or `None` if none arrives
+/// within `wait`
+#[allow(dead_code)]
+pub async fn next_frame(
+    ws: &mut WsStream,
+    wait: Duration,
+    filter: impl Fn(&Value) -> bool,
+) -> Option<Value> {
+    timeout(wait, async {
+        while let Some(Ok(message)) = ws.next().await {
+            if let Message::Text(text) = message {
+                let frame = serde_json::from_str(&text).unwrap();
+                if filter(&frame) {
+                    return frame;
+                }
+            }
+        }
+        panic!("Socket closed before a frame arrived");
+    })
+    .await
+    .ok()
 }
 
 async fn postgres_pool() -> PgPool {
@@ -0,0 +1,291 @@
+use crate::common::TestApp;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri, Version},
+    response::IntoResponse,
+    routing::post,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use eko_messenger::notifications::{
+    providers::{ApnsProvider, FcmProvider},
+    vapid::VapidKey,
+};
+use openssl::{
+    bn::BigNumContext,
+    derive::Deriver,
+    ec::{EcGroup, EcKey, EcPoint, PointConversionForm},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+    rsa::Rsa,
+    sign::Signer,
+    symm::{Cipher, decrypt_aead},
+};
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::{net::TcpListener, time::sleep};
+
+pub const MOCK_FCM_PROJECT: &str = "test-project";
+pub const MOCK_FCM_ACCESS_TOKEN: &str = "mock-access-token";
+pub const MOCK_APNS_TOPIC: &str = "chat.eko.test";
+pub const MOCK_APNS_KEY_ID: &str = "TESTKEYID1";
+
+/// Scripted statuses, each with an optional `Retry-After`
+type Responses = Arc<Mutex<VecDeque<(u16, Option<String>)>>>;
+/// Private key and auth secret of each Web Push subscription handed out, by id
+type SubscriptionKeys = Arc<Mutex<HashMap<String, (EcKey<Private>, [u8; 16])>>>;
+
+/// A push received by the mock
+#[derive(Clone, Debug)]
+pub struct ReceivedPush {
+    pub path: String,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// A push service answering with scripted responses, then with 201 once they run out. Serves
+/// Web Push at `/push/{id}`, and the FCM and APNs APIs for the providers it hands out
+#[derive(Clone)]
+pub struct MockPushService {
+    pub address: String,
+    responses: Responses,
+    /// Every push received, in order
+    received: Arc<Mutex<Vec<ReceivedPush>>>,
+    token_requests: Arc<Mutex<u32>>,
+    subscription_keys: SubscriptionKeys,
+}
+
+#[allow(dead_code)]
+impl MockPushService {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let service = MockPushService {
+            address,
+            responses: Arc::default(),
+            received: Arc::default(),
+            token_requests: Arc::default(),
+            subscription_keys: Arc::default(),
+        };
+        let router = Router::new()
+            .route("/push/{id}", post(receive))
+            .route("/v1/projects/{project}/messages:send", post(receive))
+            .route("/3/device/{token}", post(receive))
+            .route("/token", post(access_token))
+            .with_state(service.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        service
+    }
+
+    /// Answers the next push with `status`, and a `Retry-After` if given
+    pub fn respond(&self, status: u16, retry_after: Option<&str>) {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back((status, retry_after.map(str::to_string)));
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.received.lock().unwrap().len() as u32
+    }
+
+    /// OAuth access tokens handed to the FCM provider
+    pub fn token_requests(&self) -> u32 {
+        *self.token_requests.lock().unwrap()
+    }
+
+    /// The `n`th push received, waiting for it as pushes are sent in the background
+    pub async fn received(&self, n: usize) -> ReceivedPush {
+        for _ in 0..100 {
+            if let Some(push) = self.received.lock().unwrap().get(n) {
+                return push.clone();
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Push {} was never received", n);
+    }
+
+    /// Headers of the `n`th push received
+    pub async fn headers(&self, n: usize) -> HeaderMap {
+        self.received(n).await.headers
+    }
+
+    /// Decrypted payload of the `n`th push, which must be a Web Push (RFC 8291)
+    pub async fn payload(&self, n: usize) -> String {
+        let push = self.received(n).await;
+        let id = push.path.trim_start_matches("/push/");
+        let (key, auth) = self.subscription_keys.lock().unwrap()[id].clone();
+
+        let body = &push.body;
+        let salt = &body[..16];
+        let id_len = body[20] as usize;
+        let server_public = &body[21..21 + id_len];
+        let ciphertext = &body[21 + id_len..];
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let point = EcPoint::from_bytes(&group, server_public, &mut ctx).unwrap();
+        let peer = PKey::from_ec_key(EcKey::from_public_key(&group, &point).unwrap()).unwrap();
+        let own_public = key
+            .public_key()
+            .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .unwrap();
+        let own = PKey::from_ec_key(key).unwrap();
+        let mut deriver = Deriver::new(&own).unwrap();
+        deriver.set_peer(&peer).unwrap();
+        let secret = deriver.derive_to_vec().unwrap();
+
+        let key_info = [b"WebPush: info\0".as_slice(), &own_public, server_public].concat();
+        let ikm = hkdf(&auth, &secret, &key_info, 32);
+        let cek = hkdf(salt, &ikm, b"Content-Encoding: aes128gcm\0", 16);
+        let nonce = hkdf(salt, &ikm, b"Content-Encoding: nonce\0", 12);
+        let (data, tag) = ciphertext.split_at(ciphertext.len() - 16);
+        let plain =
+            decrypt_aead(Cipher::aes_128_gcm(), &cek, Some(&nonce), &[], data, tag).unwrap();
+
+        // The last record ends in a 2 delimiter, then padding
+        let end = plain.iter().rposition(|&b| b == 2).unwrap();
+        String::from_utf8(plain[..end].to_vec()).unwrap()
+    }
+
+    /// An FCM provider sending to this service, with a service account of its own
+    pub fn fcm_provider(&self) -> FcmProvider {
+        static KEY: OnceLock<String> = OnceLock::new();
+        let private_key = KEY.get_or_init(|| {
+            let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+            String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap()
+        });
+        let credentials = json!({
+            "type": "service_account",
+            "project_id": MOCK_FCM_PROJECT,
+            "client_email": "push@test-project.iam.gserviceaccount.com",
+            "private_key": private_key,
+            "token_uri": format!("{}/token", self.address),
+        });
+        FcmProvider::new(
+            reqwest::Client::new(),
+            &credentials.to_string(),
+            self.address.clone(),
+        )
+        .unwrap()
+    }
+
+    /// An APNs provider sending to this service
+    pub fn apns_provider(&self) -> ApnsProvider {
+        static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+        let key = KEY.get_or_init(|| {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+            key.private_key_to_pem_pkcs8().unwrap()
+        });
+        ApnsProvider::new(
+            ApnsProvider::client(Duration::from_secs(10)).unwrap(),
+            key,
+            MOCK_APNS_KEY_ID.to_string(),
+            "TESTTEAMID".to_string(),
+            MOCK_APNS_TOPIC.to_string(),
+            self.address.clone(),
+        )
+        .unwrap()
+    }
+
+    /// A subscription at this service with real keys, so the payload can be encrypted
+    pub fn subscription(&self, id: &str) -> Value {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let p256dh = key
+            .public_key()
+            .to_bytes(&group, PointConversionForm::UNCOMPRESSED, &mut ctx)
+            .unwrap();
+        let mut auth = [0u8; 16];
+        rand_bytes(&mut auth).unwrap();
+        self.subscription_keys
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), (key, auth));
+        json!({
+            "endpoint": format!("{}/push/{}", self.address, id),
+            "keys": {
+                "p256dh": URL_SAFE_NO_PAD.encode(p256dh),
+                "auth": URL_SAFE_NO_PAD.encode(auth),
+            }
+        })
+    }
+}
+
+async fn receive(
+    State(service): State<MockPushService>,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    service.received.lock().unwrap().push(ReceivedPush {
+        path: uri.path().to_string(),
+        version,
+        headers,
+        body,
+    });
+    let (status, retry_after) = service
+        .responses
+        .lock()
+        .unwrap()
+        .pop_front()
+        .unwrap_or((201, None));
+    let status = StatusCode::from_u16(status).unwrap();
+    match retry_after {
+        Some(retry_after) => (status, [("Retry-After", retry_after)]).into_response(),
+        None => status.into_response(),
+    }
+}
+
+async fn access_token(State(service): State<MockPushService>) -> Json<Value> {
+    *service.token_requests.lock().unwrap() += 1;
+    Json(json!({
+        "access_token": MOCK_FCM_ACCESS_TOKEN,
+        "expires_in": 3600,
+        "token_type": "Bearer",
+    }))
+}
+
+/// Registers `body` as a push subscription of the device `token` belongs to
+pub async fn register_push(app: &TestApp, token: &str, body: &Value) -> reqwest::Response {
+    app.client
+        .post(format!("{}/push/register", &app.address))
+        .bearer_auth(token)
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// A new VAPID key, as if created at `VAPID_KEY_PATH`
+pub fn generate_vapid_key() -> VapidKey {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    VapidKey::from_pem(&key.private_key_to_pem_pkcs8().unwrap()).unwrap()
+}
+
+/// HKDF-SHA256 for outputs of at most one block
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let prk = hmac(salt, ikm);
+    let mut okm = hmac(&prk, &[info, &[1]].concat());
+    okm.truncate(len);
+    okm
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = PKey::hmac(key).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(data).unwrap();
+    signer.sign_to_vec().unwrap()
+}
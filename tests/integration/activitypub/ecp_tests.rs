@@ -30,4 +30,14 @@ async fn test_well_known_ecp() {
     );
 
     assert_field_equals(&capabilities, "protocol", &"eko-chat".into());
+
+    // Fallbacks for networks that block websockets
+    assert_has_field(&capabilities, "sse");
+    assert_has_field(&capabilities, "longPoll");
+    assert!(
+        capabilities["longPoll"]["endpoint"]
+            .as_str()
+            .unwrap()
+            .ends_with("/inbox/poll")
+    );
 }
@@ -0,0 +1,89 @@
+use crate::common::*;
+
+use serde_json::json;
+
+/// Test that email/password operations go to the provider routed for the email domain
+#[tokio::test]
+async fn test_composite_routes_by_email_domain() {
+    let app = spawn_app_composite().await;
+
+    // example.com is routed to the local password provider
+    let alice = TestUser::create(&app, "alice").await;
+    let hash = app
+        .storage
+        .users
+        .get_password_hash(&alice.uid)
+        .await
+        .unwrap();
+    assert!(hash.is_some(), "example.com users should have a password");
+
+    let login_req =
+        app.generate_login_request(alice.email.clone(), "wrong-password".to_string(), None);
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/login", &app.address))
+        .header("User-Agent", "test-client")
+        .json(&login_req)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+
+    // Everything else falls back to the first registered provider
+    app.signup_http("bob", "bob@other.test", "short").await;
+    let bob = app.login_http("bob@other.test", "anything").await;
+    let hash = app.storage.users.get_password_hash(&bob.uid).await.unwrap();
+    assert!(
+        hash.is_none(),
+        "other.test users should not have a password"
+    );
+}
+
+/// Test that users from every provider resolve through webfinger
+#[tokio::test]
+async fn test_composite_resolves_users_from_all_providers() {
+    let app = spawn_app_composite().await;
+
+    TestUser::create(&app, "alice").await;
+    app.signup_http("bob", "bob@other.test", "password").await;
+
+    let host = app.domain.trim_start_matches("http://");
+    for username in ["alice", "bob"] {
+        let response = app
+            .client
+            .get(format!("{}/.well-known/webfinger", &app.address))
+            .query(&[("resource", format!("acct:{}@{}", username, host))])
+            .send()
+            .await
+            .unwrap();
+        assert_status(response, 200).await;
+    }
+}
+
+/// Test that reset tokens reach the provider that stores passwords even when it is not the default
+#[tokio::test]
+async fn test_composite_reset_password() {
+    let app = spawn_app_composite().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let token = app
+        .storage
+        .users
+        .create_password_reset_token(
+            &alice.uid,
+            time::OffsetDateTime::now_utc() + time::Duration::hours(1),
+        )
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/password/reset", &app.address))
+        .json(&json!({ "token": token, "newPassword": "reset-password" }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 200).await;
+
+    app.login_http(&alice.email, "reset-password").await;
+}
@@ -2,15 +2,24 @@
 
 use crate::common::*;
 
-use eko_messenger::auth::{Auth, FirebaseAuth};
+use eko_messenger::auth::{Auth, FirebaseAuth, JwtHelper, KeyPurpose, RefreshOutcome};
 use std::env;
+use std::sync::Arc;
 
 /// Helper to get Firebase auth service (for tests that need it)
 async fn get_auth_service(app: &TestApp) -> Auth {
     let firebase_auth = FirebaseAuth::new_from_env(app.domain.clone(), reqwest::Client::new())
         .await
         .unwrap();
-    Auth::new(app.domain.clone(), firebase_auth, app.storage.clone())
+    let jwt_helper = JwtHelper::new(app.storage.clone(), KeyPurpose::Access)
+        .await
+        .unwrap();
+    Auth::new(
+        app.domain.clone(),
+        firebase_auth,
+        app.storage.clone(),
+        Arc::new(jwt_helper),
+    )
 }
 
 /// Test login and token verification with Firebase (requires env vars)
@@ -51,10 +60,13 @@ async fn test_firebase_refresh_token() {
         .await
         .unwrap();
 
-    let refresh_res = auth
+    let RefreshOutcome::Refreshed(refresh_res) = auth
         .refresh_token(&login_res.refresh_token, "127.0.0.1", "test-agent")
         .await
-        .unwrap();
+        .unwrap()
+    else {
+        panic!("Fresh refresh token was treated as reused");
+    };
 
     assert!(!refresh_res.access_token.is_empty());
 
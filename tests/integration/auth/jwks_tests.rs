@@ -0,0 +1,159 @@
+use crate::common::*;
+
+use eko_messenger::{
+    auth::{Claims, JwtHelper, KeyPurpose},
+    storage::models::StoredSigningKey,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use openssl::{
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::PKey,
+};
+use std::sync::Arc;
+
+async fn insert_key(
+    app: &TestApp,
+    purpose: KeyPurpose,
+    algorithm: Algorithm,
+    age: time::Duration,
+) -> String {
+    let private_key = match algorithm {
+        Algorithm::ES256 => {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+        }
+        _ => PKey::generate_ed25519().unwrap(),
+    };
+    let key = StoredSigningKey {
+        kid: uuid::Uuid::new_v4().to_string(),
+        purpose: purpose.as_str().to_string(),
+        algorithm: format!("{:?}", algorithm),
+        private_key: private_key.private_key_to_pkcs8().unwrap(),
+        created_at: time::OffsetDateTime::now_utc() - age,
+    };
+    app.storage
+        .signing_keys
+        .insert_signing_key(&key)
+        .await
+        .unwrap();
+    key.kid
+}
+
+fn kids(jwks: &JwkSet) -> Vec<String> {
+    jwks.keys
+        .iter()
+        .map(|k| k.common.key_id.clone().unwrap())
+        .collect()
+}
+
+/// Test that access tokens can be verified with nothing but the published JWKS
+#[tokio::test]
+async fn test_access_token_verifies_against_jwks() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let token = &alice.devices[0].token;
+
+    let jwks: JwkSet = app
+        .client
+        .get(format!("{}/.well-known/jwks.json", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let header = decode_header(token).unwrap();
+    assert_eq!(header.alg, Algorithm::EdDSA);
+    let kid = header.kid.expect("Access tokens should carry a kid");
+
+    let jwk = jwks.find(&kid).expect("Signing key should be published");
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_jwk(jwk).unwrap(),
+        &Validation::new(Algorithm::EdDSA),
+    )
+    .unwrap()
+    .claims;
+    assert_eq!(claims.sub, alice.uid);
+}
+
+/// Test that a rotated key keeps signing until its successor activates, and is deleted once
+/// its tokens have expired
+#[tokio::test]
+async fn test_signing_key_rotation() {
+    let app = spawn_app().await;
+    let purpose = KeyPurpose::OidcVerification;
+
+    let expired = insert_key(&app, purpose, Algorithm::EdDSA, time::Duration::days(60)).await;
+    let current = insert_key(&app, purpose, Algorithm::EdDSA, time::Duration::days(31)).await;
+
+    let helper = Arc::new(JwtHelper::new(app.storage.clone(), purpose).await.unwrap());
+
+    let published = kids(&helper.jwks());
+    assert!(!published.contains(&expired), "Expired key still published");
+    assert!(published.contains(&current), "Previous key must overlap");
+    assert_eq!(published.len(), 2, "A new key should have been created");
+
+    // The new key is not used until it has been published for a while
+    let claims = serde_json::json!({ "exp": usize::MAX });
+    let token = helper.sign(&claims).unwrap();
+    assert_eq!(decode_header(&token).unwrap().kid, Some(current.clone()));
+    helper.verify::<serde_json::Value>(&token).unwrap();
+
+    let stored = app
+        .storage
+        .signing_keys
+        .get_signing_keys(purpose.as_str())
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 2);
+}
+
+/// Test that ES256 keys are published and that changing the configured algorithm rotates
+#[tokio::test]
+async fn test_es256_key_and_algorithm_change() {
+    let app = spawn_app().await;
+    let purpose = KeyPurpose::OidcVerification;
+
+    let es256 = insert_key(&app, purpose, Algorithm::ES256, time::Duration::days(1)).await;
+
+    // The configured algorithm is EdDSA, so a new key is created straight away
+    let helper = JwtHelper::new(app.storage.clone(), purpose).await.unwrap();
+    let jwks = helper.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+
+    let token = helper
+        .sign(&serde_json::json!({ "exp": usize::MAX }))
+        .unwrap();
+    let header = decode_header(&token).unwrap();
+    assert_eq!(header.alg, Algorithm::ES256);
+    assert_eq!(header.kid, Some(es256.clone()));
+
+    let jwk = jwks.find(&es256).unwrap();
+    decode::<serde_json::Value>(
+        &token,
+        &DecodingKey::from_jwk(jwk).unwrap(),
+        &Validation::new(Algorithm::ES256),
+    )
+    .unwrap();
+}
+
+/// Test that tokens signed for one purpose are rejected for another
+#[tokio::test]
+async fn test_keys_are_separated_by_purpose() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let verification = JwtHelper::new(app.storage.clone(), KeyPurpose::OidcVerification)
+        .await
+        .unwrap();
+    assert!(
+        verification
+            .verify::<Claims>(&alice.devices[0].token)
+            .is_err()
+    );
+}
@@ -0,0 +1,245 @@
+use crate::common::*;
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use eko_messenger::{
+    auth::{IdentityProvider, LocalAuth, ResetTokenSender},
+    errors::AppError,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Keeps the reset tokens it is handed, as a mail transport would send them
+#[derive(Clone, Default)]
+struct RecordingSender(Arc<Mutex<Vec<(String, Uuid)>>>);
+
+#[async_trait]
+impl ResetTokenSender for RecordingSender {
+    async fn send_reset_token(&self, email: &str, token: Uuid) -> Result<(), AppError> {
+        self.0.lock().unwrap().push((email.to_string(), token));
+        Ok(())
+    }
+}
+
+async fn try_login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    let login_req = app.generate_login_request(email.to_string(), password.to_string(), None);
+    app.client
+        .post(format!("{}/auth/v1/login", &app.address))
+        .header("User-Agent", "test-client")
+        .json(&login_req)
+        .send()
+        .await
+        .expect("Login request failed")
+}
+
+/// Test signup and login against the Argon2 backed provider
+#[tokio::test]
+async fn test_local_signup_and_login() {
+    let app = spawn_app_local().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    assert!(!alice.devices[0].token.is_empty());
+    let hash = app
+        .storage
+        .users
+        .get_password_hash(&alice.uid)
+        .await
+        .unwrap()
+        .expect("Local users should have a password hash");
+    assert!(hash.starts_with("$argon2id$"), "Unexpected hash: {}", hash);
+}
+
+/// Test that a wrong password is rejected
+#[tokio::test]
+async fn test_local_login_wrong_password() {
+    let app = spawn_app_local().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let response = try_login(&app, &alice.email, "not-the-password").await;
+    assert_status(response, 401).await;
+
+    let response = try_login(&app, "nobody@example.com", "password").await;
+    assert_status(response, 401).await;
+}
+
+/// Test that signup enforces the minimum password length
+#[tokio::test]
+async fn test_local_signup_short_password() {
+    let app = spawn_app_local().await;
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/signup", &app.address))
+        .json(&json!({
+            "username": "alice",
+            "email": "alice@example.com",
+            "password": "short",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_status(response, 400).await;
+}
+
+/// Test changing the password of the authenticated user, which signs out their other devices
+#[tokio::test]
+async fn test_local_change_password() {
+    let app = spawn_app_local().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let url = format!("{}/auth/v1/password", &app.address);
+
+    let response = app
+        .client
+        .post(&url)
+        .bearer_auth(&alice.devices[0].token)
+        .json(&json!({
+            "currentPassword": "wrong-password",
+            "newPassword": "new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+
+    let response = app
+        .client
+        .post(&url)
+        .bearer_auth(&alice.devices[0].token)
+        .json(&json!({
+            "currentPassword": alice.password,
+            "newPassword": "new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 200).await;
+
+    let response = try_login(&app, &alice.email, &alice.password).await;
+    assert_status(response, 401).await;
+
+    let response = try_login(&app, &alice.email, "new-password").await;
+    assert_status(response, 200).await;
+
+    assert_success(app.refresh_http(&alice.devices[0].refresh_token).await).await;
+    assert_status(app.refresh_http(&alice.devices[1].refresh_token).await, 401).await;
+}
+
+/// Test the reset token flow, including that tokens are single use and sessions are revoked
+#[tokio::test]
+async fn test_local_reset_password() {
+    let app = spawn_app_local().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    // Unknown emails are accepted so accounts cannot be enumerated
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/password/reset-request", &app.address))
+        .json(&json!({ "email": "nobody@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 202).await;
+
+    let token = app
+        .storage
+        .users
+        .create_password_reset_token(
+            &alice.uid,
+            time::OffsetDateTime::now_utc() + time::Duration::hours(1),
+        )
+        .await
+        .unwrap();
+
+    let reset_url = format!("{}/auth/v1/password/reset", &app.address);
+    let body = json!({ "token": token, "newPassword": "reset-password" });
+
+    let response = app
+        .client
+        .post(&reset_url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 200).await;
+
+    let response = app
+        .client
+        .post(&reset_url)
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+
+    let response = try_login(&app, &alice.email, "reset-password").await;
+    assert_status(response, 200).await;
+
+    // Every session from before the reset is revoked
+    assert_status(app.refresh_http(&alice.devices[0].refresh_token).await, 401).await;
+}
+
+/// Test that reset tokens are handed to the configured sender, and only for known accounts
+#[tokio::test]
+async fn test_local_reset_token_sender() {
+    let app = spawn_app_local().await;
+    let alice = TestUser::create(&app, "alice").await;
+
+    let sender = RecordingSender::default();
+    let local =
+        LocalAuth::new(app.domain.clone(), app.storage.clone()).with_reset_sender(sender.clone());
+    local
+        .request_password_reset("nobody@example.com".to_string())
+        .await
+        .unwrap();
+    local
+        .request_password_reset(alice.email.clone())
+        .await
+        .unwrap();
+
+    let sent = sender.0.lock().unwrap().clone();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, alice.email);
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/password/reset", &app.address))
+        .json(&json!({ "token": sent[0].1, "newPassword": "reset-password" }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 200).await;
+}
+
+/// Test that expired reset tokens are rejected
+#[tokio::test]
+async fn test_local_reset_password_expired_token() {
+    let app = spawn_app_local().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let token = app
+        .storage
+        .users
+        .create_password_reset_token(
+            &alice.uid,
+            time::OffsetDateTime::now_utc() - time::Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/password/reset", &app.address))
+        .json(&json!({ "token": token, "newPassword": "reset-password" }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+}
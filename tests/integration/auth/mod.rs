@@ -1,3 +1,11 @@
+#[cfg(feature = "auth-local")]
+pub mod composite_tests;
 #[cfg(feature = "auth-firebase")]
 pub mod firebase_login_tests;
+pub mod jwks_tests;
+#[cfg(feature = "auth-local")]
+pub mod local_login_tests;
 pub mod login_tests;
+pub mod oidc_state_tests;
+pub mod refresh_tests;
+pub mod username_tests;
@@ -0,0 +1,60 @@
+use crate::common::*;
+
+use eko_messenger::storage::{
+    memory::MemoryAuthStateStore, models::StoredAuthState, traits::AuthStateStore,
+};
+use time::{Duration, OffsetDateTime};
+
+fn auth_state(csrf_token: &str, expires_in: Duration) -> StoredAuthState {
+    StoredAuthState {
+        csrf_token: csrf_token.to_string(),
+        nonce: "nonce".to_string(),
+        pkce_verifier: "verifier".to_string(),
+        provider: "default".to_string(),
+        expires_at: OffsetDateTime::now_utc() + expires_in,
+    }
+}
+
+/// States can be taken once, and the sweep only removes expired ones
+async fn check_auth_state_store(store: &dyn AuthStateStore) {
+    let live = uuid::Uuid::new_v4().to_string();
+    let expired = uuid::Uuid::new_v4().to_string();
+    store
+        .insert_auth_state(&auth_state(&live, Duration::minutes(15)))
+        .await
+        .unwrap();
+    store
+        .insert_auth_state(&auth_state(&expired, Duration::minutes(-1)))
+        .await
+        .unwrap();
+
+    assert!(
+        store
+            .delete_expired_auth_states(OffsetDateTime::now_utc())
+            .await
+            .unwrap()
+            >= 1
+    );
+    assert!(store.take_auth_state(&expired).await.unwrap().is_none());
+
+    let taken = store
+        .take_auth_state(&live)
+        .await
+        .unwrap()
+        .expect("Live state should be stored");
+    assert_eq!(taken.pkce_verifier, "verifier");
+    assert_eq!(taken.provider, "default");
+
+    assert!(store.take_auth_state(&live).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_postgres_auth_state_store() {
+    let app = spawn_app().await;
+    check_auth_state_store(app.storage.auth_states.as_ref()).await;
+}
+
+#[tokio::test]
+async fn test_memory_auth_state_store() {
+    check_auth_state_store(&MemoryAuthStateStore::new()).await;
+}
@@ -0,0 +1,75 @@
+use crate::common::*;
+
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Test that refresh tokens rotate and the new token keeps working
+#[tokio::test]
+async fn test_refresh_token_rotation() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let response = assert_success(app.refresh_http(&alice.devices[0].refresh_token).await).await;
+    let body: Value = response.json().await.unwrap();
+    let rotated: uuid::Uuid = serde_json::from_value(body["refreshToken"].clone()).unwrap();
+    assert_ne!(rotated, alice.devices[0].refresh_token);
+
+    assert_success(app.refresh_http(&rotated).await).await;
+}
+
+/// Test that presenting a rotated token revokes the whole family, including the token the
+/// legitimate client holds
+#[tokio::test]
+async fn test_refresh_token_reuse_revokes_family() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let stolen = alice.devices[0].refresh_token;
+
+    let response = assert_success(app.refresh_http(&stolen).await).await;
+    let body: Value = response.json().await.unwrap();
+    let current: uuid::Uuid = serde_json::from_value(body["refreshToken"].clone()).unwrap();
+
+    assert_status(app.refresh_http(&stolen).await, 401).await;
+    assert_status(app.refresh_http(&current).await, 401).await;
+}
+
+/// Test that reuse closes the device's websocket and tells the other devices
+#[tokio::test]
+async fn test_refresh_token_reuse_notifies_devices() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let stolen = alice.devices[0].refresh_token;
+
+    let mut phone = app.connect_websocket(&alice.devices[0].token).await;
+    let mut laptop = app.connect_websocket(&alice.devices[1].token).await;
+    // Let the server register both sockets
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_success(app.refresh_http(&stolen).await).await;
+    assert_status(app.refresh_http(&stolen).await, 401).await;
+
+    let closed = timeout(Duration::from_secs(2), phone.next())
+        .await
+        .expect("Compromised device socket was not closed");
+    assert!(
+        matches!(closed, Some(Ok(Message::Close(_))) | None),
+        "Expected close frame, got {:?}",
+        closed
+    );
+
+    let event = timeout(Duration::from_secs(2), laptop.next())
+        .await
+        .expect("Other device was not notified")
+        .unwrap()
+        .unwrap();
+    let event: Value = serde_json::from_str(event.to_text().unwrap()).unwrap();
+    assert_field_equals(&event, "type", &"RefreshTokenReused".into());
+    assert_field_equals(&event, "did", &alice.devices[0].url.clone().into());
+}
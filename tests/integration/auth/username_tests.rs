@@ -0,0 +1,92 @@
+use crate::common::*;
+
+use eko_messenger::errors::AppError;
+use serde_json::{Value, json};
+
+async fn check_username(app: &TestApp, username: &str) -> Value {
+    let response = app
+        .client
+        .get(format!("{}/auth/v1/username-availability", &app.address))
+        .query(&[("username", username)])
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await.json().await.unwrap()
+}
+
+/// Test the availability endpoint for free, taken and invalid names
+#[tokio::test]
+async fn test_username_availability() {
+    let app = spawn_app().await;
+
+    let _alice = TestUser::create(&app, "alice").await;
+
+    let body = check_username(&app, "bob").await;
+    assert_field_equals(&body, "available", &Value::Bool(true));
+    assert_field_equals(&body, "reason", &Value::Null);
+
+    for username in [
+        "alice", "admin", "ab", "Alice", "al-ice", "_alice", "1alice",
+    ] {
+        let body = check_username(&app, username).await;
+        assert_field_equals(&body, "available", &Value::Bool(false));
+        assert!(
+            body["reason"].is_string(),
+            "{} should have a reason",
+            username
+        );
+    }
+}
+
+/// Test that signup rejects usernames that fail validation
+#[tokio::test]
+async fn test_signup_rejects_invalid_username() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/signup", &app.address))
+        .json(&json!({
+            "username": "root",
+            "email": "root@example.com",
+            "password": "password",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_status(response, 400).await;
+}
+
+/// Test that concurrent signups for one username give the losers a 400 rather than a 500
+#[tokio::test]
+async fn test_concurrent_signups_for_username() {
+    let app = spawn_app().await;
+
+    let signups = (0..4).map(|i| {
+        app.client
+            .post(format!("{}/auth/v1/signup", &app.address))
+            .json(&json!({
+                "username": "carol",
+                "email": format!("carol{}@example.com", i),
+                "password": "password",
+            }))
+            .send()
+    });
+    let mut statuses: Vec<u16> = futures::future::join_all(signups)
+        .await
+        .into_iter()
+        .map(|response| response.unwrap().status().as_u16())
+        .collect();
+    statuses.sort();
+    assert_eq!(statuses, [201, 400, 400, 400]);
+
+    // The insert itself, as reached by a signup that passed the check before the winner's
+    let error = app
+        .storage
+        .users
+        .create_oidc_user("uid", "carol", "late@example.com", "issuer", "sub")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, AppError::BadRequest(_)), "{:?}", error);
+}
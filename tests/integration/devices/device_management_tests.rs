@@ -0,0 +1,175 @@
+use crate::common::*;
+
+use eko_messenger::activitypub::types::eko_types::DeviceAction;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+async fn list_devices(app: &TestApp, user: &TestUser, device_index: usize) -> Vec<Value> {
+    let response = app
+        .client
+        .get(format!("{}/users/{}/devices", &app.address, user.uid))
+        .bearer_auth(&user.devices[device_index].token)
+        .send()
+        .await
+        .unwrap();
+    let response = assert_success(response).await;
+    response.json().await.unwrap()
+}
+
+async fn revoke_device(
+    app: &TestApp,
+    user: &TestUser,
+    device_index: usize,
+    did: &str,
+) -> reqwest::Response {
+    app.client
+        .delete(format!(
+            "{}/users/{}/devices/{}",
+            &app.address, user.uid, did
+        ))
+        .bearer_auth(&user.devices[device_index].token)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Test that the device list carries names, refresh details and live status
+#[tokio::test]
+async fn test_list_devices() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+
+    let _socket = app.connect_websocket(&alice.devices[1].token).await;
+    // Let the server register the socket
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let devices = list_devices(&app, &alice, 0).await;
+    assert_eq!(devices.len(), 2);
+
+    assert_field_equals(
+        &devices[0],
+        "id",
+        &Value::from(alice.devices[0].url.clone()),
+    );
+    assert_field_equals(&devices[0], "current", &Value::Bool(true));
+    assert_field_equals(&devices[0], "online", &Value::Bool(false));
+    assert_field_equals(&devices[0], "approved", &Value::Bool(true));
+
+    assert_field_equals(
+        &devices[1],
+        "id",
+        &Value::from(alice.devices[1].url.clone()),
+    );
+    assert_field_equals(&devices[1], "deviceName", &Value::from("laptop"));
+    assert_field_equals(&devices[1], "lastUserAgent", &Value::from("test-client"));
+    assert_field_equals(&devices[1], "current", &Value::Bool(false));
+    assert_field_equals(&devices[1], "online", &Value::Bool(true));
+    assert_has_field(&devices[1], "createdAt");
+    assert_has_field(&devices[1], "lastRefreshedAt");
+}
+
+/// Test that revoking a device publishes a revoke action, closes its socket and kills its
+/// tokens
+#[tokio::test]
+async fn test_revoke_device() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+
+    let mut laptop = app.connect_websocket(&alice.devices[1].token).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let laptop_did = alice.devices[1].id.to_string();
+    assert_status(revoke_device(&app, &alice, 0, &laptop_did).await, 204).await;
+
+    let closed = timeout(Duration::from_secs(2), laptop.next())
+        .await
+        .expect("Revoked device socket was not closed");
+    assert!(matches!(closed, Some(Ok(Message::Close(_))) | None));
+
+    assert_status(app.refresh_http(&alice.devices[1].refresh_token).await, 401).await;
+
+    let devices = list_devices(&app, &alice, 0).await;
+    assert_eq!(devices.len(), 1);
+
+    let actions = app
+        .storage
+        .devices
+        .device_actions_for_user(&alice.uid)
+        .await
+        .unwrap();
+    assert!(
+        actions.iter().any(|a| matches!(
+            a,
+            DeviceAction::RevokeDevice(r) if r.did == alice.devices[1].url
+        )),
+        "Revocation should be published as a device action"
+    );
+
+    assert_status(revoke_device(&app, &alice, 0, &laptop_did).await, 404).await;
+}
+
+/// Test that logging out revokes the device and closes its socket
+#[tokio::test]
+async fn test_logout_closes_socket() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let mut socket = app.connect_websocket(&alice.devices[0].token).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = app
+        .client
+        .post(format!("{}/auth/v1/logout", &app.address))
+        .bearer_auth(&alice.devices[0].token)
+        .json(&serde_json::json!({ "refreshToken": alice.devices[0].refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await;
+
+    let closed = timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("Logged out device socket was not closed");
+    assert!(matches!(closed, Some(Ok(Message::Close(_))) | None));
+    assert_status(app.refresh_http(&alice.devices[0].refresh_token).await, 401).await;
+}
+
+/// Test that users cannot see or revoke each other's devices
+#[tokio::test]
+async fn test_device_management_forbidden() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = app
+        .client
+        .get(format!("{}/users/{}/devices", &app.address, alice.uid))
+        .bearer_auth(&bob.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 403).await;
+
+    let response = app
+        .client
+        .delete(format!(
+            "{}/users/{}/devices/{}",
+            &app.address, alice.uid, alice.devices[0].id
+        ))
+        .bearer_auth(&bob.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 403).await;
+
+    let response = revoke_device(&app, &bob, 0, &alice.devices[0].id.to_string()).await;
+    assert_status(response, 404).await;
+}
@@ -0,0 +1 @@
+pub mod device_management_tests;
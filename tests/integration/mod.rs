@@ -1,5 +1,8 @@
 pub mod activitypub;
 pub mod auth;
+pub mod devices;
 pub mod groups;
 pub mod messaging;
+pub mod notifications;
+pub mod users;
 pub mod websocket;
@@ -0,0 +1,233 @@
+use crate::common::*;
+
+use async_trait::async_trait;
+use eko_messenger::{
+    auth::{JwtHelper, KeyPurpose},
+    devices::DeviceId,
+    errors::AppError,
+    notifications::{NotificationService, PushKind, PushProviderKind, PushSubscription},
+    storage::{Storage, models::StoredSubscription, traits::NotificationStore},
+};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+async fn subscriptions(app: &TestApp, device: &TestDevice) -> Vec<StoredSubscription> {
+    app.storage
+        .notifications
+        .get_endpoints(device.id)
+        .await
+        .unwrap()
+}
+
+/// A store that cannot record push outcomes, as when the database fails mid wake
+struct FailingOutcomes(Arc<dyn NotificationStore>);
+
+#[async_trait]
+impl NotificationStore for FailingOutcomes {
+    async fn upsert_endpoint(
+        &self,
+        did: DeviceId,
+        endpoint: &PushSubscription,
+    ) -> Result<(), AppError> {
+        self.0.upsert_endpoint(did, endpoint).await
+    }
+    async fn assign_vapid_key(&self, public_key: &str) -> Result<u64, AppError> {
+        self.0.assign_vapid_key(public_key).await
+    }
+    async fn delete_endpoint(&self, _did: DeviceId, _endpoint: &str) -> Result<bool, AppError> {
+        Err(anyhow::anyhow!("Database unavailable").into())
+    }
+    async fn delete_endpoints(&self, did: DeviceId) -> Result<u64, AppError> {
+        self.0.delete_endpoints(did).await
+    }
+    async fn get_endpoints(&self, did: DeviceId) -> Result<Vec<StoredSubscription>, AppError> {
+        self.0.get_endpoints(did).await
+    }
+    async fn record_push_success(&self, _endpoint: &str) -> Result<(), AppError> {
+        Err(anyhow::anyhow!("Database unavailable").into())
+    }
+    async fn record_push_failure(
+        &self,
+        _endpoint: &str,
+        _retry_after: Option<OffsetDateTime>,
+    ) -> Result<(), AppError> {
+        Err(anyhow::anyhow!("Database unavailable").into())
+    }
+}
+
+/// Test that only a push service saying the subscription is gone gets it deleted
+#[tokio::test]
+async fn test_gone_subscription_is_pruned() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    assert_success(register_push(&app, &device.token, &push.subscription("gone")).await).await;
+    assert_success(register_push(&app, &device.token, &push.subscription("working")).await).await;
+
+    // Endpoints are tried in order, so "gone" answers first
+    push.respond(410, None);
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+
+    let remaining = subscriptions(&app, device).await;
+    assert_eq!(remaining.len(), 1);
+    assert!(remaining[0].subscription.endpoint.ends_with("/working"));
+    assert_eq!(push.hits(), 2);
+}
+
+/// Test that a rate limited subscription is kept and left alone until its Retry-After, or
+/// until it is registered again
+#[tokio::test]
+async fn test_rate_limited_subscription_waits() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    assert_success(register_push(&app, &device.token, &push.subscription("limited")).await).await;
+
+    push.respond(429, Some("120"));
+    assert!(
+        app.notifications
+            .notify(device.id, PushKind::Message)
+            .await
+            .is_err()
+    );
+
+    let stored = subscriptions(&app, device).await;
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].failure_count, 1);
+    assert!(stored[0].retry_after.is_some());
+    assert_eq!(push.hits(), 1);
+
+    // Skipped while the push service asked for quiet
+    assert!(
+        app.notifications
+            .notify(device.id, PushKind::Message)
+            .await
+            .is_err()
+    );
+    assert_eq!(push.hits(), 1);
+
+    assert_success(register_push(&app, &device.token, &push.subscription("limited")).await).await;
+    let stored = subscriptions(&app, device).await;
+    assert_eq!(stored[0].failure_count, 0);
+    assert!(stored[0].last_failure_at.is_none());
+    assert!(stored[0].retry_after.is_none());
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+    assert_eq!(push.hits(), 2);
+}
+
+/// Test that server errors are retried and a later success clears the failures
+#[tokio::test]
+async fn test_server_errors_retried() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    assert_success(register_push(&app, &device.token, &push.subscription("flaky")).await).await;
+
+    // Every attempt of the first wake fails
+    for _ in 0..3 {
+        push.respond(503, None);
+    }
+    assert!(
+        app.notifications
+            .notify(device.id, PushKind::Message)
+            .await
+            .is_err()
+    );
+    assert_eq!(push.hits(), 3);
+    let stored = subscriptions(&app, device).await;
+    assert_eq!(stored[0].failure_count, 1);
+    assert!(stored[0].retry_after.is_none());
+
+    push.respond(500, Some("0"));
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+    assert_eq!(push.hits(), 5);
+    assert_eq!(subscriptions(&app, device).await[0].failure_count, 0);
+
+    let metrics = app.notifications.metrics();
+    assert_eq!(metrics.attempts, 5);
+    assert_eq!(metrics.delivered, 1);
+    assert_eq!(metrics.unavailable, 4);
+    assert_eq!(metrics.retries, 3);
+    assert_eq!(metrics.success_rate, Some(0.2));
+}
+
+/// Test that push metrics are served to admins only
+#[tokio::test]
+async fn test_push_metrics_endpoint() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    assert_success(register_push(&app, &device.token, &push.subscription("device")).await).await;
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+
+    let metrics = |token: String| {
+        app.client
+            .get(format!("{}/push/metrics", &app.address))
+            .bearer_auth(token)
+            .send()
+    };
+    assert_status(metrics(device.token.clone()).await.unwrap(), 403).await;
+
+    // A token as issued to a uid listed in ADMIN_UIDS
+    let jwt = JwtHelper::new(app.storage.clone(), KeyPurpose::Access)
+        .await
+        .unwrap()
+        .with_admins([alice.uid.clone()]);
+    let token = jwt.create_jwt(&alice.uid, device.id).unwrap();
+    let response = metrics(token).await.unwrap();
+    let metrics: Value = assert_success(response).await.json().await.unwrap();
+    assert_eq!(metrics["delivered"], 1);
+    assert_eq!(metrics["successRate"], 1.0);
+}
+
+/// Test that failing to record an outcome neither skips the device's other subscriptions nor
+/// fails a wake that went through
+#[tokio::test]
+async fn test_outcome_bookkeeping_failure() {
+    let app = spawn_app().await;
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    for token in ["a-token", "b-token", "c-token"] {
+        let body = json!({ "provider": "fcm", "token": token });
+        assert_success(register_push(&app, &device.token, &body).await).await;
+    }
+
+    let storage = Storage {
+        notifications: Arc::new(FailingOutcomes(app.storage.notifications.clone())),
+        ..(*app.storage).clone()
+    };
+    let notifications = NotificationService::new(Arc::new(storage))
+        .await
+        .unwrap()
+        .with_provider(PushProviderKind::Fcm, Arc::new(app.push.fcm_provider()));
+
+    // Tokens are tried in order: one rejected, one gone and one delivered
+    app.push.respond(400, None);
+    app.push.respond(410, None);
+    notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+    assert_eq!(app.push.hits(), 3);
+}
@@ -0,0 +1,5 @@
+pub mod delivery_tests;
+pub mod policy_tests;
+pub mod provider_tests;
+pub mod subscription_tests;
+pub mod vapid_tests;
@@ -0,0 +1,98 @@
+use crate::common::*;
+
+use eko_messenger::{
+    activitypub::Activity,
+    notifications::{PushKind, PushPolicy},
+};
+use reqwest::header::HeaderMap;
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).map(|value| value.to_str().unwrap())
+}
+
+/// Test that a message wakes the offline recipient at high urgency under its device topic
+#[tokio::test]
+async fn test_message_push_is_urgent() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    assert_success(register_push(&app, &bob.devices[0].token, &push.subscription("bob")).await)
+        .await;
+
+    assert_success(alice.send_message_to(&app, &bob, "hello").await).await;
+
+    let headers = push.headers(0).await;
+    let policy = PushPolicy::default();
+    assert_eq!(header(&headers, "urgency"), Some("high"));
+    assert_eq!(
+        header(&headers, "ttl"),
+        Some(policy.message.ttl.to_string().as_str())
+    );
+    let topic = policy.topic(bob.devices[0].id, PushKind::Message).unwrap();
+    assert!(topic.len() <= 32);
+    assert_eq!(header(&headers, "topic"), Some(topic.as_str()));
+}
+
+/// Test that a message sent with notify false still wakes the device, but at low urgency
+#[tokio::test]
+async fn test_silent_message_push_is_low_urgency() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    assert_success(register_push(&app, &bob.devices[0].token, &push.subscription("bob")).await)
+        .await;
+
+    let envelope = SignalEnvelope::new()
+        .add_messages_for_all_devices(alice.devices[0].url.clone(), &bob, "edited")
+        .build_message(&alice.actor_id, &bob.actor_id);
+    let mut activity = alice.create_message_activity(envelope);
+    if let Activity::Create(create) = &mut activity {
+        create.notify = Some(false);
+    }
+    assert_success(alice.post_to_outbox(&app, activity).await).await;
+
+    let headers = push.headers(0).await;
+    assert_eq!(header(&headers, "urgency"), Some("low"));
+    let topic = PushPolicy::default()
+        .topic(bob.devices[0].id, PushKind::SilentMessage)
+        .unwrap();
+    assert_eq!(header(&headers, "topic"), Some(topic.as_str()));
+
+    // The flag reaches the recipient
+    let inbox = bob.get_inbox(&app).await;
+    assert_eq!(inbox["orderedItems"][0]["notify"], false);
+}
+
+/// Test that a delivery receipt wakes the offline sender in the background
+#[tokio::test]
+async fn test_delivered_push_is_background() {
+    let app = spawn_app().await;
+    let push = MockPushService::start().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    assert_success(register_push(&app, &alice.devices[0].token, &push.subscription("alice")).await)
+        .await;
+
+    let response = assert_success(alice.send_message_to(&app, &bob, "hello").await).await;
+    let create: serde_json::Value = response.json().await.unwrap();
+    let create_id = create["id"].as_str().unwrap();
+    assert_success(bob.send_delivered(&app, create_id, &alice).await).await;
+
+    let headers = push.headers(0).await;
+    let policy = PushPolicy::default();
+    assert_eq!(header(&headers, "urgency"), Some("low"));
+    assert_eq!(
+        header(&headers, "ttl"),
+        Some(policy.background.ttl.to_string().as_str())
+    );
+    let topic = policy
+        .topic(alice.devices[0].id, PushKind::Background)
+        .unwrap();
+    assert_eq!(header(&headers, "topic"), Some(topic.as_str()));
+    assert_eq!(push.hits(), 1);
+}
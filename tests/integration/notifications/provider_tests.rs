@@ -0,0 +1,145 @@
+use crate::common::*;
+
+use axum::http::Version;
+use eko_messenger::notifications::{PushKind, PushPolicy, PushProviderKind};
+use serde_json::{Value, json};
+
+/// Test that an FCM token is woken through the HTTP v1 API with a cached access token
+#[tokio::test]
+async fn test_fcm_push() {
+    let app = spawn_app().await;
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+
+    let body = json!({ "provider": "fcm", "token": "fcm-device-token" });
+    assert_success(register_push(&app, &device.token, &body).await).await;
+    let stored = app
+        .storage
+        .notifications
+        .get_endpoints(device.id)
+        .await
+        .unwrap();
+    assert_eq!(stored[0].subscription.provider, PushProviderKind::Fcm);
+    assert!(stored[0].subscription.keys.is_none());
+
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+    app.notifications
+        .notify(device.id, PushKind::Background)
+        .await
+        .unwrap();
+
+    let push = app.push.received(0).await;
+    assert_eq!(
+        push.path,
+        format!("/v1/projects/{}/messages:send", MOCK_FCM_PROJECT)
+    );
+    assert_eq!(
+        push.headers["authorization"],
+        format!("Bearer {}", MOCK_FCM_ACCESS_TOKEN).as_str()
+    );
+    let message: Value = serde_json::from_slice(&push.body).unwrap();
+    let topic = PushPolicy::default()
+        .topic(device.id, PushKind::Message)
+        .unwrap();
+    assert_eq!(message["message"]["token"], "fcm-device-token");
+    assert_eq!(message["message"]["data"]["type"], "wake");
+    assert_eq!(message["message"]["android"]["priority"], "HIGH");
+    assert_eq!(message["message"]["android"]["collapse_key"], topic);
+
+    let push = app.push.received(1).await;
+    let message: Value = serde_json::from_slice(&push.body).unwrap();
+    assert_eq!(message["message"]["android"]["priority"], "NORMAL");
+    assert_eq!(app.push.token_requests(), 1);
+}
+
+/// Test that an APNs token is woken over HTTP/2 with a signed provider token, and pruned once
+/// unregistered
+#[tokio::test]
+async fn test_apns_push() {
+    let app = spawn_app().await;
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+
+    let body = json!({ "provider": "apns", "token": "apns-device-token" });
+    assert_success(register_push(&app, &device.token, &body).await).await;
+
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+    let push = app.push.received(0).await;
+    assert_eq!(push.path, "/3/device/apns-device-token");
+    assert_eq!(push.version, Version::HTTP_2);
+    assert_eq!(push.headers["apns-topic"], MOCK_APNS_TOPIC);
+    assert_eq!(push.headers["apns-push-type"], "alert");
+    assert_eq!(push.headers["apns-priority"], "10");
+    let token = push.headers["authorization"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("Bearer ");
+    let header = jsonwebtoken::decode_header(token).unwrap();
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::ES256);
+    assert_eq!(header.kid.as_deref(), Some(MOCK_APNS_KEY_ID));
+
+    // Unregistered tokens are answered with 410
+    app.push.respond(410, None);
+    assert!(
+        app.notifications
+            .notify(device.id, PushKind::Background)
+            .await
+            .is_err()
+    );
+    let push = app.push.received(1).await;
+    assert_eq!(push.headers["apns-push-type"], "background");
+    assert_eq!(push.headers["apns-priority"], "5");
+    let stored = app
+        .storage
+        .notifications
+        .get_endpoints(device.id)
+        .await
+        .unwrap();
+    assert!(stored.is_empty());
+}
+
+/// Test that registrations must match their provider and that providers are advertised
+#[tokio::test]
+async fn test_register_validates_provider() {
+    let app = spawn_app().await;
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+
+    let missing_keys = json!({ "endpoint": format!("{}/push/web", app.push.address) });
+    assert_status(register_push(&app, &device.token, &missing_keys).await, 400).await;
+    let mut token_and_keys = app.push.subscription("web");
+    token_and_keys["provider"] = json!("fcm");
+    token_and_keys["token"] = json!("fcm-device-token");
+    assert_status(
+        register_push(&app, &device.token, &token_and_keys).await,
+        400,
+    )
+    .await;
+    let unknown = json!({ "provider": "mqtt", "token": "token" });
+    assert!(
+        register_push(&app, &device.token, &unknown)
+            .await
+            .status()
+            .is_client_error()
+    );
+
+    let capabilities: Value = app
+        .client
+        .get(format!("{}/.well-known/ecp", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        capabilities["webpush"]["providers"],
+        json!(["apns", "fcm", "webpush"])
+    );
+}
@@ -0,0 +1,120 @@
+use crate::common::*;
+
+use serde_json::{Value, json};
+
+/// A Web Push subscription at `endpoint`, with keys nothing is encrypted to
+fn subscription(endpoint: &str) -> Value {
+    json!({
+        "endpoint": endpoint,
+        "keys": { "p256dh": "p256dh-key", "auth": "auth-secret" }
+    })
+}
+
+async fn revoke(app: &TestApp, token: &str, body: Value) -> reqwest::Response {
+    app.client
+        .post(format!("{}/push/revoke", &app.address))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn endpoints(app: &TestApp, device: &TestDevice) -> Vec<String> {
+    app.storage
+        .notifications
+        .get_endpoints(device.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|stored| stored.subscription.endpoint)
+        .collect()
+}
+
+/// Test that a device keeps several subscriptions and can revoke them one by one or all at once
+#[tokio::test]
+async fn test_multiple_subscriptions_and_revoke() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+
+    assert_success(
+        register_push(
+            &app,
+            &device.token,
+            &subscription("https://push.example.com/browser"),
+        )
+        .await,
+    )
+    .await;
+    assert_success(
+        register_push(
+            &app,
+            &device.token,
+            &subscription("https://push.example.com/native"),
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(endpoints(&app, device).await.len(), 2);
+
+    let response = revoke(
+        &app,
+        &device.token,
+        json!({ "endpoint": "https://push.example.com/browser" }),
+    )
+    .await;
+    assert_status(response, 204).await;
+    assert_eq!(
+        endpoints(&app, device).await,
+        vec!["https://push.example.com/native".to_string()]
+    );
+
+    let response = revoke(
+        &app,
+        &device.token,
+        json!({ "endpoint": "https://push.example.com/browser" }),
+    )
+    .await;
+    assert_status(response, 404).await;
+
+    assert_success(
+        register_push(
+            &app,
+            &device.token,
+            &subscription("https://push.example.com/browser"),
+        )
+        .await,
+    )
+    .await;
+    assert_status(revoke(&app, &device.token, json!({})).await, 204).await;
+    assert!(endpoints(&app, device).await.is_empty());
+}
+
+/// Test that an endpoint registered again by another device moves to it
+#[tokio::test]
+async fn test_endpoint_moves_between_devices() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+
+    let endpoint = "https://push.example.com/shared";
+    assert_success(register_push(&app, &alice.devices[0].token, &subscription(endpoint)).await)
+        .await;
+    assert_success(register_push(&app, &alice.devices[1].token, &subscription(endpoint)).await)
+        .await;
+
+    assert!(endpoints(&app, &alice.devices[0]).await.is_empty());
+    assert_eq!(endpoints(&app, &alice.devices[1]).await, vec![endpoint]);
+
+    // The first device cannot revoke what is no longer its own
+    let response = revoke(
+        &app,
+        &alice.devices[0].token,
+        json!({ "endpoint": endpoint }),
+    )
+    .await;
+    assert_status(response, 404).await;
+}
@@ -0,0 +1,146 @@
+use crate::common::*;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use eko_messenger::notifications::{
+    PushKind, PushProviderKind, PushSubscription, vapid::VapidKeys,
+};
+use reqwest::header::HeaderMap;
+use serde_json::{Value, json};
+use time::{Duration, OffsetDateTime};
+
+const SUBJECT: &str = "mailto:push@example.com";
+
+async fn capabilities(app: &TestApp) -> Value {
+    app.client
+        .get(format!("{}/.well-known/ecp", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// The `k` public key and the claims of a `vapid t=..., k=...` authorization
+fn vapid_authorization(headers: &HeaderMap) -> (String, Value) {
+    let value = headers["authorization"].to_str().unwrap();
+    let params = value.trim_start_matches("vapid ");
+    let param = |name: &str| {
+        params
+            .split(", ")
+            .find_map(|param| param.strip_prefix(name))
+            .unwrap()
+            .to_string()
+    };
+    let token = param("t=");
+    let claims = token.split('.').nth(1).unwrap();
+    let claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+    (param("k="), claims)
+}
+
+/// Test that during a rotation both keys are published, and that subscriptions made with the
+/// previous key are still signed with it and asked to resubscribe
+#[tokio::test]
+async fn test_vapid_rotation() {
+    let previous = generate_vapid_key();
+    let current = generate_vapid_key();
+    let retires_at = OffsetDateTime::now_utc() + Duration::days(7);
+    let keys = VapidKeys::new(current.clone())
+        .with_subject(SUBJECT.to_string())
+        .unwrap()
+        .with_previous(previous.clone(), Some(retires_at));
+    let app = spawn_app_with_options(SpawnOptions {
+        vapid_keys: Some(keys),
+        ..Default::default()
+    })
+    .await;
+    let push = &app.push;
+
+    let capabilities = capabilities(&app).await;
+    let vapid = &capabilities["webpush"]["vapid"];
+    assert_eq!(vapid["publicKey"], current.public_key);
+    assert_eq!(vapid["subject"], SUBJECT);
+    assert_eq!(vapid["previousKeys"][0]["publicKey"], previous.public_key);
+    assert!(vapid["previousKeys"][0]["retiresAt"].is_string());
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    let mut old = push.subscription("a-old");
+    old["applicationServerKey"] = json!(previous.public_key);
+    assert_success(register_push(&app, &device.token, &old).await).await;
+    assert_success(register_push(&app, &device.token, &push.subscription("b-new")).await).await;
+    let mut unknown = push.subscription("unknown");
+    unknown["applicationServerKey"] = json!(generate_vapid_key().public_key);
+    assert_status(register_push(&app, &device.token, &unknown).await, 400).await;
+
+    app.notifications
+        .notify(device.id, PushKind::Message)
+        .await
+        .unwrap();
+
+    // Subscriptions are pushed to in endpoint order
+    let (key, claims) = vapid_authorization(&push.headers(0).await);
+    assert_eq!(key, previous.public_key);
+    assert_eq!(claims["sub"], SUBJECT);
+    assert_eq!(push.payload(0).await, "resubscribe");
+
+    let (key, claims) = vapid_authorization(&push.headers(1).await);
+    assert_eq!(key, current.public_key);
+    assert_eq!(claims["sub"], SUBJECT);
+    assert_eq!(push.payload(1).await, "wake");
+}
+
+/// Test that a retired key is no longer published or accepted, and that its subscriptions are
+/// pruned instead of pushed to
+#[tokio::test]
+async fn test_retired_vapid_key() {
+    let previous = generate_vapid_key();
+    let keys = VapidKeys::new(generate_vapid_key()).with_previous(
+        previous.clone(),
+        Some(OffsetDateTime::now_utc() - Duration::minutes(1)),
+    );
+    let app = spawn_app_with_options(SpawnOptions {
+        vapid_keys: Some(keys),
+        ..Default::default()
+    })
+    .await;
+
+    let capabilities = capabilities(&app).await;
+    assert_eq!(capabilities["webpush"]["vapid"]["previousKeys"], json!([]));
+
+    let alice = TestUser::create(&app, "alice").await;
+    let device = &alice.devices[0];
+    let mut old = app.push.subscription("old");
+    old["applicationServerKey"] = json!(previous.public_key);
+    assert_status(register_push(&app, &device.token, &old).await, 400).await;
+
+    // As if registered before the key retired
+    app.storage
+        .notifications
+        .upsert_endpoint(
+            device.id,
+            &PushSubscription {
+                provider: PushProviderKind::WebPush,
+                endpoint: old["endpoint"].as_str().unwrap().to_string(),
+                keys: Some(serde_json::from_value(old["keys"].clone()).unwrap()),
+                vapid_key: Some(previous.public_key.clone()),
+            },
+        )
+        .await
+        .unwrap();
+    assert!(
+        app.notifications
+            .notify(device.id, PushKind::Message)
+            .await
+            .is_err()
+    );
+
+    assert_eq!(app.push.hits(), 0);
+    let stored = app
+        .storage
+        .notifications
+        .get_endpoints(device.id)
+        .await
+        .unwrap();
+    assert!(stored.is_empty());
+}
@@ -0,0 +1,133 @@
+use crate::common::*;
+
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+async fn delete_account(
+    app: &TestApp,
+    user: &TestUser,
+    device_index: usize,
+    body: Value,
+) -> reqwest::Response {
+    app.client
+        .delete(format!("{}/users/{}", &app.address, user.uid))
+        .bearer_auth(&user.devices[device_index].token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Test that deleting an account purges its data, tombstones the actor and tells contacts
+#[tokio::test]
+async fn test_delete_account() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_success(alice.send_message_to(&app, &bob, "bye").await).await;
+    assert_success(bob.send_message_to(&app, &alice, "wait").await).await;
+    assert_success(
+        alice
+            .upsert_group_state(&app, Uuid::new_v4(), 1, b"state")
+            .await,
+    )
+    .await;
+
+    let mut laptop = app.connect_websocket(&alice.devices[1].token).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let body = json!({ "password": alice.password });
+    assert_status(delete_account(&app, &alice, 0, body).await, 204).await;
+
+    // Skip the backlog replayed on connect
+    timeout(Duration::from_secs(2), async {
+        while let Some(Ok(message)) = laptop.next().await {
+            if matches!(message, Message::Close(_)) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Deleted account's sockets were not closed");
+
+    let response = app.client.get(&alice.actor_id).send().await.unwrap();
+    assert_status(response, 410).await;
+
+    for device in &alice.devices {
+        assert_status(app.refresh_http(&device.refresh_token).await, 401).await;
+    }
+
+    let users = &app.storage.users;
+    assert!(users.get_user_by_uid(&alice.uid).await.unwrap().is_none());
+    assert!(
+        app.storage
+            .devices
+            .list_devices(&alice.uid)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        app.storage
+            .groups
+            .get_all_group_states(&alice.uid)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let inbox = bob.get_inbox(&app).await;
+    let delete = inbox["orderedItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|a| a["type"] == "Delete")
+        .expect("Contact did not receive a Delete");
+    assert_field_equals(delete, "object", &Value::from(alice.actor_id.clone()));
+}
+
+/// Test that deletion requires credentials for the account being deleted
+#[tokio::test]
+async fn test_delete_account_requires_reauthentication() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_status(delete_account(&app, &alice, 0, json!({})).await, 400).await;
+
+    let body = json!({ "email": bob.email, "password": bob.password });
+    assert_status(delete_account(&app, &alice, 0, body).await, 401).await;
+
+    let response = app
+        .client
+        .delete(format!("{}/users/{}", &app.address, alice.uid))
+        .bearer_auth(&bob.devices[0].token)
+        .json(&json!({ "password": bob.password }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 403).await;
+
+    assert_success(app.client.get(&alice.actor_id).send().await.unwrap()).await;
+}
+
+/// Test that a wrong password does not delete the account
+#[cfg(feature = "auth-local")]
+#[tokio::test]
+async fn test_delete_account_wrong_password() {
+    let app = spawn_app_local().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let body = json!({ "password": "not-the-password" });
+    assert_status(delete_account(&app, &alice, 0, body).await, 401).await;
+    assert_success(app.client.get(&alice.actor_id).send().await.unwrap()).await;
+}
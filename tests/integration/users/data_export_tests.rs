@@ -0,0 +1,158 @@
+use crate::common::*;
+
+use serde_json::Value;
+use std::time::Duration;
+use uuid::Uuid;
+
+async fn request_export(app: &TestApp, user: &TestUser, uid: &str) -> reqwest::Response {
+    app.client
+        .post(format!("{}/users/{}/exports", &app.address, uid))
+        .bearer_auth(&user.devices[0].token)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Polls the export until it leaves the pending state
+async fn wait_for_export(app: &TestApp, user: &TestUser, id: &str) -> Value {
+    let url = format!("{}/users/{}/exports/{}", &app.address, user.uid, id);
+    for _ in 0..50 {
+        let response = app
+            .client
+            .get(&url)
+            .bearer_auth(&user.devices[0].token)
+            .send()
+            .await
+            .unwrap();
+        let export: Value = assert_success(response).await.json().await.unwrap();
+        if export["status"] != "pending" {
+            return export;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Export {} never finished", id);
+}
+
+/// Test that an export bundles the user's data and can be downloaded exactly once
+#[tokio::test]
+async fn test_data_export() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_success(bob.send_message_to(&app, &alice, "hello").await).await;
+    let group_id = Uuid::new_v4();
+    assert_success(alice.upsert_group_state(&app, group_id, 3, b"state").await).await;
+
+    let response = request_export(&app, &alice, &alice.uid).await;
+    let export: Value = assert_status(response, 202).await.json().await.unwrap();
+    let id = export["id"].as_str().unwrap().to_string();
+
+    let export = wait_for_export(&app, &alice, &id).await;
+    assert_eq!(export["status"], "ready");
+    let download_url = export["downloadUrl"].as_str().unwrap().to_string();
+
+    let response = app.client.get(&download_url).send().await.unwrap();
+    let response = assert_success(response).await;
+    assert!(
+        response.headers()["content-disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+    let archive: Value = response.json().await.unwrap();
+
+    assert_eq!(archive["profile"]["username"], "alice");
+    assert_eq!(archive["profile"]["actor"]["id"], alice.actor_id.as_str());
+    assert_eq!(archive["deviceChain"].as_array().unwrap().len(), 2);
+    assert_eq!(archive["groupStates"][0]["groupId"], group_id.to_string());
+    assert_eq!(archive["contacts"][0], bob.actor_id.as_str());
+
+    let devices = archive["devices"].as_array().unwrap();
+    assert_eq!(devices.len(), 2);
+    for device in devices {
+        let pending = device["pendingInbox"].as_array().unwrap();
+        assert_eq!(pending.len(), 1, "Unexpected inbox for {}", device["id"]);
+        assert_eq!(pending[0]["activityType"], "Create");
+    }
+
+    // Building the export must not consume the inbox
+    assert_collection_size(&alice.get_inbox(&app).await, 1);
+
+    let response = app.client.get(&download_url).send().await.unwrap();
+    assert_status(response, 410).await;
+
+    let export = wait_for_export(&app, &alice, &id).await;
+    assert_eq!(export["status"], "downloaded");
+    assert!(export["downloadUrl"].is_null());
+}
+
+/// Test that download links only work with a token signed for that export
+#[tokio::test]
+async fn test_data_export_download_token() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let response = request_export(&app, &alice, &alice.uid).await;
+    let first: Value = assert_status(response, 202).await.json().await.unwrap();
+    let response = request_export(&app, &alice, &alice.uid).await;
+    let second: Value = assert_status(response, 202).await.json().await.unwrap();
+
+    let first = wait_for_export(&app, &alice, first["id"].as_str().unwrap()).await;
+    let second_id = second["id"].as_str().unwrap();
+    let first_url = first["downloadUrl"].as_str().unwrap();
+    let token = first_url.split_once("token=").unwrap().1;
+
+    let response = app
+        .client
+        .get(format!(
+            "{}/exports/{}/download?token={}",
+            &app.address, second_id, token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+
+    // Access tokens are signed with other keys
+    let response = app
+        .client
+        .get(format!(
+            "{}/exports/{}/download?token={}",
+            &app.address, second_id, alice.devices[0].token
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+}
+
+/// Test that users cannot export or view another user's data
+#[tokio::test]
+async fn test_data_export_forbidden() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_status(request_export(&app, &bob, &alice.uid).await, 403).await;
+
+    let response = request_export(&app, &alice, &alice.uid).await;
+    let export: Value = assert_status(response, 202).await.json().await.unwrap();
+    let response = app
+        .client
+        .get(format!(
+            "{}/users/{}/exports/{}",
+            &app.address,
+            bob.uid,
+            export["id"].as_str().unwrap()
+        ))
+        .bearer_auth(&bob.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 404).await;
+}
@@ -0,0 +1,4 @@
+pub mod account_deletion_tests;
+pub mod data_export_tests;
+pub mod profile_tests;
+pub mod username_change_tests;
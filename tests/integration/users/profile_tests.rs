@@ -0,0 +1,157 @@
+use crate::common::*;
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde_json::{Value, json};
+use std::io::Cursor;
+
+async fn update_profile(
+    app: &TestApp,
+    user: &TestUser,
+    uid: &str,
+    body: Value,
+) -> reqwest::Response {
+    app.client
+        .put(format!("{}/users/{}/profile", &app.address, uid))
+        .bearer_auth(&user.devices[0].token)
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn upload_avatar(
+    app: &TestApp,
+    user: &TestUser,
+    content_type: &str,
+    body: Vec<u8>,
+) -> reqwest::Response {
+    app.client
+        .put(format!("{}/users/{}/avatar", &app.address, user.uid))
+        .bearer_auth(&user.devices[0].token)
+        .header("Content-Type", content_type)
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+    let mut out = Cursor::new(Vec::new());
+    image.write_to(&mut out, ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+async fn fetch_image(app: &TestApp, url: &str) -> DynamicImage {
+    let response = assert_success(app.client.get(url).send().await.unwrap()).await;
+    assert_eq!(response.headers()["content-type"], "image/png");
+    image::load_from_memory(&response.bytes().await.unwrap()).unwrap()
+}
+
+/// Test that profile fields are served on the actor and sent to contacts
+#[tokio::test]
+async fn test_update_profile() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    assert_success(alice.send_message_to(&app, &bob, "hi").await).await;
+
+    let body = json!({ "name": "  Alice Liddell ", "summary": "Down the rabbit hole" });
+    assert_success(update_profile(&app, &alice, &alice.uid, body).await).await;
+
+    let actor = alice.get_actor(&app).await;
+    assert_field_equals(&actor, "name", &Value::from("Alice Liddell"));
+    assert_field_equals(&actor, "summary", &Value::from("Down the rabbit hole"));
+
+    let inbox = bob.get_inbox(&app).await;
+    let update = inbox["orderedItems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|a| a["type"] == "Update")
+        .expect("Contact did not receive an Update");
+    assert_eq!(update["object"]["name"], "Alice Liddell");
+
+    // Blank fields clear the profile
+    let body = json!({ "name": "", "summary": null });
+    assert_success(update_profile(&app, &alice, &alice.uid, body).await).await;
+    let actor = alice.get_actor(&app).await;
+    assert!(actor["name"].is_null());
+    assert!(actor["summary"].is_null());
+}
+
+/// Test the length limits and that users cannot edit each other
+#[tokio::test]
+async fn test_update_profile_rejected() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let body = json!({ "name": "a".repeat(65) });
+    assert_status(update_profile(&app, &alice, &alice.uid, body).await, 400).await;
+
+    let body = json!({ "name": "Bob" });
+    assert_status(update_profile(&app, &bob, &alice.uid, body).await, 403).await;
+}
+
+/// Test that avatars are cropped, thumbnailed and served from the actor's URL
+#[tokio::test]
+async fn test_avatar_upload() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let response = upload_avatar(&app, &alice, "image/png", png(800, 600)).await;
+    let person: Value = assert_success(response).await.json().await.unwrap();
+    let avatar_url = person["profilePicture"].as_str().unwrap().to_string();
+
+    let actor = alice.get_actor(&app).await;
+    assert_field_equals(&actor, "profilePicture", &Value::from(avatar_url.clone()));
+
+    let avatar = fetch_image(&app, &avatar_url).await;
+    assert_eq!((avatar.width(), avatar.height()), (512, 512));
+    let thumbnail = fetch_image(&app, &format!("{}/thumbnail", avatar_url)).await;
+    assert_eq!((thumbnail.width(), thumbnail.height()), (96, 96));
+
+    // Small images are not scaled up
+    let response = upload_avatar(&app, &alice, "image/png", png(100, 200)).await;
+    let person: Value = assert_success(response).await.json().await.unwrap();
+    let new_url = person["profilePicture"].as_str().unwrap();
+    assert_ne!(new_url, avatar_url);
+    let avatar = fetch_image(&app, new_url).await;
+    assert_eq!((avatar.width(), avatar.height()), (100, 100));
+
+    let response = app.client.get(&avatar_url).send().await.unwrap();
+    assert_status(response, 404).await;
+
+    let response = app
+        .client
+        .delete(format!("{}/users/{}/avatar", &app.address, alice.uid))
+        .bearer_auth(&alice.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await;
+    assert!(alice.get_actor(&app).await["profilePicture"].is_null());
+}
+
+/// Test that uploads must be a supported image within the size limit
+#[tokio::test]
+async fn test_avatar_upload_rejected() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+
+    let response = upload_avatar(&app, &alice, "image/gif", png(10, 10)).await;
+    assert_status(response, 400).await;
+
+    let response = upload_avatar(&app, &alice, "image/png", b"not an image".to_vec()).await;
+    assert_status(response, 400).await;
+
+    let response = upload_avatar(&app, &alice, "image/png", vec![0; 3 * 1024 * 1024]).await;
+    assert_status(response, 413).await;
+
+    assert!(alice.get_actor(&app).await["profilePicture"].is_null());
+}
@@ -0,0 +1,120 @@
+use crate::common::*;
+
+use serde_json::{Value, json};
+
+async fn change_username(app: &TestApp, user: &TestUser, username: &str) -> reqwest::Response {
+    app.client
+        .put(format!("{}/users/{}/username", &app.address, user.uid))
+        .bearer_auth(&user.devices[0].token)
+        .json(&json!({ "username": username }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn webfinger(app: &TestApp, username: &str) -> reqwest::Response {
+    let host = app.domain.trim_start_matches("http://");
+    app.client
+        .get(format!("{}/.well-known/webfinger", &app.address))
+        .query(&[("resource", format!("acct:{}@{}", username, host))])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Test that the old name redirects to the new one and is listed as an alias
+#[tokio::test]
+async fn test_change_username_webfinger() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let host = app.domain.trim_start_matches("http://").to_string();
+
+    let response = assert_success(change_username(&app, &alice, "alicia").await).await;
+    let person: Value = response.json().await.unwrap();
+    assert_field_equals(&person, "preferredUsername", &Value::from("alicia"));
+
+    let response = assert_success(webfinger(&app, "alicia").await).await;
+    let jrd: Value = response.json().await.unwrap();
+    let aliases = jrd["aliases"].as_array().unwrap();
+    assert!(aliases.contains(&Value::from(alice.actor_id.clone())));
+    assert!(aliases.contains(&Value::from(format!("acct:alice@{}", host))));
+
+    // Lookups of the old name land on the new one, by a redirect that is not to be cached
+    let no_redirects = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = no_redirects
+        .get(format!("{}/.well-known/webfinger", &app.address))
+        .query(&[("resource", format!("acct:alice@{}", host))])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 307);
+    let response = assert_success(webfinger(&app, "alice").await).await;
+    let jrd: Value = response.json().await.unwrap();
+    assert_field_equals(
+        &jrd,
+        "subject",
+        &Value::from(format!("acct:alicia@{}", host)),
+    );
+    assert_eq!(jrd["links"][0]["href"], Value::from(alice.actor_id.clone()));
+}
+
+/// Test that contacts and the user's own devices receive an Update of the actor
+#[tokio::test]
+async fn test_change_username_updates_contacts() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    let charlie = TestUser::create(&app, "charlie").await;
+
+    assert_success(alice.send_message_to(&app, &bob, "hi").await).await;
+    assert_success(change_username(&app, &alice, "alicia").await).await;
+
+    for user in [&bob, &alice] {
+        let inbox = user.get_inbox(&app).await;
+        let update = inbox["orderedItems"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|a| a["type"] == "Update")
+            .unwrap_or_else(|| panic!("{} did not receive an Update", user.username));
+        assert_field_equals(update, "actor", &Value::from(alice.actor_id.clone()));
+        assert_eq!(update["object"]["preferredUsername"], "alicia");
+    }
+
+    let inbox = charlie.get_inbox(&app).await;
+    assert_collection_size(&inbox, 0);
+}
+
+/// Test that taken and recently given up names cannot be claimed by someone else
+#[tokio::test]
+async fn test_change_username_conflicts() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_status(change_username(&app, &bob, "alice").await, 400).await;
+    assert_status(change_username(&app, &bob, "Not Valid").await, 400).await;
+
+    assert_success(change_username(&app, &alice, "alicia").await).await;
+    assert_status(change_username(&app, &bob, "alice").await, 400).await;
+
+    // Alice can take her old name back
+    assert_success(change_username(&app, &alice, "alice").await).await;
+    assert_success(webfinger(&app, "alice").await).await;
+
+    let response = app
+        .client
+        .put(format!("{}/users/{}/username", &app.address, alice.uid))
+        .bearer_auth(&bob.devices[0].token)
+        .json(&json!({ "username": "bobby" }))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 403).await;
+}
@@ -0,0 +1,206 @@
+use crate::common::*;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use eko_messenger::auth::RefreshResponse;
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+async fn connect_without_credentials(app: &TestApp, path: &str) -> WsStream {
+    let ws_url = format!("{}{}", app.address.replace("http://", "ws://"), path);
+    let (stream, _) = connect_async(ws_url)
+        .await
+        .expect("WebSocket connection failed");
+    stream
+}
+
+/// Returns the reason the server closed the socket with
+async fn close_reason(ws: &mut WsStream) -> String {
+    timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = ws.next().await {
+            if let Message::Close(frame) = message {
+                return frame.map(|f| f.reason.to_string()).unwrap_or_default();
+            }
+        }
+        panic!("Socket ended without a close frame");
+    })
+    .await
+    .expect("Socket was not closed")
+}
+
+/// Test that a socket can be authenticated with its first frame
+#[tokio::test]
+async fn test_first_frame_authentication() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut ws = connect_without_credentials(&app, "/ws").await;
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Authenticate", "token": bob.devices[0].token }),
+    )
+    .await;
+    let event = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No frame received");
+    assert_eq!(event["type"], "Authenticated");
+    assert!(event["expiresAt"].as_u64().is_some());
+
+    assert_success(alice.send_message_to(&app, &bob, "hello").await).await;
+    let activity = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No frame received");
+    assert_eq!(activity["type"], "Create");
+}
+
+/// Test that a socket is closed when its first frame carries a bad token
+#[tokio::test]
+async fn test_first_frame_with_invalid_token() {
+    let app = spawn_app().await;
+
+    let mut ws = connect_without_credentials(&app, "/ws").await;
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Authenticate", "token": "invalid-token" }),
+    )
+    .await;
+    assert!(!close_reason(&mut ws).await.is_empty());
+}
+
+/// Test that a ticket from the ticket endpoint opens a socket, once
+#[tokio::test]
+async fn test_ticket_authentication() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = app
+        .client
+        .post(format!("{}/ws/ticket", &app.address))
+        .bearer_auth(&bob.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    let ticket: Value = assert_success(response).await.json().await.unwrap();
+    let ticket = ticket["ticket"].as_str().unwrap();
+
+    // Tickets are kept apart from OIDC login states, so a callback cannot use one up
+    let claims = ticket.split('.').nth(1).unwrap();
+    let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+    let jti = claims["jti"].as_str().unwrap();
+    assert!(
+        app.storage
+            .auth_states
+            .take_auth_state(jti)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let mut ws = connect_without_credentials(&app, &format!("/ws?ticket={}", ticket)).await;
+    assert_success(alice.send_message_to(&app, &bob, "hello").await).await;
+    let activity = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No frame received");
+    assert_eq!(activity["type"], "Create");
+
+    // Tickets are single use, so one copied from a URL opens nothing
+    let ws_url = format!(
+        "{}/ws?ticket={}",
+        app.address.replace("http://", "ws://"),
+        ticket
+    );
+    assert!(connect_async(ws_url).await.is_err());
+    let response = app
+        .client
+        .get(format!("{}/inbox/stream?ticket={}", &app.address, ticket))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    // Tickets are not access tokens
+    let response = app
+        .client
+        .get(format!("{}/users/{}/inbox", &app.address, bob.uid))
+        .bearer_auth(ticket)
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.status().is_success());
+}
+
+/// Test that a socket can be refreshed with a new token for the same device only
+#[tokio::test]
+async fn test_refresh_over_socket() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+
+    let refreshed: RefreshResponse =
+        assert_success(app.refresh_http(&alice.devices[0].refresh_token).await)
+            .await
+            .json()
+            .await
+            .unwrap();
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Refresh", "requestId": "1", "token": refreshed.access_token }),
+    )
+    .await;
+    let event = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No frame received");
+    assert_eq!(event["type"], "Refreshed");
+    assert_eq!(event["requestId"], "1");
+
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Refresh", "requestId": "2", "token": alice.devices[1].token }),
+    )
+    .await;
+    let event = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No frame received");
+    assert_eq!(event["type"], "Error");
+    assert_eq!(event["requestId"], "2");
+    assert_eq!(event["status"], 403);
+}
+
+/// Test that tokens of a revoked device no longer open sockets
+#[tokio::test]
+async fn test_revoked_device_cannot_connect() {
+    let app = spawn_app().await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let laptop = &alice.devices[1];
+
+    let response = app
+        .client
+        .delete(format!(
+            "{}/users/{}/devices/{}",
+            &app.address, alice.uid, laptop.id
+        ))
+        .bearer_auth(&alice.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await;
+
+    let mut ws = connect_without_credentials(&app, "/ws").await;
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Authenticate", "token": laptop.token }),
+    )
+    .await;
+    assert_eq!(close_reason(&mut ws).await, "Device has been revoked");
+}
@@ -0,0 +1,87 @@
+use eko_messenger::{
+    activitypub::{Activity, Delivered},
+    devices::DeviceId,
+    notifications::PushKind,
+    websocket::WebSocketService,
+};
+use serde_json::json;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn delivered() -> Activity {
+    Activity::Delivered(Delivered {
+        id: None,
+        context: json!("https://www.w3.org/ns/activitystreams"),
+        actor: "http://localhost/users/alice".to_string(),
+        to: "http://localhost/users/bob".to_string(),
+        object: "http://localhost/activities/1".to_string(),
+    })
+}
+
+/// Test that a full queue reports failure so callers fall back to the inbox
+#[tokio::test]
+async fn test_full_queue_is_not_delivered() {
+    let sockets = WebSocketService::with_limits(2, Duration::from_secs(60));
+    let did = DeviceId::new(Uuid::new_v4());
+    let mut receiver = sockets.register(did).await;
+
+    assert!(
+        sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+    assert!(
+        sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+    assert!(
+        !sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+
+    // Draining the queue makes room again
+    receiver.messages.recv().await.unwrap();
+    assert!(
+        sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+    assert!(sockets.is_online(&did).await);
+}
+
+/// Test that a client whose queue stays full is disconnected
+#[tokio::test]
+async fn test_slow_consumer_is_evicted() {
+    let sockets = WebSocketService::with_limits(1, Duration::from_millis(100));
+    let did = DeviceId::new(Uuid::new_v4());
+    let mut receiver = sockets.register(did).await;
+
+    assert!(
+        sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+    assert!(
+        !sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+    assert!(sockets.is_online(&did).await);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(
+        !sockets
+            .try_websocket_delivery(&delivered(), did, 1, PushKind::Background)
+            .await
+    );
+
+    assert!(!sockets.is_online(&did).await);
+    let frame = receiver
+        .close
+        .recv()
+        .await
+        .expect("No close frame was sent");
+    assert_eq!(frame.reason.as_str(), "Too slow to receive messages");
+}
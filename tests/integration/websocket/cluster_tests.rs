@@ -0,0 +1,143 @@
+use crate::common::*;
+
+use eko_messenger::{
+    devices::DeviceId,
+    websocket::{WebSocketService, service::NODE_TIMEOUT},
+};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use time::OffsetDateTime;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// A second server node sharing the app's database
+async fn spawn_node(app: &TestApp) -> Arc<WebSocketService> {
+    let node = Arc::new(WebSocketService::new().with_cluster(app.storage.socket_routes.clone()));
+    node.clone()
+        .spawn_cluster_tasks()
+        .await
+        .expect("Failed to join the socket cluster");
+    node
+}
+
+/// The node the device's socket is routed to
+async fn routed_node(app: &TestApp, did: DeviceId) -> Option<Uuid> {
+    app.storage
+        .socket_routes
+        .get_route(did, OffsetDateTime::now_utc() - NODE_TIMEOUT)
+        .await
+        .unwrap()
+}
+
+/// Test that a message reaches a device connected to another node
+#[tokio::test]
+async fn test_delivery_relayed_to_other_node() {
+    let app = spawn_app_clustered().await;
+    let node = spawn_node(&app).await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut receiver = node.register(bob.devices[0].id).await;
+
+    // Larger than a NOTIFY payload can be
+    let text = "hello ".repeat(2000);
+    let response = alice.send_message_to(&app, &bob, &text).await;
+    let create: Value = assert_success(response).await.json().await.unwrap();
+
+    let message = timeout(Duration::from_secs(5), receiver.messages.recv())
+        .await
+        .expect("No message was relayed")
+        .unwrap();
+    let relayed: Value = serde_json::from_str(message.message.to_text().unwrap()).unwrap();
+    assert_eq!(relayed["id"], create["id"]);
+    assert_eq!(relayed["type"], "Create");
+    assert_eq!(
+        relayed["seq"],
+        message.seq.expect("Relayed without its sequence number")
+    );
+}
+
+/// Test that revoking a device closes its socket on the node holding it
+#[tokio::test]
+async fn test_disconnect_relayed_to_other_node() {
+    let app = spawn_app_clustered().await;
+    let node = spawn_node(&app).await;
+
+    let mut alice = TestUser::create(&app, "alice").await;
+    alice.add_device(&app, "laptop").await;
+    let laptop = alice.devices[1].id;
+
+    let mut receiver = node.register(laptop).await;
+
+    let response = app
+        .client
+        .delete(format!(
+            "{}/users/{}/devices/{}",
+            &app.address, alice.uid, laptop
+        ))
+        .bearer_auth(&alice.devices[0].token)
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await;
+
+    let frame = timeout(Duration::from_secs(5), receiver.close.recv())
+        .await
+        .expect("No close was relayed")
+        .unwrap();
+    assert_eq!(frame.reason.as_str(), "Device revoked");
+    assert!(!node.is_online(&laptop).await);
+
+    // The route goes with the socket, so nothing is relayed to the node for it anymore
+    timeout(Duration::from_secs(5), async {
+        while routed_node(&app, laptop).await.is_some() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("The socket route was left behind");
+}
+
+/// Test that the node holding a socket wakes the device itself when a relayed delivery does
+/// not fit in the socket's queue, since the sending node took it as delivered
+#[tokio::test]
+async fn test_relayed_delivery_to_full_queue_wakes_device() {
+    let app = spawn_app_clustered().await;
+    let node = Arc::new(
+        WebSocketService::with_limits(1, Duration::from_secs(60))
+            .with_cluster(app.storage.socket_routes.clone())
+            .with_notifications(app.notifications.clone()),
+    );
+    node.clone()
+        .spawn_cluster_tasks()
+        .await
+        .expect("Failed to join the socket cluster");
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+    let response = app
+        .client
+        .post(format!("{}/push/register", &app.address))
+        .bearer_auth(&bob.devices[0].token)
+        .json(&app.push.subscription("bob"))
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await;
+
+    // Never read, so only the first message fits
+    let _receiver = node.register(bob.devices[0].id).await;
+    for text in ["first", "second"] {
+        assert_success(alice.send_message_to(&app, &bob, text).await).await;
+    }
+
+    timeout(Duration::from_secs(5), async {
+        while app.push.hits() == 0 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("The device was not woken");
+    assert_eq!(app.push.hits(), 1);
+}
@@ -1,10 +1,10 @@
 use crate::common::*;
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use std::time::Duration;
 use tokio::time::timeout;
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{client::IntoClientRequest, http::HeaderValue},
+    tungstenite::{Message, client::IntoClientRequest, http::HeaderValue},
 };
 
 /// Test that WebSocket connection requires authentication
@@ -12,15 +12,28 @@ use tokio_tungstenite::{
 async fn test_websocket_requires_authentication() {
     let app = spawn_app().await;
 
-    // Try connecting without authentication token
+    // Connect without authentication token
     let ws_url = format!("{}/ws", app.address.replace("http://", "ws://"));
-    let result = timeout(Duration::from_secs(2), connect_async(&ws_url)).await;
+    let (mut ws, _) = connect_async(&ws_url).await.unwrap();
 
-    // Connection should fail without authentication
-    assert!(
-        result.is_err() || result.unwrap().is_err(),
-        "WebSocket connection should fail without authentication"
-    );
+    // Anything but an Authenticate frame closes the socket
+    ws.send(Message::Text(
+        r#"{"type":"Ack","requestId":"1","activity":{}}"#.to_string(),
+    ))
+    .await
+    .unwrap();
+    let frame = timeout(Duration::from_secs(2), async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Close(frame))) => return frame,
+                Some(Ok(_)) => {}
+                _ => panic!("Socket ended without a close frame"),
+            }
+        }
+    })
+    .await
+    .expect("Unauthenticated socket was not closed");
+    assert!(frame.unwrap().reason == "Authentication required");
 }
 
 /// Test that WebSocket connection succeeds with valid authentication
@@ -141,3 +154,110 @@ async fn test_websocket_with_invalid_token() {
         "WebSocket connection with invalid token should fail"
     );
 }
+
+async fn is_online(app: &TestApp, user: &TestUser, device_index: usize) -> bool {
+    let devices: serde_json::Value = app
+        .client
+        .get(format!("{}/users/{}/devices", &app.address, user.uid))
+        .bearer_auth(&user.devices[0].token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    devices
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["id"] == user.devices[device_index].url)
+        .expect("Device not listed")["online"]
+        .as_bool()
+        .unwrap()
+}
+
+/// Test that a client answering pings stays connected and one that stops is dropped
+#[tokio::test]
+async fn test_idle_websocket_is_closed() {
+    let app = spawn_app_with_options(SpawnOptions {
+        socket_heartbeat: Some((Duration::from_millis(100), Duration::from_millis(300))),
+        ..Default::default()
+    })
+    .await;
+    let alice = TestUser::create(&app, "alice").await;
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+
+    // Reading answers the server's pings
+    let mut pings = 0;
+    let _ = timeout(Duration::from_millis(600), async {
+        while let Some(Ok(message)) = ws.next().await {
+            if message.is_ping() {
+                pings += 1;
+            }
+        }
+    })
+    .await;
+    assert!(pings > 0, "The server never pinged");
+    assert!(is_online(&app, &alice, 0).await);
+
+    // Without reads no pongs go out
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(!is_online(&app, &alice, 0).await);
+
+    // Answering the pings still buffered may fail on the closed connection before the close
+    // frame is read, so only check its reason if it arrives
+    timeout(Duration::from_secs(2), async {
+        while let Some(Ok(message)) = ws.next().await {
+            if let Message::Close(Some(frame)) = message {
+                assert_eq!(frame.reason, "Idle timeout");
+            }
+        }
+    })
+    .await
+    .expect("The idle socket was not closed");
+}
+
+/// Test that reconnecting closes the previous socket without losing the new one
+#[tokio::test]
+async fn test_reconnect_replaces_previous_websocket() {
+    let app = spawn_app().await;
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut first = app.connect_websocket(&bob.devices[0].token).await;
+    let mut second = app.connect_websocket(&bob.devices[0].token).await;
+
+    let closed = timeout(Duration::from_secs(2), async {
+        while let Some(Ok(message)) = first.next().await {
+            if let Message::Close(frame) = message {
+                return frame.map(|f| f.reason.to_string());
+            }
+        }
+        None
+    })
+    .await
+    .unwrap();
+    assert_eq!(closed.as_deref(), Some("Replaced by a newer connection"));
+    drop(first);
+
+    // The first connection's cleanup must not unregister the second
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(is_online(&app, &bob, 0).await);
+
+    alice.send_message_to(&app, &bob, "hello").await;
+    let message = timeout(Duration::from_secs(2), async {
+        while let Some(Ok(message)) = second.next().await {
+            if let Message::Text(text) = message {
+                let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if value["type"] == "Create" {
+                    return value;
+                }
+            }
+        }
+        panic!("Socket closed before the message arrived");
+    })
+    .await
+    .expect("The message was not sent over the new socket");
+    assert_eq!(message["actor"], alice.actor_id);
+}
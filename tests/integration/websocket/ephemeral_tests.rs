@@ -0,0 +1,103 @@
+use crate::common::*;
+
+use eko_messenger::websocket::ephemeral::EPHEMERAL_BURST;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// "typing", base64 encoded like any encrypted content
+const TYPING: &str = "dHlwaW5n";
+
+fn ephemeral_frame(from: &TestUser, to: &TestUser) -> Value {
+    let content: Vec<Value> = to
+        .devices
+        .iter()
+        .map(|device| json!({ "to": device.url, "from": from.devices[0].url, "content": TYPING }))
+        .collect();
+    json!({ "type": "Ephemeral", "to": to.actor_id, "ttl": 5, "content": content })
+}
+
+/// Test that an ephemeral event reaches connected devices only and is never stored
+#[tokio::test]
+async fn test_ephemeral_relayed_to_connected_devices() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let mut bob = TestUser::create(&app, "bob").await;
+    bob.add_device(&app, "laptop").await;
+
+    let mut alice_ws = app.connect_websocket(&alice.devices[0].token).await;
+    let mut bob_ws = app.connect_websocket(&bob.devices[0].token).await;
+
+    send_frame(&mut alice_ws, &ephemeral_frame(&alice, &bob)).await;
+
+    let event = next_frame(&mut bob_ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No ephemeral event arrived");
+    assert_eq!(event["type"], "Ephemeral");
+    assert_eq!(event["actor"], alice.actor_id);
+    assert_eq!(event["content"]["to"], bob.devices[0].url);
+    assert_eq!(event["content"]["content"], TYPING);
+    assert!(event["expiresAt"].as_i64().is_some());
+
+    // The sender gets no reply on success
+    assert!(
+        next_frame(&mut alice_ws, Duration::from_millis(300), |_| true)
+            .await
+            .is_none()
+    );
+
+    // The offline laptop gets nothing, now or later
+    for device in &bob.devices {
+        let pending = app
+            .storage
+            .activities
+            .pending_deliveries(device.id)
+            .await
+            .unwrap();
+        assert!(pending.is_empty());
+    }
+    let mut laptop_ws = app.connect_websocket(&bob.devices[1].token).await;
+    assert!(
+        next_frame(&mut laptop_ws, Duration::from_millis(300), |_| true)
+            .await
+            .is_none()
+    );
+}
+
+/// Test that a device sending too many ephemeral events is told to slow down
+#[tokio::test]
+async fn test_ephemeral_rate_limited() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+    let frame = ephemeral_frame(&alice, &bob);
+    for _ in 0..EPHEMERAL_BURST + 5 {
+        send_frame(&mut ws, &frame).await;
+    }
+
+    let error = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("No error after exceeding the limit");
+    assert_eq!(error["type"], "Error");
+    assert_eq!(error["status"], 429);
+}
+
+/// Test that ephemeral events cannot claim another sending device
+#[tokio::test]
+async fn test_ephemeral_sender_must_match() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+    send_frame(&mut ws, &ephemeral_frame(&bob, &bob)).await;
+
+    let error = next_frame(&mut ws, Duration::from_secs(5), |_| true)
+        .await
+        .expect("Forged sender was not rejected");
+    assert_eq!(error["status"], 400);
+}
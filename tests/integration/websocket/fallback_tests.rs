@@ -0,0 +1,166 @@
+use crate::common::*;
+
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Reads an event stream until `count` data events arrived, returning each with its id
+async fn next_sse_events(response: &mut reqwest::Response, count: usize) -> Vec<(String, Value)> {
+    timeout(Duration::from_secs(10), async {
+        let mut buffer = String::new();
+        let mut events = Vec::new();
+        while events.len() < count {
+            let chunk = response.chunk().await.unwrap().expect("Event stream ended");
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(end) = buffer.find("\n\n") {
+                let block: String = buffer.drain(..end + 2).collect();
+                let mut id = String::new();
+                let mut data = None;
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("id:") {
+                        id = value.trim().to_string();
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data = Some(serde_json::from_str(value.trim()).unwrap());
+                    }
+                }
+                if let Some(data) = data {
+                    events.push((id, data));
+                }
+            }
+        }
+        events
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Fewer than {} events arrived", count))
+}
+
+async fn open_stream(app: &TestApp, token: &str, last_event_id: Option<&str>) -> reqwest::Response {
+    let mut request = app
+        .client
+        .get(format!("{}/inbox/stream", &app.address))
+        .bearer_auth(token);
+    if let Some(id) = last_event_id {
+        request = request.header("Last-Event-ID", id);
+    }
+    assert_success(request.send().await.unwrap()).await
+}
+
+async fn poll(app: &TestApp, token: &str, after: i64, wait: u64) -> Value {
+    let response = app
+        .client
+        .get(format!(
+            "{}/inbox/poll?after={}&wait={}",
+            &app.address, after, wait
+        ))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_success(response).await.json().await.unwrap()
+}
+
+/// Test that the event stream replays the inbox, follows new deliveries and resumes by id
+#[tokio::test]
+async fn test_event_stream_delivery_and_resume() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    assert_success(alice.send_message_to(&app, &bob, "first").await).await;
+
+    let mut stream = open_stream(&app, &bob.devices[0].token, None).await;
+    assert_success(alice.send_message_to(&app, &bob, "second").await).await;
+
+    let events = next_sse_events(&mut stream, 2).await;
+    assert!(events.iter().all(|(_, event)| event["type"] == "Create"));
+    assert_eq!(events[0].0, events[0].1["seq"].to_string());
+    drop(stream);
+
+    let mut stream = open_stream(&app, &bob.devices[0].token, Some(&events[0].0)).await;
+    let resumed = next_sse_events(&mut stream, 1).await;
+    assert_eq!(resumed[0].1["id"], events[1].1["id"]);
+}
+
+/// Test that the event stream needs credentials
+#[tokio::test]
+async fn test_event_stream_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = app
+        .client
+        .get(format!("{}/inbox/stream", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_status(response, 401).await;
+}
+
+/// Test that a long poll waits for a delivery and returns it with a cursor to continue from
+#[tokio::test]
+async fn test_long_poll_waits_for_delivery() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let send_later = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_success(alice.send_message_to(&app, &bob, "hello").await).await;
+    };
+    let (response, _) = timeout(
+        Duration::from_secs(5),
+        futures_util::future::join(poll(&app, &bob.devices[0].token, 0, 10), send_later),
+    )
+    .await
+    .expect("Poll did not return after a delivery");
+    let messages = response["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["type"], "Create");
+    assert_eq!(response["cursor"], messages[0]["seq"]);
+
+    // Nothing after the cursor, so an empty poll returns once the wait is over
+    let cursor = response["cursor"].as_i64().unwrap();
+    let response = poll(&app, &bob.devices[0].token, cursor, 1).await;
+    assert!(response["messages"].as_array().unwrap().is_empty());
+    assert_eq!(response["cursor"], cursor);
+}
+
+/// Test that activities stay pending for polls until acknowledged
+#[tokio::test]
+async fn test_long_poll_acknowledge() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = alice.send_message_to(&app, &bob, "hello").await;
+    let create: Value = assert_success(response).await.json().await.unwrap();
+    let response = bob
+        .send_delivered(&app, create["id"].as_str().unwrap(), &alice)
+        .await;
+    assert_success(response).await;
+
+    let token = &alice.devices[0].token;
+    let first = poll(&app, token, 0, 0).await;
+    assert_eq!(first["messages"][0]["type"], "Delivered");
+    let delivered_id = first["messages"][0]["id"].clone();
+
+    // Not acknowledged yet, so polling from the start returns it again
+    let again = poll(&app, token, 0, 0).await;
+    assert_eq!(again["messages"][0]["id"], delivered_id);
+
+    let response = app
+        .client
+        .post(format!("{}/inbox/ack", &app.address))
+        .bearer_auth(token)
+        .json(&json!({ "activities": [delivered_id] }))
+        .send()
+        .await
+        .unwrap();
+    let acknowledged: Value = assert_success(response).await.json().await.unwrap();
+    assert_eq!(acknowledged["removed"], 1);
+
+    let after_ack = poll(&app, token, 0, 0).await;
+    assert!(after_ack["messages"].as_array().unwrap().is_empty());
+}
@@ -1 +1,8 @@
+pub mod auth_tests;
+pub mod backpressure_tests;
+pub mod cluster_tests;
 pub mod connection_tests;
+pub mod ephemeral_tests;
+pub mod fallback_tests;
+pub mod protocol_tests;
+pub mod replay_tests;
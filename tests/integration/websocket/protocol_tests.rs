@@ -0,0 +1,191 @@
+use crate::common::*;
+
+use serde_json::{Value, json};
+use std::time::Duration;
+
+/// How long to wait for the server to answer a frame
+const WAIT: Duration = Duration::from_secs(2);
+
+/// Test that activities sent over the socket go through the outbox
+#[tokio::test]
+async fn test_websocket_send_activity() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let envelope = SignalEnvelope::new()
+        .add_messages_for_all_devices(alice.devices[0].url.clone(), &bob, "hello")
+        .build_message(&alice.actor_id, &bob.actor_id);
+    let activity = alice.create_message_activity(envelope);
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Send", "requestId": "1", "activity": activity }),
+    )
+    .await;
+
+    let response = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Response")
+        .await
+        .expect("No Response received");
+    assert_eq!(response["requestId"], "1");
+    assert_eq!(response["status"], 201);
+    assert!(response["activity"]["id"].is_string());
+
+    assert_all_devices_received_message(&app, &bob, 1, Some(&alice.actor_id), None, None).await;
+}
+
+/// Test that an Ack frame acts as a Delivered for the message
+#[tokio::test]
+async fn test_websocket_ack() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = alice.send_message_to(&app, &bob, "hello").await;
+    let create: Value = assert_success(response).await.json().await.unwrap();
+
+    let mut ws = app.connect_websocket(&bob.devices[0].token).await;
+    send_frame(
+        &mut ws,
+        &json!({
+            "type": "Ack",
+            "requestId": "ack-1",
+            "object": create["id"],
+            "to": alice.actor_id,
+        }),
+    )
+    .await;
+
+    let response = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Response")
+        .await
+        .expect("No Response received");
+    assert_eq!(response["requestId"], "ack-1");
+    assert_eq!(response["activity"]["type"], "Delivered");
+
+    assert_collection_size(&bob.get_inbox(&app).await, 0);
+    let inbox = alice.get_inbox(&app).await;
+    assert_eq!(inbox["orderedItems"][0]["type"], "Delivered");
+}
+
+/// Test that bad frames and rejected activities get an error carrying the request id
+#[tokio::test]
+async fn test_websocket_request_errors() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+
+    send_frame(&mut ws, &json!({ "type": "Unknown" })).await;
+    let error = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Error")
+        .await
+        .expect("No Error received");
+    assert!(error["requestId"].is_null());
+    assert_eq!(error["status"], 400);
+
+    // Sending as someone else is rejected like it is over HTTP
+    let envelope = SignalEnvelope::new()
+        .add_messages_for_all_devices(bob.devices[0].url.clone(), &alice, "spoofed")
+        .build_message(&bob.actor_id, &alice.actor_id);
+    let activity = bob.create_message_activity(envelope);
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Send", "requestId": "2", "activity": activity }),
+    )
+    .await;
+    let error = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Error")
+        .await
+        .expect("No Error received");
+    assert_eq!(error["requestId"], "2");
+    assert_eq!(error["status"], 403);
+
+    // The socket stays usable after an error
+    send_frame(&mut ws, &json!("not an object")).await;
+    assert_eq!(
+        next_frame(&mut ws, WAIT, |frame| frame["type"] == "Error")
+            .await
+            .expect("No Error received")["status"],
+        400
+    );
+}
+
+/// Test that activities pushed over the socket are sent again until the client confirms them
+#[tokio::test]
+async fn test_websocket_redelivers_until_received() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = alice.send_message_to(&app, &bob, "hello").await;
+    let create: Value = assert_success(response).await.json().await.unwrap();
+
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+    let response = bob
+        .send_delivered(&app, create["id"].as_str().unwrap(), &alice)
+        .await;
+    assert_success(response).await;
+    let delivered = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Delivered")
+        .await
+        .expect("No Delivered received");
+    drop(ws);
+
+    // The connection dropped without a confirmation, so it is sent again
+    let mut ws = app.connect_websocket(&alice.devices[0].token).await;
+    assert_eq!(
+        next_frame(&mut ws, WAIT, |frame| frame["type"] == "Delivered")
+            .await
+            .expect("No Delivered received")["id"],
+        delivered["id"]
+    );
+
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Received", "requestId": "r-1", "activities": [delivered["id"]] }),
+    )
+    .await;
+    let acknowledged = next_frame(&mut ws, WAIT, |frame| frame["type"] == "Acknowledged")
+        .await
+        .expect("No Acknowledged received");
+    assert_eq!(acknowledged["requestId"], "r-1");
+    assert_eq!(acknowledged["removed"], 1);
+
+    let pending = app
+        .storage
+        .activities
+        .pending_deliveries(alice.devices[0].id)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
+}
+
+/// Test that a Create can only be confirmed with a Delivered
+#[tokio::test]
+async fn test_websocket_received_skips_create() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let response = alice.send_message_to(&app, &bob, "hello").await;
+    let create: Value = assert_success(response).await.json().await.unwrap();
+
+    let mut ws = app.connect_websocket(&bob.devices[0].token).await;
+    send_frame(
+        &mut ws,
+        &json!({ "type": "Received", "requestId": "r-1", "activities": [create["id"]] }),
+    )
+    .await;
+    assert_eq!(
+        next_frame(&mut ws, WAIT, |frame| frame["type"] == "Acknowledged")
+            .await
+            .expect("No Acknowledged received")["removed"],
+        0
+    );
+
+    assert_collection_size(&bob.get_inbox(&app).await, 1);
+}
@@ -0,0 +1,89 @@
+use crate::common::*;
+
+use eko_messenger::websocket::handler::REPLAY_PAGE_SIZE;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Reads `count` activities, failing if they do not arrive
+async fn next_activities(ws: &mut WsStream, count: usize) -> Vec<Value> {
+    timeout(Duration::from_secs(10), async {
+        let mut activities = Vec::new();
+        while activities.len() < count {
+            match ws.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    activities.push(serde_json::from_str(&text).unwrap());
+                }
+                Some(Ok(_)) => {}
+                _ => panic!("Socket closed after {} activities", activities.len()),
+            }
+        }
+        activities
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Fewer than {} activities arrived", count))
+}
+
+/// Test that a client resuming after a sequence number only gets what came later
+#[tokio::test]
+async fn test_resume_after_sequence() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    for i in 0..3 {
+        let response = alice.send_message_to(&app, &bob, &format!("{}", i)).await;
+        assert_success(response).await;
+    }
+
+    let mut ws = app.connect_websocket(&bob.devices[0].token).await;
+    let activities = next_activities(&mut ws, 3).await;
+    let seqs: Vec<i64> = activities
+        .iter()
+        .map(|a| {
+            a["seq"]
+                .as_i64()
+                .expect("Activity without a sequence number")
+        })
+        .collect();
+    assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+    drop(ws);
+
+    let mut ws = app.resume_websocket(&bob.devices[0].token, seqs[1]).await;
+    let resumed = next_activities(&mut ws, 1).await;
+    assert_eq!(resumed[0]["seq"], seqs[2]);
+    assert_eq!(resumed[0]["id"], activities[2]["id"]);
+
+    // Nothing else follows
+    assert!(
+        timeout(Duration::from_millis(300), ws.next())
+            .await
+            .is_err()
+    );
+}
+
+/// Test that a backlog larger than a page arrives complete and in order
+#[tokio::test]
+async fn test_large_backlog_replayed_in_pages() {
+    let app = spawn_app().await;
+
+    let alice = TestUser::create(&app, "alice").await;
+    let bob = TestUser::create(&app, "bob").await;
+
+    let count = REPLAY_PAGE_SIZE as usize * 2 + 5;
+    for i in 0..count {
+        let response = alice.send_message_to(&app, &bob, &format!("{}", i)).await;
+        assert_success(response).await;
+    }
+
+    let mut ws = app.connect_websocket(&bob.devices[0].token).await;
+    let activities = next_activities(&mut ws, count).await;
+    let seqs: Vec<i64> = activities
+        .iter()
+        .map(|a| a["seq"].as_i64().unwrap())
+        .collect();
+    assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+}